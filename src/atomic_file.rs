@@ -0,0 +1,89 @@
+//! Output files that only appear under their name once completely written.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A file written under a temporary name next to its target and renamed
+/// over it by [`Self::commit`]. Dropping it uncommitted, e.g. while a panic
+/// unwinds, removes the temporary file, so the target never holds part of
+/// the output, and anything that was there before is left alone.
+#[derive(Debug)]
+pub struct AtomicFile {
+    file: File,
+    temp: PathBuf,
+    target: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    pub fn create(target: &Path) -> io::Result<Self> {
+        let name = target.file_name().ok_or(io::ErrorKind::InvalidInput)?;
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp = target.with_file_name(temp_name);
+
+        Ok(Self {
+            file: File::create(&temp)?,
+            temp,
+            target: target.to_owned(),
+            committed: false,
+        })
+    }
+
+    /// Flushes the file and moves it to the target, replacing whatever was
+    /// there.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.flush()?;
+        std::fs::rename(&self.temp, &self.target)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.temp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::scratch_dir;
+
+    #[test]
+    fn only_a_committed_file_reaches_its_target() {
+        let dir = scratch_dir("atomic-file");
+        let target = dir.join("out.huff");
+        std::fs::write(&target, "the previous output").unwrap();
+
+        // interrupted halfway through
+        let mut file = AtomicFile::create(&target).unwrap();
+        file.write_all(b"half of the new").unwrap();
+        drop(file);
+        assert_eq!(std::fs::read(&target).unwrap(), b"the previous output");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let mut file = AtomicFile::create(&target).unwrap();
+        file.write_all(b"all of the new output").unwrap();
+        file.commit().unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"all of the new output");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
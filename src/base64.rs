@@ -0,0 +1,89 @@
+//! Standard base64 with padding (RFC 4648), for passing compressed data
+//! around as text.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (group >> (18 - 6 * i)) & 0x3f;
+                out.push(ALPHABET[index as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+/// The bytes `text` encodes, or `None` if it isn't padded base64 in the
+/// standard alphabet. Whitespace is not allowed.
+pub fn decode(text: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        ALPHABET.iter().position(|&a| a == c).map(|i| i as u32)
+    }
+
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let groups = text.len() / 4;
+    for (n, quad) in text.chunks_exact(4).enumerate() {
+        // only the last group may be padded, and only at its end
+        let padding = quad.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && n + 1 != groups) {
+            return None;
+        }
+
+        let mut group = 0;
+        for &c in &quad[..4 - padding] {
+            group = group << 6 | value(c)?;
+        }
+        group <<= 6 * padding;
+
+        let bytes = group.to_be_bytes();
+        out.extend_from_slice(&bytes[1..4 - padding]);
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc_4648_test_vectors() {
+        for (data, text) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"fooba", "Zm9vYmE="),
+            (b"foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(encode(data), text);
+            assert_eq!(decode(text).unwrap(), data);
+        }
+
+        let all: Vec<u8> = (0..=u8::MAX).collect();
+        assert_eq!(decode(&encode(&all)).unwrap(), all);
+    }
+
+    #[test]
+    fn rejects_malformed_base64() {
+        for text in ["Zg=", "Zg===", "Z===", "Zg==Zm8=", "Zm 9v", "Zm9v\n"] {
+            assert_eq!(decode(text), None, "{text}");
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! In-memory round-trip timings, for `--benchmark`.
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use huffman_encoding::format::{self, EncodeOptions};
+
+/// How many times the input is encoded and decoded.
+pub const ROUNDS: u32 = 10;
+
+/// Encodes `data` with `options` and decodes it again [`ROUNDS`] times,
+/// checking that it comes back unchanged, and describes the average speed
+/// of each. Text changes the options ask for are left out, since the
+/// decoded bytes couldn't match otherwise.
+pub fn run(data: &[u8], options: &EncodeOptions) -> Result<String, String> {
+    let options = options
+        .clone()
+        .with_normalized_newlines(false)
+        .with_stripped_bom(false);
+
+    let (mut encoding, mut decoding) = (Duration::ZERO, Duration::ZERO);
+    let mut encoded_len = 0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        let encoded = format::encode_with(data, &options).map_err(|err| err.to_string())?;
+        encoding += start.elapsed();
+
+        let start = Instant::now();
+        let decoded = format::decode(&encoded).map_err(|err| err.to_string())?;
+        decoding += start.elapsed();
+
+        if decoded != data {
+            return Err("the decoded bytes differ from the input".into());
+        }
+        encoded_len = encoded.len();
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{ROUNDS} rounds of {} bytes, compressed to {encoded_len}",
+        data.len()
+    )
+    .unwrap();
+    for (stage, time) in [("encode", encoding), ("decode", decoding)] {
+        writeln!(out, "{stage}: {:.1} MB/s", throughput(data.len(), time)).unwrap();
+    }
+
+    Ok(out)
+}
+
+/// Millions of bytes per second for [`ROUNDS`] rounds of `len` bytes.
+fn throughput(len: usize, time: Duration) -> f64 {
+    let secs = time.as_secs_f64();
+    if secs == 0.0 {
+        return 0.0;
+    }
+    f64::from(ROUNDS) * len as f64 / secs / 1e6
+}
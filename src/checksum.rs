@@ -0,0 +1,351 @@
+/// Which checksum of the original bytes a stream carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Checksum {
+    None,
+    /// CRC-32 as used by gzip and zip, stored little-endian.
+    #[default]
+    Crc32,
+    /// 64-bit xxHash with seed 0, stored little-endian. Much faster than
+    /// CRC-32 on large inputs.
+    #[cfg(feature = "xxhash")]
+    XxHash64,
+}
+
+impl Checksum {
+    /// Every checksum this build can write and check.
+    pub const ALL: &[Self] = &[
+        Self::None,
+        Self::Crc32,
+        #[cfg(feature = "xxhash")]
+        Self::XxHash64,
+    ];
+
+    /// Parses the name used on the command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "crc32" => Some(Self::Crc32),
+            #[cfg(feature = "xxhash")]
+            "xxhash" => Some(Self::XxHash64),
+            _ => None,
+        }
+    }
+
+    /// The name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Crc32 => "crc32",
+            #[cfg(feature = "xxhash")]
+            Self::XxHash64 => "xxhash",
+        }
+    }
+
+    /// The byte identifying this checksum in a stream header.
+    pub fn tag(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Crc32 => 1,
+            #[cfg(feature = "xxhash")]
+            Self::XxHash64 => 2,
+        }
+    }
+
+    /// The checksum for `tag`, or `None` if it is unknown or this build was
+    /// compiled without it.
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Self::None),
+            1 => Some(Self::Crc32),
+            #[cfg(feature = "xxhash")]
+            2 => Some(Self::XxHash64),
+            _ => None,
+        }
+    }
+
+    /// Size of the stored digest in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::Crc32 => 4,
+            #[cfg(feature = "xxhash")]
+            Self::XxHash64 => 8,
+        }
+    }
+
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        let mut digest = Vec::with_capacity(self.digest_len());
+        self.write_digest(data, &mut digest);
+        digest
+    }
+
+    /// Appends the [digest](Self::digest) of `data` to `out`.
+    pub fn write_digest(self, data: &[u8], out: &mut Vec<u8>) {
+        match self {
+            Self::None => {}
+            Self::Crc32 => out.extend(crc32(data).to_le_bytes()),
+            #[cfg(feature = "xxhash")]
+            Self::XxHash64 => out.extend(xxh64(data).to_le_bytes()),
+        }
+    }
+}
+
+/// Lookup table for the reflected CRC-32 polynomial, one entry per byte.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+/// Feeds `data` into a CRC-32 whose register holds `crc`, before the final
+/// inversion.
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// A checksum of data given a piece at a time, for input that isn't held
+/// in memory all at once. Its digest is the one [`Checksum::digest`] gives
+/// for all the pieces joined.
+#[derive(Debug, Clone)]
+pub struct Digester(DigestState);
+
+#[derive(Debug, Clone)]
+enum DigestState {
+    None,
+    /// The register before the final inversion.
+    Crc32(u32),
+    #[cfg(feature = "xxhash")]
+    XxHash64(Box<Xxh64>),
+}
+
+impl Checksum {
+    /// A [`Digester`] for this checksum, with nothing given to it yet.
+    pub fn digester(self) -> Digester {
+        Digester(match self {
+            Self::None => DigestState::None,
+            Self::Crc32 => DigestState::Crc32(!0),
+            #[cfg(feature = "xxhash")]
+            Self::XxHash64 => DigestState::XxHash64(Box::new(Xxh64::new())),
+        })
+    }
+}
+
+impl Digester {
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.0 {
+            DigestState::None => {}
+            DigestState::Crc32(crc) => *crc = crc32_update(*crc, data),
+            #[cfg(feature = "xxhash")]
+            DigestState::XxHash64(state) => state.update(data),
+        }
+    }
+
+    /// The digest of everything given to [`Self::update`], stored the way
+    /// [`Checksum::write_digest`] stores it.
+    pub fn finish(self) -> Vec<u8> {
+        match self.0 {
+            DigestState::None => Vec::new(),
+            DigestState::Crc32(crc) => (!crc).to_le_bytes().to_vec(),
+            #[cfg(feature = "xxhash")]
+            DigestState::XxHash64(state) => state.finish().to_le_bytes().to_vec(),
+        }
+    }
+}
+
+#[cfg(feature = "xxhash")]
+const P1: u64 = 0x9e37_79b1_85eb_ca87;
+#[cfg(feature = "xxhash")]
+const P2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+#[cfg(feature = "xxhash")]
+const P3: u64 = 0x1656_67b1_9e37_79f9;
+#[cfg(feature = "xxhash")]
+const P4: u64 = 0x85eb_ca77_c2b2_ae63;
+#[cfg(feature = "xxhash")]
+const P5: u64 = 0x27d4_eb2f_1656_67c5;
+
+#[cfg(feature = "xxhash")]
+pub fn xxh64(data: &[u8]) -> u64 {
+    let mut state = Xxh64::new();
+    state.update(data);
+    state.finish()
+}
+
+/// The state of [`xxh64`] between pieces of its input: the four lanes, and
+/// the bytes of a 32-byte stripe that aren't complete yet.
+#[cfg(feature = "xxhash")]
+#[derive(Debug, Clone)]
+struct Xxh64 {
+    lanes: [u64; 4],
+    stripe: [u8; 32],
+    buffered: usize,
+    len: u64,
+}
+
+#[cfg(feature = "xxhash")]
+impl Xxh64 {
+    fn new() -> Self {
+        Self {
+            lanes: [P1.wrapping_add(P2), P2, 0, P1.wrapping_neg()],
+            stripe: [0; 32],
+            buffered: 0,
+            len: 0,
+        }
+    }
+
+    fn round(acc: u64, lane: u64) -> u64 {
+        acc.wrapping_add(lane.wrapping_mul(P2))
+            .rotate_left(31)
+            .wrapping_mul(P1)
+    }
+
+    fn merge(acc: u64, lane: u64) -> u64 {
+        (acc ^ Self::round(0, lane))
+            .wrapping_mul(P1)
+            .wrapping_add(P4)
+    }
+
+    fn consume(&mut self, stripe: &[u8]) {
+        for (lane, bytes) in self.lanes.iter_mut().zip(stripe.chunks_exact(8)) {
+            *lane = Self::round(*lane, u64::from_le_bytes(bytes.try_into().unwrap()));
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        if self.buffered > 0 {
+            let taken = data.len().min(32 - self.buffered);
+            self.stripe[self.buffered..self.buffered + taken].copy_from_slice(&data[..taken]);
+            self.buffered += taken;
+            data = &data[taken..];
+            if self.buffered < 32 {
+                return;
+            }
+            let stripe = self.stripe;
+            self.consume(&stripe);
+            self.buffered = 0;
+        }
+
+        let stripes = data.chunks_exact(32);
+        let rest = stripes.remainder();
+        for stripe in stripes {
+            self.consume(stripe);
+        }
+        self.stripe[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    fn finish(&self) -> u64 {
+        let mut hash = if self.len >= 32 {
+            let [a, b, c, d] = self.lanes;
+            let hash = a
+                .rotate_left(1)
+                .wrapping_add(b.rotate_left(7))
+                .wrapping_add(c.rotate_left(12))
+                .wrapping_add(d.rotate_left(18));
+            self.lanes.into_iter().fold(hash, Self::merge)
+        } else {
+            P5
+        };
+        hash = hash.wrapping_add(self.len);
+
+        let mut rest = &self.stripe[..self.buffered];
+        while let Some((word, tail)) = rest.split_first_chunk::<8>() {
+            hash ^= Self::round(0, u64::from_le_bytes(*word));
+            hash = hash.rotate_left(27).wrapping_mul(P1).wrapping_add(P4);
+            rest = tail;
+        }
+        if let Some((word, tail)) = rest.split_first_chunk::<4>() {
+            hash ^= (u32::from_le_bytes(*word) as u64).wrapping_mul(P1);
+            hash = hash.rotate_left(23).wrapping_mul(P2).wrapping_add(P3);
+            rest = tail;
+        }
+        for &byte in rest {
+            hash ^= (byte as u64).wrapping_mul(P5);
+            hash = hash.rotate_left(11).wrapping_mul(P1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(P2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(P3);
+        hash ^ (hash >> 32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[cfg(feature = "xxhash")]
+    #[test]
+    fn xxh64_reference_values() {
+        let long: Vec<u8> = (0..100).collect();
+
+        assert_eq!(xxh64(b""), 0xef46_db37_51d8_e999);
+        assert_eq!(xxh64(b"abc"), 0x44bc_2cf5_ad77_0999);
+        assert_eq!(
+            xxh64(b"The quick brown fox jumps over the lazy dog"),
+            0x0b24_2d36_1fda_71bc
+        );
+        assert_eq!(xxh64(&long), 0x6ac1_e580_3216_6597);
+    }
+
+    #[test]
+    fn digesters_match_the_digest_of_the_whole() {
+        let data: Vec<u8> = (0..=u8::MAX).cycle().take(1000).collect();
+        for &checksum in Checksum::ALL {
+            // pieces shorter and longer than a stripe, some unaligned
+            for sizes in [&[1000][..], &[0, 1, 31, 32, 33, 100], &[7; 150]] {
+                let mut digester = checksum.digester();
+                let mut rest = &data[..];
+                for &size in sizes.iter().cycle() {
+                    if rest.is_empty() {
+                        break;
+                    }
+                    let (piece, tail) = rest.split_at(size.min(rest.len()));
+                    digester.update(piece);
+                    rest = tail;
+                }
+                assert_eq!(digester.finish(), checksum.digest(&data), "{sizes:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        for name in ["none", "crc32", "xxhash"] {
+            let Some(checksum) = Checksum::from_name(name) else {
+                continue;
+            };
+            assert_eq!(Checksum::from_tag(checksum.tag()), Some(checksum));
+            assert_eq!(checksum.name(), name);
+            assert_eq!(checksum.digest(b"abc").len(), checksum.digest_len());
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use crate::huffman::HuffmanNode;
+
+/// Codes up to this many bits are packed into a `u16`.
+const MAX_SHORT_LEN: usize = u16::BITS as usize;
+
+/// A single code, most significant bit first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code<'a> {
+    /// The low `len` bits of `bits`.
+    Short { bits: u16, len: u8 },
+    /// Codes too long to pack, which only deeply skewed trees produce.
+    Long(&'a [bool]),
+}
+
+impl Code<'_> {
+    pub fn bit_len(&self) -> usize {
+        match self {
+            Code::Short { len, .. } => *len as usize,
+            Code::Long(bits) => bits.len(),
+        }
+    }
+
+    pub fn bits(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.bit_len()).map(move |i| match self {
+            Code::Short { bits, len } => (bits >> (*len as usize - 1 - i)) & 1 == 1,
+            Code::Long(bits) => bits[i],
+        })
+    }
+}
+
+/// Canonical codes for the symbols of a tree, for encoding and decoding
+/// without walking the tree.
+///
+/// Codes are assigned by code length and then by symbol, so for trees made
+/// by [`HuffmanNode::build_tree`] they are the same codes the tree encodes.
+#[derive(Debug, Clone)]
+pub struct CodeTable {
+    short: [Option<(u16, u8)>; 256],
+    long: Vec<(u8, Vec<bool>)>,
+    /// `length_counts[len]` is how many codes are `len` bits long.
+    length_counts: Vec<usize>,
+    /// Symbols in canonical order.
+    symbols: Vec<u8>,
+}
+
+impl CodeTable {
+    pub fn new(tree: &HuffmanNode) -> Self {
+        Self::from_code_lengths(&tree.code_lengths())
+    }
+
+    /// `lengths` must come from a tree, holding 0 for absent symbols.
+    pub fn from_code_lengths(lengths: &[usize; 256]) -> Self {
+        let mut symbols: Vec<u8> = (0..=u8::MAX)
+            .filter(|&symbol| lengths[symbol as usize] > 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| (lengths[symbol as usize], symbol));
+
+        let max_len = symbols.last().map_or(0, |&symbol| lengths[symbol as usize]);
+        let mut length_counts = vec![0; max_len + 1];
+
+        let mut short = [None; 256];
+        let mut long = Vec::new();
+
+        let mut code: Vec<bool> = Vec::new();
+        for (i, &symbol) in symbols.iter().enumerate() {
+            let len = lengths[symbol as usize];
+            length_counts[len] += 1;
+
+            // the next code is one more than the previous one, shifted left
+            // to the new length
+            if i > 0 {
+                let last_zero = code
+                    .iter()
+                    .rposition(|&bit| !bit)
+                    .expect("code lengths do not come from a tree");
+                code.truncate(last_zero);
+                code.push(true);
+            }
+            code.resize(len, false);
+
+            if len <= MAX_SHORT_LEN {
+                let bits = code.iter().fold(0, |acc, &bit| (acc << 1) | bit as u16);
+                short[symbol as usize] = Some((bits, len as u8));
+            } else {
+                long.push((symbol, code.clone()));
+            }
+        }
+
+        Self {
+            short,
+            long,
+            length_counts,
+            symbols,
+        }
+    }
+
+    pub fn encode_symbol(&self, symbol: u8) -> Option<Code<'_>> {
+        if let Some((bits, len)) = self.short[symbol as usize] {
+            return Some(Code::Short { bits, len });
+        }
+
+        self.long
+            .iter()
+            .find(|(val, _)| *val == symbol)
+            .map(|(_, code)| Code::Long(code))
+    }
+
+    /// Every symbol with its code length and its code as a number, in
+    /// canonical order: by length and then by symbol. This is all a decoder
+    /// elsewhere needs, e.g. to be pasted into C or JavaScript. Returns `None`
+    /// if a code is longer than 32 bits.
+    pub fn canonical_table(&self) -> Option<Vec<(u8, u8, u32)>> {
+        self.symbols
+            .iter()
+            .map(|&symbol| {
+                let code = self.encode_symbol(symbol).expect("every symbol has a code");
+                let value = code.bits().fold(0, |acc, bit| (acc << 1) | bit as u32);
+                (code.bit_len() <= u32::BITS as usize).then_some((
+                    symbol,
+                    code.bit_len() as u8,
+                    value,
+                ))
+            })
+            .collect()
+    }
+
+    /// Reads one code from `bits`, or returns `None` if they run out first.
+    pub fn decode_symbol<I: Iterator<Item = bool>>(&self, bits: &mut I) -> Option<u8> {
+        // how far the code read so far is past the first code of its length;
+        // it stays small even when the codes themselves don't fit an integer
+        let mut offset = 0;
+        let mut index = 0;
+
+        for &count in &self.length_counts[1..] {
+            offset += bits.next()? as usize;
+            if offset < count {
+                return Some(self.symbols[index + offset]);
+            }
+
+            index += count;
+            offset = (offset - count) * 2;
+        }
+
+        None
+    }
+}
+
+/// Codes up to this many bits are decoded by a single [`PrefixTable`] lookup.
+/// Longer ones are finished by walking the tree, so a table never has more
+/// than `2^11` entries, about 6 KiB, however deep the tree is.
+pub(crate) const MAX_PREFIX_BITS: usize = 11;
+
+/// Decodes a whole code at once by indexing with the next few bits, instead
+/// of walking the tree one node per bit.
+///
+/// Unlike [`CodeTable`] it is built from the tree's own codes, so it works
+/// for trees that aren't canonical, like ones read from a crafted header.
+///
+/// It is indexed by at most [`MAX_PREFIX_BITS`] bits, which bounds its size
+/// even for codes up to 255 bits long.
+#[derive(Debug, Clone)]
+pub struct PrefixTable {
+    /// How many bits index `entries`.
+    bits: usize,
+    /// The symbol and code length for every `bits`-bit prefix, or `None` if
+    /// the prefix starts a longer code.
+    entries: Vec<Option<(u8, u8)>>,
+}
+
+impl PrefixTable {
+    pub fn new(tree: &HuffmanNode) -> Self {
+        let codes = tree.codes();
+        let max_len = codes.iter().map(|(_, code)| code.len()).max().unwrap_or(0);
+        let bits = max_len.min(MAX_PREFIX_BITS);
+
+        let mut entries = vec![None; 1 << bits];
+        for (symbol, code) in codes.iter().filter(|(_, code)| code.len() <= bits) {
+            // every index starting with the code decodes to it, whatever
+            // the remaining bits are
+            let prefix = code.iter().fold(0, |acc, &bit| (acc << 1) | bit as usize);
+            let free_bits = bits - code.len();
+            let first = prefix << free_bits;
+            entries[first..first + (1 << free_bits)].fill(Some((*symbol, code.len() as u8)));
+        }
+
+        Self { bits, entries }
+    }
+
+    /// How many upcoming bits [`Self::decode_symbol`] looks at.
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    /// Decodes the code at the start of `window`, the next [`Self::bits`]
+    /// bits with the first one highest, returning its symbol and length.
+    /// Returns `None` if the code is longer than the table covers.
+    pub fn decode_symbol(&self, window: usize) -> Option<(u8, usize)> {
+        let (symbol, len) = self.entries[window]?;
+        Some((symbol, len as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tree_from_codes;
+
+    fn code_string(code: Code) -> String {
+        code.bits().map(|bit| if bit { '1' } else { '0' }).collect()
+    }
+
+    #[test]
+    fn assigns_canonical_codes() {
+        let tree = tree_from_codes(&[(b'c', "0"), (b'b', "10"), (b'd', "110"), (b'a', "111")]);
+        let table = CodeTable::new(&tree);
+
+        let codes: Vec<_> = [b'a', b'b', b'c', b'd']
+            .map(|symbol| code_string(table.encode_symbol(symbol).unwrap()))
+            .into();
+
+        assert_eq!(codes, ["110", "10", "0", "111"]);
+        assert_eq!(table.encode_symbol(b'e'), None);
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let table = CodeTable::new(&HuffmanNode::build_tree(b"abracadabra, alakazam"));
+
+        for &symbol in b"abrcdlkzm, " {
+            let code = table.encode_symbol(symbol).unwrap();
+            assert_eq!(table.decode_symbol(&mut code.bits()), Some(symbol));
+        }
+        assert_eq!(table.decode_symbol(&mut std::iter::empty()), None);
+    }
+
+    #[test]
+    fn canonical_table_lists_prefix_free_codes_in_order() {
+        let tree = HuffmanNode::build_tree(b"abracadabra, alakazam");
+        let table = CodeTable::new(&tree).canonical_table().unwrap();
+        let codes = tree.code_map();
+
+        assert_eq!(table.len(), codes.len());
+        assert!(table.is_sorted_by_key(|&(symbol, len, _)| (len, symbol)));
+        for &(symbol, len, code) in &table {
+            assert_eq!(format!("{code:0len$b}", len = len as usize), codes[&symbol]);
+            for &(other, other_len, other_code) in &table {
+                if other != symbol && other_len >= len {
+                    assert_ne!(other_code >> (other_len - len), code);
+                }
+            }
+        }
+
+        // symbol n has the code 1^n 0, so the last few are too long
+        let codes: Vec<_> = (0..40).map(|n| "1".repeat(n) + "0").collect();
+        let mut codes: Vec<_> = codes
+            .iter()
+            .enumerate()
+            .map(|(n, code)| (n as u8, code.as_str()))
+            .collect();
+        let last = "1".repeat(40);
+        codes.push((40, &last));
+        assert_eq!(
+            CodeTable::new(&tree_from_codes(&codes)).canonical_table(),
+            None
+        );
+    }
+
+    #[test]
+    fn falls_back_to_bit_vectors_for_long_codes() {
+        // a chain where symbol n has the code 1^n 0, and the last is all 1s
+        let codes: Vec<_> = (0..24)
+            .map(|n| "1".repeat(n) + "0")
+            .chain(["1".repeat(24)])
+            .collect();
+        let codes: Vec<_> = codes
+            .iter()
+            .enumerate()
+            .map(|(n, code)| (n as u8, code.as_str()))
+            .collect();
+        let table = CodeTable::new(&tree_from_codes(&codes));
+
+        assert!(matches!(table.encode_symbol(5), Some(Code::Short { .. })));
+        for &(symbol, code) in &codes {
+            let encoded = table.encode_symbol(symbol).unwrap();
+            assert_eq!(
+                encoded.bit_len() > MAX_SHORT_LEN,
+                code.len() > MAX_SHORT_LEN
+            );
+            assert_eq!(code_string(encoded), code);
+            assert_eq!(table.decode_symbol(&mut encoded.bits()), Some(symbol));
+        }
+    }
+
+    #[test]
+    fn prefix_table_size_is_capped_for_deep_trees() {
+        let codes: Vec<_> = (0..200).map(|n| "1".repeat(n) + "0").collect();
+        let mut codes: Vec<_> = codes
+            .iter()
+            .enumerate()
+            .map(|(n, code)| (n as u8, code.as_str()))
+            .collect();
+        let last = "1".repeat(200);
+        codes.push((200, &last));
+        let table = PrefixTable::new(&tree_from_codes(&codes));
+
+        assert_eq!(table.bits(), MAX_PREFIX_BITS);
+        assert_eq!(table.entries.len(), 1 << MAX_PREFIX_BITS);
+    }
+
+    #[test]
+    fn prefix_table_decodes_short_codes_of_any_tree() {
+        // not canonical, and the deepest codes are longer than the table
+        // covers: `a` is 1, `b` 01, then symbol n is 0^n 1 and 13 is all 0s
+        let long = MAX_PREFIX_BITS + 2;
+        let codes: Vec<_> = (2..long)
+            .map(|n| (n as u8, "0".repeat(n) + "1"))
+            .chain([(long as u8, "0".repeat(long))])
+            .collect();
+        let mut codes: Vec<_> = codes.iter().map(|(n, code)| (*n, code.as_str())).collect();
+        codes.extend([(b'a', "1"), (b'b', "01")]);
+        let table = PrefixTable::new(&tree_from_codes(&codes));
+        assert_eq!(table.bits(), MAX_PREFIX_BITS);
+
+        let window = |code: &str| usize::from_str_radix(&format!("{code:0<11}"), 2).unwrap();
+        assert_eq!(table.decode_symbol(window("1")), Some((b'a', 1)));
+        assert_eq!(table.decode_symbol(window("0110")), Some((b'b', 2)));
+        assert_eq!(table.decode_symbol(window("0001")), Some((3, 4)));
+        assert_eq!(table.decode_symbol(window("")), None);
+    }
+}
@@ -0,0 +1,170 @@
+//! Order-1 coding: each byte is coded with a tree of its own for the byte
+//! before it, its context, which suits text where a letter says a lot about
+//! the next one.
+//!
+//! The stream is the symbol count as a little-endian `u64`, a 32-byte
+//! bitmap of the contexts that have a tree, least significant bit first,
+//! each of those trees as written by [`HuffmanNode::serialize_tree_bytes`]
+//! in context order, and then the codes padded to a byte. The first byte's
+//! context is [`FIRST_CONTEXT`].
+//!
+//! Codes are the canonical ones of [`CodeTable`] for each tree's code
+//! lengths, so only the lengths matter and not the shape of the trees.
+
+use crate::code_table::CodeTable;
+use crate::error::{DecodeError, EncodeError};
+use crate::huffman::HuffmanNode;
+
+/// The context of the first byte, which has no byte before it.
+pub const FIRST_CONTEXT: u8 = 0;
+
+/// The tree of each context, indexed by the context byte, or `None` for a
+/// byte that nothing follows.
+pub type Trees = Vec<Option<HuffmanNode>>;
+
+/// Builds a tree per context from `data` and writes the stream, or returns
+/// [`EncodeError::EmptyInput`] for empty `data`, which has no trees.
+pub fn serialize(data: &[u8]) -> Result<Vec<u8>, EncodeError> {
+    if data.is_empty() {
+        return Err(EncodeError::EmptyInput);
+    }
+
+    let mut counts = vec![[0_usize; 256]; 256];
+    for (context, byte) in contexts(data) {
+        counts[context as usize][byte as usize] += 1;
+    }
+    let trees: Vec<_> = counts
+        .iter()
+        .map(HuffmanNode::build_tree_from_counts)
+        .collect();
+
+    let mut out = (data.len() as u64).to_le_bytes().to_vec();
+    let mut bitmap = [0_u8; 32];
+    for (context, tree) in trees.iter().enumerate() {
+        if tree.is_some() {
+            bitmap[context / 8] |= 1 << (context % 8);
+        }
+    }
+    out.extend(bitmap);
+    for tree in trees.iter().flatten() {
+        out.extend(tree.serialize_tree_bytes());
+    }
+
+    let tables: Vec<_> = trees
+        .iter()
+        .map(|tree| tree.as_ref().map(CodeTable::new))
+        .collect();
+    let mut byte = 0_u8;
+    let mut filled = 0;
+    for (context, symbol) in contexts(data) {
+        let table = tables[context as usize]
+            .as_ref()
+            .expect("seen contexts have trees");
+        let code = table
+            .encode_symbol(symbol)
+            .ok_or(EncodeError::MissingSymbol(symbol))?;
+        for bit in code.bits() {
+            byte = byte << 1 | bit as u8;
+            filled += 1;
+            if filled == 8 {
+                out.push(byte);
+                (byte, filled) = (0, 0);
+            }
+        }
+    }
+    if filled > 0 {
+        out.push(byte << (8 - filled));
+    }
+
+    Ok(out)
+}
+
+/// Reads the symbol count and the trees at the start of a stream from
+/// [`serialize`], and returns the rest of it.
+pub fn peek_trees(input: &[u8]) -> Result<(u64, Trees, &[u8]), DecodeError> {
+    let (len, input) = input
+        .split_first_chunk::<8>()
+        .ok_or(DecodeError::TruncatedHeader)?;
+    let (bitmap, mut input) = input
+        .split_first_chunk::<32>()
+        .ok_or(DecodeError::TruncatedHeader)?;
+
+    let mut trees = Vec::with_capacity(256);
+    for context in 0..256 {
+        if bitmap[context / 8] & 1 << (context % 8) == 0 {
+            trees.push(None);
+            continue;
+        }
+        let tree = HuffmanNode::decode_tree_bytes(input)?;
+        input = &input[tree.tree_header_len()..];
+        trees.push(Some(tree));
+    }
+
+    Ok((u64::from_le_bytes(*len), trees, input))
+}
+
+/// Decodes a stream from [`serialize`] onto the end of `output`, keeping
+/// the bytes before an error, and returns what follows it.
+pub fn decode_into<'a>(input: &'a [u8], output: &mut Vec<u8>) -> Result<&'a [u8], DecodeError> {
+    let (len, trees, payload) = peek_trees(input)?;
+    // every code is at least a bit long
+    if len > payload.len() as u64 * 8 {
+        return Err(DecodeError::ImplausibleLength);
+    }
+    let tables: Vec<_> = trees
+        .iter()
+        .map(|tree| tree.as_ref().map(CodeTable::new))
+        .collect();
+
+    let mut bits = payload
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |i| byte >> i & 1 == 1));
+    let mut read = 0;
+    let mut context = FIRST_CONTEXT;
+    for _ in 0..len {
+        let table = tables[context as usize]
+            .as_ref()
+            .ok_or(DecodeError::InvalidTree)?;
+        let mut counted = bits.by_ref().inspect(|_| read += 1);
+        let symbol = table
+            .decode_symbol(&mut counted)
+            .ok_or(DecodeError::TruncatedPayload)?;
+        output.push(symbol);
+        context = symbol;
+    }
+
+    Ok(&payload[usize::div_ceil(read, 8)..])
+}
+
+/// Each byte of `data` along with its context.
+fn contexts(data: &[u8]) -> impl Iterator<Item = (u8, u8)> + '_ {
+    let previous = std::iter::once(FIRST_CONTEXT).chain(data.iter().copied());
+    previous.zip(data.iter().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_stops_at_the_end_of_the_stream() {
+        let text = include_bytes!("../nyi.txt");
+        let mut stream = serialize(text).unwrap();
+        stream.extend(b"next");
+
+        let mut output = Vec::new();
+        let rest = decode_into(&stream, &mut output).unwrap();
+        assert_eq!(output, text);
+        assert_eq!(rest, b"next");
+    }
+
+    #[test]
+    fn a_context_without_a_tree_is_an_error() {
+        let mut stream = serialize(b"abababab").unwrap();
+        // drop the tree of the first byte's context
+        stream[8] &= !1;
+
+        let mut output = Vec::new();
+        assert!(decode_into(&stream, &mut output).is_err());
+    }
+}
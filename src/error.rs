@@ -0,0 +1,224 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::format::Mode;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ended before the length prefix and tree were complete.
+    TruncatedHeader,
+    /// The input ended before the declared number of symbols was decoded.
+    TruncatedPayload,
+    /// The declared symbol count is more than the payload could hold even
+    /// at one bit per symbol.
+    ImplausibleLength,
+    /// The header's tree has no codes at all.
+    EmptyTree,
+    /// The header's code lengths do not describe a complete tree.
+    InvalidTree,
+    /// The header's tree gives this byte more than one code.
+    DuplicateSymbol(u8),
+    /// The input does not start with the expected magic bytes.
+    BadMagic,
+    /// The input was written in a format version this build can't read.
+    UnsupportedVersion {
+        found: u8,
+        supported: RangeInclusive<u8>,
+    },
+    /// The header names a checksum this build doesn't know.
+    UnsupportedChecksum(u8),
+    /// The header names an encoding mode this build doesn't know.
+    UnsupportedMode(u8),
+    /// The header sets flags this build doesn't know.
+    UnsupportedFlags(u8),
+    /// The input declares more output than the caller allowed.
+    OutputTooLarge,
+    /// There are bytes after the end of the stream.
+    TrailingData,
+    /// Text meant to hold base64 doesn't.
+    InvalidBase64,
+    /// The decoded bytes were expected to be UTF-8 text but aren't.
+    InvalidUtf8,
+    /// The header's comment or file name is not valid UTF-8.
+    InvalidComment,
+    /// The header's file name could lead outside the directory it is
+    /// restored in, like `../name` does.
+    UnsafeName,
+    /// The decoded bytes don't match the checksum in the header.
+    ChecksumMismatch,
+    /// Files that [`crate::format::concat_same_tree`] was given don't share
+    /// a tree, or differ in mode, checksum or flags.
+    DifferentTrees,
+    /// [`crate::format::concat_same_tree`] was given a file in a mode other
+    /// than [`Mode::Huffman`], which has no codes to copy.
+    UnjoinableMode(Mode),
+    /// The caller asked for decoding to stop, through
+    /// [`crate::stream::Observer::cancelled`].
+    Cancelled,
+    /// Reading the input or writing the output failed, e.g. in
+    /// [`crate::huffman::read_tree`].
+    Io(std::io::ErrorKind),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TruncatedHeader => write!(f, "input ends in the middle of the header"),
+            Self::TruncatedPayload => write!(f, "input ends before all symbols were decoded"),
+            Self::ImplausibleLength => {
+                write!(f, "input declares more symbols than its payload can hold")
+            }
+            Self::EmptyTree => write!(f, "the header's tree has no codes"),
+            Self::InvalidTree => write!(f, "the header does not describe a valid tree"),
+            Self::DuplicateSymbol(symbol) => {
+                write!(
+                    f,
+                    "the header's tree has more than one code for byte 0x{symbol:02x}"
+                )
+            }
+            Self::BadMagic => write!(f, "input is not in a format this tool wrote"),
+            Self::UnsupportedVersion { found, supported } => write!(
+                f,
+                "input uses format version {found}, but only versions {} to {} are supported",
+                supported.start(),
+                supported.end()
+            ),
+            Self::UnsupportedChecksum(tag) => write!(f, "input uses unknown checksum {tag}"),
+            Self::UnsupportedMode(mode) => write!(f, "input uses unknown mode {mode}"),
+            Self::UnsupportedFlags(flags) => write!(f, "input sets unknown flags 0x{flags:02x}"),
+            Self::OutputTooLarge => write!(f, "input would decode to more than the allowed size"),
+            Self::TrailingData => write!(f, "input has data after the end of the stream"),
+            Self::InvalidBase64 => write!(f, "input is not valid base64"),
+            Self::InvalidUtf8 => write!(f, "decoded data is not valid UTF-8"),
+            Self::InvalidComment => {
+                write!(f, "the header's comment or file name is not valid UTF-8")
+            }
+            Self::UnsafeName => write!(f, "the header's file name is not a plain file name"),
+            Self::ChecksumMismatch => write!(f, "checksum mismatch, the input is corrupt"),
+            Self::DifferentTrees => {
+                write!(
+                    f,
+                    "the inputs were not coded with the same tree and options"
+                )
+            }
+            Self::UnjoinableMode(mode) => {
+                write!(
+                    f,
+                    "{} files can't be joined without coding them again",
+                    mode.name()
+                )
+            }
+            Self::Cancelled => write!(f, "decoding was cancelled"),
+            Self::Io(kind) => write!(f, "I/O error: {kind}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<std::io::Error> for DecodeError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err.kind())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The input contains a byte that is not a leaf of the tree.
+    MissingSymbol(u8),
+    /// The input contains these bytes, in ascending order, which the model
+    /// the tree is built from gives no weight.
+    NotInModel(Vec<u8>),
+    /// There is nothing to build a tree from.
+    EmptyInput,
+    /// A code is too long for a [`TreeLayout::LengthTable`] to store.
+    ///
+    /// [`TreeLayout::LengthTable`]: crate::huffman::TreeLayout::LengthTable
+    CodeTooLong,
+    /// The comment is longer than [`MAX_COMMENT_LEN`] bytes.
+    ///
+    /// [`MAX_COMMENT_LEN`]: crate::format::MAX_COMMENT_LEN
+    CommentTooLong,
+    /// The file name isn't a [safe name](crate::format::is_safe_name).
+    UnsafeName,
+    /// The caller asked for encoding to stop, through
+    /// [`crate::stream::Observer::cancelled`].
+    Cancelled,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingSymbol(byte) => write!(f, "byte 0x{byte:02x} has no code in the tree"),
+            Self::NotInModel(bytes) => {
+                let bytes: Vec<_> = bytes.iter().map(|byte| format!("0x{byte:02x}")).collect();
+                write!(f, "the model has no weight for bytes {}", bytes.join(", "))
+            }
+            Self::EmptyInput => write!(f, "cannot build a tree over empty input"),
+            Self::CodeTooLong => write!(f, "a code is too long for a length table"),
+            Self::CommentTooLong => write!(f, "the comment is longer than 255 bytes"),
+            Self::UnsafeName => write!(f, "the file name is not a plain file name"),
+            Self::Cancelled => write!(f, "encoding was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/// An error from [`crate::files`], which names the file that couldn't be
+/// read or written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressError {
+    Read {
+        path: PathBuf,
+        kind: std::io::ErrorKind,
+    },
+    Write {
+        path: PathBuf,
+        kind: std::io::ErrorKind,
+    },
+    Encode(EncodeError),
+    Decode(DecodeError),
+}
+
+impl CompressError {
+    pub fn read(path: &Path, err: &std::io::Error) -> Self {
+        Self::Read {
+            path: path.to_owned(),
+            kind: err.kind(),
+        }
+    }
+
+    pub fn write(path: &Path, err: &std::io::Error) -> Self {
+        Self::Write {
+            path: path.to_owned(),
+            kind: err.kind(),
+        }
+    }
+}
+
+impl fmt::Display for CompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read { path, kind } => write!(f, "failed to read {}: {kind}", path.display()),
+            Self::Write { path, kind } => write!(f, "failed to write {}: {kind}", path.display()),
+            Self::Encode(err) => err.fmt(f),
+            Self::Decode(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CompressError {}
+
+impl From<EncodeError> for CompressError {
+    fn from(err: EncodeError) -> Self {
+        Self::Encode(err)
+    }
+}
+
+impl From<DecodeError> for CompressError {
+    fn from(err: DecodeError) -> Self {
+        Self::Decode(err)
+    }
+}
@@ -0,0 +1,197 @@
+//! The byte layout of a compressed file's header, for `--explain`.
+
+use std::fmt::Write;
+
+use huffman_encoding::error::DecodeError;
+use huffman_encoding::format::{self, Mode};
+use huffman_encoding::huffman::{HuffmanNode, LENGTH_TABLE_BYTES, TreeLayout};
+
+use crate::inspect::shown;
+
+/// Annotates each byte of the header of `input`, and of the stream up to
+/// the end of its tree, with what it means, a line per field. Unlike
+/// [`crate::inspect::inspect`] it says where things are rather than what
+/// the codes are.
+pub fn explain_header(input: &[u8]) -> Result<String, DecodeError> {
+    let (header, stream) = format::peek_header(input)?;
+    let stream_start = input.len() - stream.len();
+    let mut out = Layout {
+        input,
+        at: 0,
+        text: String::new(),
+    };
+
+    out.field(4, "magic HUFF");
+    out.field(1, &format!("version {}", header.version));
+    out.field(1, &format!("checksum {}", header.checksum.name()));
+    if header.version >= 2 {
+        out.field(1, &format!("mode {}", header.mode.name()));
+    }
+    if header.version >= 3 {
+        let byte = input[out.at];
+        let mut set = Vec::new();
+        if header.flags.normalized_newlines {
+            set.push("normalized newlines");
+        }
+        if header.flags.stripped_bom {
+            set.push("stripped byte order mark");
+        }
+        for (field, name) in [
+            (header.comment.is_some(), "comment"),
+            (header.name.is_some(), "name"),
+            (header.mtime.is_some(), "modification time"),
+        ] {
+            if field {
+                set.push(name);
+            }
+        }
+        let set = if set.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", set.join(", "))
+        };
+        out.field(1, &format!("flags 0x{byte:02x}{set}"));
+    }
+    if header.version >= 4 {
+        let len = u16::from_le_bytes([input[out.at], input[out.at + 1]]);
+        out.field(2, &format!("optional fields length {len}"));
+    }
+    for (text, what) in [(header.comment, "comment"), (header.name, "name")] {
+        if let Some(text) = text {
+            out.field(1, &format!("{what} length {}", text.len()));
+            out.field(text.len(), &format!("{what} {text:?}"));
+        }
+    }
+    if let Some(mtime) = header.mtime {
+        out.field(8, &format!("modification time {mtime}"));
+    }
+    let digest_start = stream_start - header.digest.len();
+    if out.at < digest_start {
+        out.field(
+            digest_start - out.at,
+            "fields from a later version, skipped",
+        );
+    }
+    let digest: String = header.digest.iter().map(|b| format!("{b:02x}")).collect();
+    out.field(header.digest.len(), &format!("digest {digest}"));
+
+    let layout = match header.mode {
+        Mode::Huffman | Mode::Nibble | Mode::Lines => TreeLayout::Pairs,
+        Mode::LengthTable => TreeLayout::LengthTable,
+        Mode::Escaped => {
+            let escape = out.next_byte()?;
+            out.field(1, &format!("escape 0x{escape:02x}"));
+            TreeLayout::Pairs
+        }
+        Mode::Stored | Mode::Order1 => {
+            out.length()?;
+            out.rest(match header.mode {
+                Mode::Stored => "the data as it is",
+                _ => "the context trees and codes",
+            });
+            return Ok(out.text);
+        }
+    };
+
+    let (_, tree) = HuffmanNode::peek_tree_with_layout(&input[out.at..], layout)?;
+    out.length()?;
+    match layout {
+        TreeLayout::Pairs => {
+            for _ in 0..tree.leaf_count() {
+                let [len, symbol] = [input[out.at], input[out.at + 1]];
+                out.field(
+                    2,
+                    &format!("symbol 0x{symbol:02x} {}, code length {len}", shown(symbol)),
+                );
+            }
+            out.field(1, "end of tree");
+        }
+        TreeLayout::LengthTable => {
+            out.field(LENGTH_TABLE_BYTES, "code lengths of all 256 byte values");
+        }
+    }
+    out.rest(match header.mode {
+        Mode::Lines => "the line index and codes",
+        _ => "the codes",
+    });
+
+    Ok(out.text)
+}
+
+/// The lines written so far, and where the next field starts.
+struct Layout<'a> {
+    input: &'a [u8],
+    at: usize,
+    text: String,
+}
+
+impl Layout<'_> {
+    /// Describes the next `len` bytes, unless there are none.
+    fn field(&mut self, len: usize, what: &str) {
+        let start = self.at;
+        self.at += len;
+        match len {
+            0 => {}
+            1 => writeln!(self.text, "byte {start}: {what}").unwrap(),
+            _ => writeln!(self.text, "bytes {start}-{}: {what}", self.at - 1).unwrap(),
+        }
+    }
+
+    /// The next byte of the stream, which needn't be there.
+    fn next_byte(&self) -> Result<u8, DecodeError> {
+        self.input
+            .get(self.at)
+            .copied()
+            .ok_or(DecodeError::TruncatedHeader)
+    }
+
+    /// Describes the symbol count that starts every stream.
+    fn length(&mut self) -> Result<(), DecodeError> {
+        let len = self
+            .input
+            .get(self.at..)
+            .and_then(|rest| rest.first_chunk::<8>())
+            .ok_or(DecodeError::TruncatedHeader)?;
+        self.field(8, &format!("length {}", u64::from_le_bytes(*len)));
+        Ok(())
+    }
+
+    /// Describes whatever is left.
+    fn rest(&mut self, what: &str) {
+        if self.at < self.input.len() {
+            writeln!(self.text, "bytes {} onwards: {what}", self.at).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use huffman_encoding::checksum::Checksum;
+
+    #[test]
+    fn annotates_each_header_byte() {
+        let encoded = format::encode(b"aaaaaaaabbbbcc\n", Checksum::Crc32).unwrap();
+        let encoded = format::with_comment(encoded, "hi").unwrap();
+
+        assert_eq!(
+            explain_header(&encoded).unwrap(),
+            "bytes 0-3: magic HUFF\n\
+             byte 4: version 4\n\
+             byte 5: checksum crc32\n\
+             byte 6: mode huffman\n\
+             byte 7: flags 0x04 (comment)\n\
+             bytes 8-9: optional fields length 3\n\
+             byte 10: comment length 2\n\
+             bytes 11-12: comment \"hi\"\n\
+             bytes 13-16: digest 408f2c6a\n\
+             bytes 17-24: length 15\n\
+             bytes 25-26: symbol 0x61 'a', code length 1\n\
+             bytes 27-28: symbol 0x62 'b', code length 2\n\
+             bytes 29-30: symbol 0x0a '\\n', code length 3\n\
+             bytes 31-32: symbol 0x63 'c', code length 3\n\
+             byte 33: end of tree\n\
+             bytes 34 onwards: the codes\n"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Compressing and decompressing files in one call, with errors that say
+//! which file failed.
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::atomic_file::AtomicFile;
+use crate::error::CompressError;
+use crate::format::{self, DecodeOptions, EncodeOptions};
+
+/// The sizes of what [`compress_file`] or [`decompress_file`] read and
+/// wrote, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressStats {
+    pub read: usize,
+    pub written: usize,
+}
+
+/// Compresses the file at `input` with `options` into `output`, which is
+/// only replaced once all of it is written; see [`AtomicFile`].
+pub fn compress_file(
+    input: &Path,
+    output: &Path,
+    options: &EncodeOptions,
+) -> Result<CompressStats, CompressError> {
+    let data = std::fs::read(input).map_err(|err| CompressError::read(input, &err))?;
+    let encoded = format::encode_with(&data, options)?;
+    write_file(output, &encoded)?;
+
+    Ok(CompressStats {
+        read: data.len(),
+        written: encoded.len(),
+    })
+}
+
+/// Decodes every member of the compressed file at `input` with the checks
+/// in `options` into `output`, like [`compress_file`] writes it. Any
+/// [`Flags`](format::Flags) are left as they are.
+pub fn decompress_file(
+    input: &Path,
+    output: &Path,
+    options: &DecodeOptions,
+) -> Result<CompressStats, CompressError> {
+    let data = std::fs::read(input).map_err(|err| CompressError::read(input, &err))?;
+    let mut rest = &data[..];
+    let mut decoded = Vec::new();
+    loop {
+        let (member, after) = format::decode_first(rest, options)?;
+        decoded.extend(member);
+        rest = after;
+        if rest.is_empty() {
+            break;
+        }
+    }
+    write_file(output, &decoded)?;
+
+    Ok(CompressStats {
+        read: data.len(),
+        written: decoded.len(),
+    })
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<(), CompressError> {
+    let write = || {
+        let mut file = AtomicFile::create(path)?;
+        file.write_all(data)?;
+        file.commit()
+    };
+    write().map_err(|err| CompressError::write(path, &err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DecodeError;
+    use crate::test_util::scratch_dir;
+
+    #[test]
+    fn round_trips_through_files() {
+        let dir = scratch_dir("files");
+        let text = include_bytes!("../nyi.txt");
+        std::fs::write(dir.join("in.txt"), text).unwrap();
+
+        let stats = compress_file(
+            &dir.join("in.txt"),
+            &dir.join("in.huff"),
+            &EncodeOptions::new(),
+        )
+        .unwrap();
+        assert_eq!(stats.read, text.len());
+        assert!(stats.written < text.len());
+
+        let stats = decompress_file(
+            &dir.join("in.huff"),
+            &dir.join("out.txt"),
+            &DecodeOptions::new(),
+        )
+        .unwrap();
+        assert_eq!(stats.written, text.len());
+        assert_eq!(std::fs::read(dir.join("out.txt")).unwrap(), text);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn errors_name_the_file() {
+        let dir = scratch_dir("files-errors");
+        let input = dir.join("in.txt");
+        std::fs::write(&input, "some text").unwrap();
+
+        // a regular file can't have anything inside it
+        let output = input.join("out.huff");
+        let err = compress_file(&input, &output, &EncodeOptions::new()).unwrap_err();
+        assert!(matches!(&err, CompressError::Write { path, .. } if *path == output));
+        assert!(
+            err.to_string()
+                .starts_with(&format!("failed to write {}: ", output.display()))
+        );
+
+        let missing = dir.join("missing.huff");
+        let err = decompress_file(&missing, &output, &DecodeOptions::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("failed to read {}: entity not found", missing.display())
+        );
+
+        let err = decompress_file(&input, &dir.join("out.txt"), &DecodeOptions::new());
+        assert_eq!(err, Err(CompressError::Decode(DecodeError::BadMagic)));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
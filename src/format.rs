@@ -0,0 +1,2007 @@
+//! The framed file format written by the command line tool.
+//!
+//! Every file starts with a header:
+//!
+//! - the magic bytes [`MAGIC`]
+//! - a format version byte, [`FORMAT_VERSION`]
+//! - a [`Checksum`] tag byte
+//! - a [`Mode`] byte, missing in version 1 files, which are all Huffman coded
+//! - a [`Flags`] byte, missing before version 3
+//! - the length in bytes of the optional fields as a little-endian `u16`,
+//!   missing before version 4, so that fields added later can be skipped
+//! - if the flags byte says so, a comment and then a file name, each as its
+//!   length in one byte followed by that many bytes of UTF-8; see
+//!   [`with_comment`] and [`with_name`]
+//! - if the flags byte says so, a modification time as a little-endian
+//!   `u64`; see [`with_mtime`]
+//! - any fields a later version added, which are skipped
+//! - the checksum of the encoded bytes, whose length depends on the tag
+//!
+//! followed by the stream for the mode: for [`Mode::Huffman`] the one
+//! written by [`HuffmanNode::serialize`], and for [`Mode::Stored`] the
+//! length as a little-endian `u64` followed by the bytes themselves. For
+//! [`Mode::Escaped`] it is the escape byte followed by the stream written by
+//! [`HuffmanNode::serialize_escaped`], and for [`Mode::Nibble`] the stream
+//! written by `serialize` for the nibbles of the data as symbols 0 to 15,
+//! the high nibble of each byte first. [`Mode::LengthTable`] has the stream
+//! written by [`HuffmanNode::serialize_with_layout`] for
+//! [`TreeLayout::LengthTable`]. [`Mode::Lines`] has the length and tree as
+//! in `serialize`, the number of lines as a little-endian `u64`, the length
+//! in bits of each line's codes as another, and then each line's codes
+//! padded to a byte; see [`encode_lines`]. [`Mode::Order1`] has the stream
+//! described in [`context_model`].
+
+use std::borrow::Cow;
+use std::io::{self, Read};
+use std::ops::RangeInclusive;
+
+use crate::base64;
+use crate::checksum::Checksum;
+use crate::context_model;
+use crate::error::{DecodeError, EncodeError};
+use crate::huffman::{self, HuffmanNode, TreeLayout};
+use crate::models;
+use crate::text;
+
+/// Magic bytes that start every compressed file.
+pub const MAGIC: &[u8; 4] = b"HUFF";
+
+/// The format version this build writes.
+pub const FORMAT_VERSION: u8 = 4;
+
+/// The format versions this build can read.
+pub const SUPPORTED_VERSIONS: RangeInclusive<u8> = 1..=FORMAT_VERSION;
+
+/// How the bytes after the header encode the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Huffman = 0,
+    /// The bytes as they are, for data that Huffman coding would only grow.
+    Stored = 1,
+    /// Huffman coding where rare bytes share one escape code and follow it
+    /// literally.
+    Escaped = 2,
+    /// Huffman coding of the two 4-bit halves of every byte, for data that
+    /// is skewed within bytes but spread over many byte values.
+    Nibble = 3,
+    /// Huffman coding with the tree stored as a table of all 256 code
+    /// lengths, which is smaller than a list of leaves for large alphabets.
+    LengthTable = 4,
+    /// Huffman coding of each line on its own with one tree for them all,
+    /// so that any line can be decoded without the ones before it.
+    Lines = 5,
+    /// Huffman coding with a tree for each value of the byte before, which
+    /// suits text.
+    Order1 = 6,
+}
+
+impl Mode {
+    /// Every mode this build can read, in tag order.
+    pub const ALL: [Self; 7] = [
+        Self::Huffman,
+        Self::Stored,
+        Self::Escaped,
+        Self::Nibble,
+        Self::LengthTable,
+        Self::Lines,
+        Self::Order1,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Huffman => "huffman",
+            Self::Stored => "stored",
+            Self::Escaped => "escaped",
+            Self::Nibble => "nibble",
+            Self::LengthTable => "length-table",
+            Self::Lines => "lines",
+            Self::Order1 => "order-1",
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Self::Huffman),
+            1 => Some(Self::Stored),
+            2 => Some(Self::Escaped),
+            3 => Some(Self::Nibble),
+            4 => Some(Self::LengthTable),
+            5 => Some(Self::Lines),
+            6 => Some(Self::Order1),
+            _ => None,
+        }
+    }
+}
+
+/// Changes made to the input before it was encoded, which a decoder may
+/// want to undo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags {
+    /// Every CRLF was turned into LF, with [`text::normalize_newlines`].
+    pub normalized_newlines: bool,
+    /// A leading [`text::BOM`] was removed, with [`text::strip_bom`].
+    pub stripped_bom: bool,
+}
+
+impl Flags {
+    const NORMALIZED_NEWLINES: u8 = 1;
+    const STRIPPED_BOM: u8 = 2;
+
+    fn to_byte(self) -> u8 {
+        let mut byte = 0;
+        if self.normalized_newlines {
+            byte |= Self::NORMALIZED_NEWLINES;
+        }
+        if self.stripped_bom {
+            byte |= Self::STRIPPED_BOM;
+        }
+        byte
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        if byte & !(Self::NORMALIZED_NEWLINES | Self::STRIPPED_BOM) != 0 {
+            return None;
+        }
+
+        Some(Self {
+            normalized_newlines: byte & Self::NORMALIZED_NEWLINES != 0,
+            stripped_bom: byte & Self::STRIPPED_BOM != 0,
+        })
+    }
+}
+
+/// Where the [`Flags`] byte sits in a header this build writes.
+const FLAGS_OFFSET: usize = 7;
+
+/// Where the optional fields start in a header this build writes, after
+/// the flags byte and their length.
+const FIELDS_OFFSET: usize = FLAGS_OFFSET + 3;
+
+/// Set in the flags byte when a comment follows it. It isn't one of the
+/// [`Flags`], which are about the data.
+const HAS_COMMENT: u8 = 4;
+
+/// Set in the flags byte when a file name follows it and the comment.
+const HAS_NAME: u8 = 8;
+
+/// Set in the flags byte when a modification time follows the text fields.
+const HAS_MTIME: u8 = 16;
+
+/// The optional text fields, in the order they're stored.
+const FIELDS: [u8; 2] = [HAS_COMMENT, HAS_NAME];
+
+/// Every bit of the flags byte that marks an optional field.
+const OPTIONAL_FIELDS: u8 = HAS_COMMENT | HAS_NAME | HAS_MTIME;
+
+/// The longest comment a header can hold, in bytes.
+pub const MAX_COMMENT_LEN: usize = u8::MAX as usize;
+
+/// The header for `data`, to be followed by its stream for `mode`. It has
+/// no flags set; see [`with_flags`].
+pub fn header(data: &[u8], checksum: Checksum, mode: Mode) -> Vec<u8> {
+    let mut header = Vec::new();
+    write_header(data, checksum, mode, &mut header);
+    header
+}
+
+/// Appends the [header](header) for `data` to `out`.
+pub(crate) fn write_header(data: &[u8], checksum: Checksum, mode: Mode, out: &mut Vec<u8>) {
+    write_header_start(checksum, mode, out);
+    checksum.write_digest(data, out);
+}
+
+/// The [`header`] for data whose digest was worked out already, as
+/// [`Digester::finish`](crate::checksum::Digester::finish) returns it.
+pub fn header_with_digest(digest: &[u8], checksum: Checksum, mode: Mode) -> Vec<u8> {
+    let mut header = Vec::new();
+    write_header_start(checksum, mode, &mut header);
+    header.extend(digest);
+    header
+}
+
+/// Everything in a header before the digest.
+fn write_header_start(checksum: Checksum, mode: Mode, out: &mut Vec<u8>) {
+    out.extend(MAGIC);
+    out.extend([FORMAT_VERSION, checksum.tag(), mode as u8, 0, 0, 0]);
+}
+
+/// Sets the flags of a file written by this build, for input that was
+/// changed before it was passed to one of the `encode` functions.
+pub fn with_flags(mut encoded: Vec<u8>, flags: Flags) -> Vec<u8> {
+    encoded[FLAGS_OFFSET] = flags.to_byte() | encoded[FLAGS_OFFSET] & OPTIONAL_FIELDS;
+    encoded
+}
+
+/// Adds `comment` to the header of a file written by this build, such as
+/// the original file name or a note. Decoding ignores it, but
+/// [`peek_header`] returns it. Fails if it is longer than
+/// [`MAX_COMMENT_LEN`] bytes.
+///
+/// # Panics
+///
+/// If the file already has a comment.
+pub fn with_comment(encoded: Vec<u8>, comment: &str) -> Result<Vec<u8>, EncodeError> {
+    with_field(encoded, HAS_COMMENT, comment).ok_or(EncodeError::CommentTooLong)
+}
+
+/// Adds the name of the original file to the header of a file written by
+/// this build, for restoring it under that name. It must be a plain
+/// [safe name](is_safe_name) of at most 255 bytes.
+///
+/// # Panics
+///
+/// If the file already has a name.
+pub fn with_name(encoded: Vec<u8>, name: &str) -> Result<Vec<u8>, EncodeError> {
+    if !is_safe_name(name) {
+        return Err(EncodeError::UnsafeName);
+    }
+    with_field(encoded, HAS_NAME, name).ok_or(EncodeError::UnsafeName)
+}
+
+/// Whether `name` can be written to without leaving the directory it is
+/// restored in: a single path component that isn't empty, `.` or `..`,
+/// with no separators or NUL bytes, and at most 255 bytes long.
+pub fn is_safe_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..")
+        && name.len() <= u8::MAX as usize
+        && !name.contains(['/', '\\', '\0'])
+        // `C:name` is relative to a drive's working directory on Windows
+        && !name.contains(':')
+}
+
+/// Adds the modification time of the original file, in seconds since the
+/// Unix epoch, to the header of a file written by this build, for
+/// restoring it along with the data.
+///
+/// # Panics
+///
+/// If the file already has a modification time.
+pub fn with_mtime(encoded: Vec<u8>, mtime: u64) -> Vec<u8> {
+    insert_field(encoded, HAS_MTIME, &mtime.to_le_bytes())
+}
+
+/// Inserts `text` as the optional text field `bit`. Returns `None` if it
+/// is too long.
+fn with_field(encoded: Vec<u8>, bit: u8, text: &str) -> Option<Vec<u8>> {
+    let len = u8::try_from(text.len()).ok()?;
+    let field: Vec<u8> = [len].into_iter().chain(text.bytes()).collect();
+
+    Some(insert_field(encoded, bit, &field))
+}
+
+/// Inserts the optional field `bit`, after the text fields before it in
+/// [`FIELDS`], or after all of them if it isn't one, and adds it to the
+/// length of the fields.
+fn insert_field(mut encoded: Vec<u8>, bit: u8, field: &[u8]) -> Vec<u8> {
+    assert!(
+        encoded[FLAGS_OFFSET] & bit == 0,
+        "the file already has this header field"
+    );
+
+    let mut at = FIELDS_OFFSET;
+    for earlier in FIELDS.into_iter().take_while(|&field| field != bit) {
+        if encoded[FLAGS_OFFSET] & earlier != 0 {
+            at += 1 + encoded[at] as usize;
+        }
+    }
+    encoded[FLAGS_OFFSET] |= bit;
+    let len_bytes = FLAGS_OFFSET + 1..FIELDS_OFFSET;
+    let len = u16::from_le_bytes(encoded[len_bytes.clone()].try_into().unwrap());
+    // three fields of at most 264 bytes each fit easily
+    let len = len + field.len() as u16;
+    encoded[len_bytes].copy_from_slice(&len.to_le_bytes());
+    encoded.splice(at..at, field.iter().copied());
+    encoded
+}
+
+/// Whether `data` starts with a header this build can read, i.e. is most
+/// likely already compressed.
+pub fn is_compressed(data: &[u8]) -> bool {
+    peek_header(data).is_ok()
+}
+
+/// Whether Huffman coding can't make data with these byte frequencies any
+/// smaller than storing it. Checked up front from the entropy, which bounds
+/// the payload size from below, plus the size of the tree.
+pub fn is_incompressible(counts: &[usize; 256]) -> bool {
+    let len: usize = counts.iter().sum();
+    let symbols = counts.iter().filter(|&&count| count > 0).count();
+
+    let payload_bits = huffman::entropy(counts) * len as f64;
+    let tree_bytes = 2 * symbols.max(2) + 1;
+
+    (payload_bits / 8.0).ceil() as usize + tree_bytes >= len
+}
+
+/// Compresses `data` with a tree built from its own frequencies, or stores
+/// it if it [is incompressible](is_incompressible).
+pub fn encode(data: &[u8], checksum: Checksum) -> Result<Vec<u8>, EncodeError> {
+    let counts = huffman::count_frequencies(data.iter().copied());
+    if is_incompressible(&counts) {
+        return Ok(encode_stored(data, checksum));
+    }
+
+    let tree = HuffmanNode::build_tree_from_counts(&counts).ok_or(EncodeError::EmptyInput)?;
+
+    Ok([header(data, checksum, Mode::Huffman), tree.serialize(data)?].concat())
+}
+
+/// Stores `data` as it is, framed like any other file.
+pub fn encode_stored(data: &[u8], checksum: Checksum) -> Vec<u8> {
+    [
+        &header(data, checksum, Mode::Stored)[..],
+        &(data.len() as u64).to_le_bytes(),
+        data,
+    ]
+    .concat()
+}
+
+/// Compresses `data` with a tree built from a frequency `model`, like one
+/// from [`crate::models`], instead of from `data` itself. The tree is still
+/// stored, so [`decode`] needs nothing extra.
+///
+/// Fails with [`EncodeError::NotInModel`], listing them, if `data`
+/// contains bytes whose count in `model` is zero.
+pub fn encode_with_model(
+    data: &[u8],
+    model: &[usize; 256],
+    checksum: Checksum,
+) -> Result<Vec<u8>, EncodeError> {
+    let missing = models::missing_symbols(data, model);
+    if !missing.is_empty() {
+        return Err(EncodeError::NotInModel(missing));
+    }
+    let tree = HuffmanNode::build_tree_from_counts(model).ok_or(EncodeError::EmptyInput)?;
+
+    Ok([header(data, checksum, Mode::Huffman), tree.serialize(data)?].concat())
+}
+
+/// Compresses `data` with the bytes that occur fewer than `threshold` times
+/// left out of the tree. They share a single escape leaf instead and are
+/// written literally after its code, which keeps the tree small for data
+/// with a long tail of rare bytes.
+///
+/// Falls back to [`encode`] if no byte is that rare.
+pub fn encode_escaped(
+    data: &[u8],
+    threshold: usize,
+    checksum: Checksum,
+) -> Result<Vec<u8>, EncodeError> {
+    let counts = huffman::count_frequencies(data.iter().copied());
+
+    let mut model = [0; 256];
+    let mut rare = 0;
+    for (byte, &count) in counts.iter().enumerate() {
+        if count >= threshold {
+            model[byte] = count;
+        } else {
+            rare += count;
+        }
+    }
+    if rare == 0 {
+        return encode(data, checksum);
+    }
+
+    // any byte that isn't in the tree can stand for the escape, because
+    // escaping it just writes it literally
+    let escape = (0..=u8::MAX)
+        .find(|&byte| model[byte as usize] == 0)
+        .expect("some byte is rare");
+    model[escape as usize] = rare;
+    let tree = HuffmanNode::build_tree_from_counts(&model).ok_or(EncodeError::EmptyInput)?;
+
+    Ok([
+        header(data, checksum, Mode::Escaped),
+        vec![escape],
+        tree.serialize_escaped(data, escape)?,
+    ]
+    .concat())
+}
+
+/// Compresses the nibbles of `data` rather than its bytes. The tree has at
+/// most 16 leaves, which can beat [`encode`] on small inputs that use many
+/// byte values but few nibble values often. Empty `data` falls back to
+/// [`Mode::Stored`], as it has no tree.
+pub fn encode_nibbles(data: &[u8], checksum: Checksum) -> Result<Vec<u8>, EncodeError> {
+    let nibbles: Vec<u8> = data
+        .iter()
+        .flat_map(|&byte| [byte >> 4, byte & 0xf])
+        .collect();
+    let counts = huffman::count_frequencies(nibbles.iter().copied());
+    let Some(tree) = HuffmanNode::build_tree_from_counts(&counts) else {
+        return Ok(encode_stored(data, checksum));
+    };
+
+    Ok([
+        header(data, checksum, Mode::Nibble),
+        tree.serialize(&nibbles)?,
+    ]
+    .concat())
+}
+
+/// Compresses `data` like [`encode`], but stores the tree as a
+/// [`TreeLayout::LengthTable`] of [`huffman::LENGTH_TABLE_BYTES`] bytes
+/// instead of two bytes per symbol, which is smaller once there are 80 or
+/// more distinct bytes.
+///
+/// Falls back to [`Mode::Huffman`] if a code is too long for the table,
+/// which takes counts as skewed as the Fibonacci numbers, and to
+/// [`Mode::Stored`] for empty `data`.
+pub fn encode_length_table(data: &[u8], checksum: Checksum) -> Result<Vec<u8>, EncodeError> {
+    let counts = huffman::count_frequencies(data.iter().copied());
+    let Some(tree) = HuffmanNode::build_tree_from_counts(&counts) else {
+        return Ok(encode_stored(data, checksum));
+    };
+
+    match tree.serialize_with_layout(data, TreeLayout::LengthTable) {
+        Ok(stream) => Ok([header(data, checksum, Mode::LengthTable), stream].concat()),
+        Err(EncodeError::CodeTooLong) => {
+            Ok([header(data, checksum, Mode::Huffman), tree.serialize(data)?].concat())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Compresses `data` with a tree for each byte value, used for the bytes
+/// that follow it, as described in [`context_model`]. On text that beats
+/// [`encode`] once there is enough of it to pay for the extra trees. Empty
+/// `data` falls back to [`Mode::Stored`].
+pub fn encode_order1(data: &[u8], checksum: Checksum) -> Result<Vec<u8>, EncodeError> {
+    match context_model::serialize(data) {
+        Ok(stream) => Ok([header(data, checksum, Mode::Order1), stream].concat()),
+        Err(EncodeError::EmptyInput) => Ok(encode_stored(data, checksum)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Compresses `data` a line at a time with one tree built over all of it,
+/// so that [`decode_line`] can pick out a line without decoding the others.
+/// A line is everything up to and including a `\n`, or whatever follows
+/// the last one.
+///
+/// Each line's codes start on a byte boundary and its bit length is stored
+/// in an index, so the file is a little larger than [`encode`] makes it.
+/// Empty `data` falls back to [`Mode::Stored`], as it has no tree.
+pub fn encode_lines(data: &[u8], checksum: Checksum) -> Result<Vec<u8>, EncodeError> {
+    let Some(tree) = HuffmanNode::from_byte_iter(data.iter().copied()) else {
+        return Ok(encode_stored(data, checksum));
+    };
+    let lengths = tree.code_lengths();
+    let lines: Vec<_> = data.split_inclusive(|&byte| byte == b'\n').collect();
+
+    let mut out = header(data, checksum, Mode::Lines);
+    out.extend((data.len() as u64).to_le_bytes());
+    out.extend(tree.serialize_tree_bytes());
+    out.extend((lines.len() as u64).to_le_bytes());
+    for line in &lines {
+        let bits: usize = line.iter().map(|&byte| lengths[byte as usize]).sum();
+        out.extend((bits as u64).to_le_bytes());
+    }
+    for line in &lines {
+        let payload = tree.serialize_payload(line)?;
+        out.extend(&payload[size_of::<u64>()..]);
+    }
+
+    Ok(out)
+}
+
+/// Joins decoded nibbles back into bytes on the end of `output`, up to the
+/// first one that isn't a nibble or lacks its other half.
+fn join_nibbles(nibbles: &[u8], output: &mut Vec<u8>) -> Result<(), DecodeError> {
+    let pairs = nibbles.chunks_exact(2);
+    let odd = !pairs.remainder().is_empty();
+    for pair in pairs {
+        let &[high @ 0..16, low @ 0..16] = pair else {
+            return Err(DecodeError::InvalidTree);
+        };
+        output.push(high << 4 | low);
+    }
+
+    if odd {
+        return Err(DecodeError::TruncatedPayload);
+    }
+    Ok(())
+}
+
+/// Everything that can be chosen about how [`encode_with`] writes a file.
+/// The defaults match [`encode`] with the default checksum.
+///
+/// ```
+/// use huffman_encoding::checksum::Checksum;
+/// use huffman_encoding::format::{self, EncodeOptions};
+///
+/// let options = EncodeOptions::new()
+///     .with_checksum(Checksum::None)
+///     .with_normalized_newlines(true);
+/// let encoded = format::encode_with(b"one\r\ntwo\r\n", &options).unwrap();
+///
+/// assert_eq!(format::decode(&encoded).unwrap(), b"one\ntwo\n");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EncodeOptions {
+    checksum: Checksum,
+    model: Option<[usize; 256]>,
+    escape_below: Option<usize>,
+    normalize_newlines: bool,
+    strip_bom: bool,
+    stored: bool,
+    nibbles: bool,
+    length_table: bool,
+    lines: bool,
+    order1: bool,
+    comment: Option<String>,
+    name: Option<String>,
+}
+
+impl EncodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Builds the tree from `model`, as [`encode_with_model`] does. Takes
+    /// precedence over [`Self::with_escape_below`].
+    pub fn with_model(mut self, model: [usize; 256]) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Escapes bytes seen fewer than `threshold` times, as
+    /// [`encode_escaped`] does.
+    pub fn with_escape_below(mut self, threshold: usize) -> Self {
+        self.escape_below = Some(threshold);
+        self
+    }
+
+    /// Writes [`Mode::Stored`] without trying to compress, which is the
+    /// fastest and never grows the data by more than the header. Takes
+    /// precedence over the options that pick a tree.
+    pub fn with_stored(mut self, stored: bool) -> Self {
+        self.stored = stored;
+        self
+    }
+
+    /// Codes nibbles instead of bytes, as [`encode_nibbles`] does. Takes
+    /// precedence over the options that pick a tree.
+    pub fn with_nibbles(mut self, nibbles: bool) -> Self {
+        self.nibbles = nibbles;
+        self
+    }
+
+    /// Stores the tree as a table of code lengths, as
+    /// [`encode_length_table`] does. Takes precedence over the options that
+    /// pick a tree, but not over [`Self::with_nibbles`].
+    pub fn with_length_table(mut self, length_table: bool) -> Self {
+        self.length_table = length_table;
+        self
+    }
+
+    /// Codes each line on its own, as [`encode_lines`] does. Takes
+    /// precedence over the options that pick a tree.
+    pub fn with_lines(mut self, lines: bool) -> Self {
+        self.lines = lines;
+        self
+    }
+
+    /// Codes each byte with a tree for the byte before it, as
+    /// [`encode_order1`] does. Takes precedence over the options that pick
+    /// a tree.
+    pub fn with_order1(mut self, order1: bool) -> Self {
+        self.order1 = order1;
+        self
+    }
+
+    /// Stores `comment` in the header, as [`with_comment`] does.
+    pub fn with_comment(mut self, comment: Option<&str>) -> Self {
+        self.comment = comment.map(String::from);
+        self
+    }
+
+    /// Stores the original file's `name` in the header, as [`with_name`]
+    /// does.
+    pub fn with_name(mut self, name: Option<&str>) -> Self {
+        self.name = name.map(String::from);
+        self
+    }
+
+    /// Turns CRLF into LF before encoding and sets
+    /// [`Flags::normalized_newlines`].
+    pub fn with_normalized_newlines(mut self, normalize: bool) -> Self {
+        self.normalize_newlines = normalize;
+        self
+    }
+
+    /// Removes a leading [`text::BOM`] before encoding and, if there was
+    /// one, sets [`Flags::stripped_bom`].
+    pub fn with_stripped_bom(mut self, strip: bool) -> Self {
+        self.strip_bom = strip;
+        self
+    }
+
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    pub fn model(&self) -> Option<&[usize; 256]> {
+        self.model.as_ref()
+    }
+
+    pub fn escape_below(&self) -> Option<usize> {
+        self.escape_below
+    }
+
+    pub fn stored(&self) -> bool {
+        self.stored
+    }
+
+    pub fn nibbles(&self) -> bool {
+        self.nibbles
+    }
+
+    pub fn length_table(&self) -> bool {
+        self.length_table
+    }
+
+    pub fn lines(&self) -> bool {
+        self.lines
+    }
+
+    pub fn order1(&self) -> bool {
+        self.order1
+    }
+
+    /// See [`with_comment`].
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// See [`with_name`].
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Whether nothing but the checksum is set, so that [`encode_with`]
+    /// writes what [`encode`] does.
+    pub fn is_plain(&self) -> bool {
+        self.model.is_none()
+            && self.escape_below.is_none()
+            && !(self.normalize_newlines || self.strip_bom)
+            && !(self.stored || self.nibbles || self.length_table || self.lines || self.order1)
+            && self.comment.is_none()
+            && self.name.is_none()
+    }
+
+    /// The flags a file written from `data` with these options has in its
+    /// header.
+    pub fn flags(&self, data: &[u8]) -> Flags {
+        Flags {
+            normalized_newlines: self.normalize_newlines,
+            stripped_bom: self.strip_bom && text::strip_bom(data).is_some(),
+        }
+    }
+
+    /// `data` as it is encoded, after any changes the options ask for.
+    pub fn prepare<'a>(&self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        let data = match text::strip_bom(data) {
+            Some(rest) if self.strip_bom => rest,
+            _ => data,
+        };
+        if self.normalize_newlines {
+            Cow::Owned(text::normalize_newlines(data))
+        } else {
+            Cow::Borrowed(data)
+        }
+    }
+}
+
+/// Compresses `data` as `options` say.
+pub fn encode_with(data: &[u8], options: &EncodeOptions) -> Result<Vec<u8>, EncodeError> {
+    let flags = options.flags(data);
+    let data = options.prepare(data);
+
+    let encoded = match (options.model(), options.escape_below()) {
+        _ if options.stored() => encode_stored(&data, options.checksum()),
+        _ if options.nibbles() => encode_nibbles(&data, options.checksum())?,
+        _ if options.length_table() => encode_length_table(&data, options.checksum())?,
+        _ if options.lines() => encode_lines(&data, options.checksum())?,
+        _ if options.order1() => encode_order1(&data, options.checksum())?,
+        (Some(model), _) => encode_with_model(&data, model, options.checksum())?,
+        (None, Some(threshold)) => encode_escaped(&data, threshold, options.checksum())?,
+        (None, None) => encode(&data, options.checksum())?,
+    };
+
+    let mut encoded = with_flags(encoded, flags);
+    if let Some(comment) = options.comment() {
+        encoded = with_comment(encoded, comment)?;
+    }
+    if let Some(name) = options.name() {
+        encoded = with_name(encoded, name)?;
+    }
+    Ok(encoded)
+}
+
+/// The fields of a parsed header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header<'a> {
+    pub version: u8,
+    pub checksum: Checksum,
+    pub mode: Mode,
+    pub flags: Flags,
+    /// See [`with_comment`].
+    pub comment: Option<&'a str>,
+    /// See [`with_name`]. It is always a [safe name](is_safe_name).
+    pub name: Option<&'a str>,
+    /// See [`with_mtime`].
+    pub mtime: Option<u64>,
+    /// The checksum of the encoded bytes, before any [`Flags`] are undone.
+    pub digest: &'a [u8],
+}
+
+/// Parses the header without decoding anything, returning it and the stream
+/// after it.
+pub fn peek_header(input: &[u8]) -> Result<(Header<'_>, &[u8]), DecodeError> {
+    let (magic, input) = input
+        .split_first_chunk::<4>()
+        .ok_or(DecodeError::TruncatedHeader)?;
+    if magic != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+
+    let (&[version, tag], input) = input
+        .split_first_chunk::<2>()
+        .ok_or(DecodeError::TruncatedHeader)?;
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(DecodeError::UnsupportedVersion {
+            found: version,
+            supported: SUPPORTED_VERSIONS,
+        });
+    }
+    let checksum = Checksum::from_tag(tag).ok_or(DecodeError::UnsupportedChecksum(tag))?;
+
+    let (mode, input) = if version == 1 {
+        (Mode::Huffman, input)
+    } else {
+        let (&mode, input) = input.split_first().ok_or(DecodeError::TruncatedHeader)?;
+        let mode = Mode::from_tag(mode).ok_or(DecodeError::UnsupportedMode(mode))?;
+        (mode, input)
+    };
+
+    let (flags, [comment, name], mtime, input) = if version < 3 {
+        (Flags::default(), [None, None], None, input)
+    } else {
+        let (&byte, input) = input.split_first().ok_or(DecodeError::TruncatedHeader)?;
+        let flags =
+            Flags::from_byte(byte & !OPTIONAL_FIELDS).ok_or(DecodeError::UnsupportedFlags(byte))?;
+        // the fields, and from version 4 on what follows all of them
+        let (mut input, after_fields) = if version < 4 {
+            (input, None)
+        } else {
+            let (len, input) = input
+                .split_first_chunk::<2>()
+                .ok_or(DecodeError::TruncatedHeader)?;
+            let (fields, rest) = input
+                .split_at_checked(u16::from_le_bytes(*len) as usize)
+                .ok_or(DecodeError::TruncatedHeader)?;
+            (fields, Some(rest))
+        };
+
+        let mut fields = [None; FIELDS.len()];
+        for (field, bit) in fields.iter_mut().zip(FIELDS) {
+            if byte & bit == 0 {
+                continue;
+            }
+            let (&len, rest) = input.split_first().ok_or(DecodeError::TruncatedHeader)?;
+            let (text, rest) = rest
+                .split_at_checked(len as usize)
+                .ok_or(DecodeError::TruncatedHeader)?;
+            *field = Some(std::str::from_utf8(text).map_err(|_| DecodeError::InvalidComment)?);
+            input = rest;
+        }
+
+        let mut mtime = None;
+        if byte & HAS_MTIME != 0 {
+            let (secs, rest) = input
+                .split_first_chunk::<8>()
+                .ok_or(DecodeError::TruncatedHeader)?;
+            mtime = Some(u64::from_le_bytes(*secs));
+            input = rest;
+        }
+        (flags, fields, mtime, after_fields.unwrap_or(input))
+    };
+    // a name from a crafted file must not lead anywhere else
+    if name.is_some_and(|name| !is_safe_name(name)) {
+        return Err(DecodeError::UnsafeName);
+    }
+
+    let (digest, input) = input
+        .split_at_checked(checksum.digest_len())
+        .ok_or(DecodeError::TruncatedHeader)?;
+
+    let header = Header {
+        version,
+        checksum,
+        mode,
+        flags,
+        comment,
+        name,
+        mtime,
+        digest,
+    };
+
+    Ok((header, input))
+}
+
+/// Reads the header at the start of `input` and nothing after it,
+/// returning its bytes for [`peek_header`]. It fails as `peek_header` does
+/// on a header that isn't complete or valid, where `input` ends or where
+/// the header goes wrong.
+pub fn read_header<R: Read>(input: &mut R) -> Result<Vec<u8>, DecodeError> {
+    let mut header = Vec::new();
+    loop {
+        match peek_header(&header) {
+            Ok(_) => return Ok(header),
+            Err(DecodeError::TruncatedHeader) => {}
+            Err(err) => return Err(err),
+        }
+        // from version 4 on the first bytes give the length of the rest,
+        // which saves parsing the header again after every byte
+        let len = match header[..] {
+            [_, _, _, _, version, tag, _, _, low, high, ..] if version >= 4 => {
+                let digest_len = Checksum::from_tag(tag).map_or(0, Checksum::digest_len);
+                FIELDS_OFFSET + u16::from_le_bytes([low, high]) as usize + digest_len
+            }
+            _ => header.len() + 1,
+        };
+        let start = header.len();
+        header.resize(len.max(start + 1), 0);
+        input
+            .read_exact(&mut header[start..])
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => DecodeError::TruncatedHeader,
+                kind => DecodeError::Io(kind),
+            })?;
+    }
+}
+
+/// Decompresses a file written by [`encode`], verifying its checksum. Any
+/// [`Flags`] are left for the caller to act on.
+pub fn decode(input: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    decode_with(input, &DecodeOptions::new())
+}
+
+/// Like [`decode`], but fails with [`DecodeError::OutputTooLarge`] instead
+/// of decoding more than `max_output` bytes, so a tiny crafted file can't
+/// exhaust memory. Every stream declares its length up front, and decoding
+/// never produces more than that, so the check happens before any work.
+pub fn decode_limited(input: &[u8], max_output: usize) -> Result<Vec<u8>, DecodeError> {
+    decode_with(input, &DecodeOptions::new().with_max_output(max_output))
+}
+
+/// The checks [`decode_with`] makes. The defaults verify the checksum and
+/// accept anything else, like [`decode`].
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    verify_checksum: bool,
+    max_output: usize,
+    reject_trailing: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            verify_checksum: true,
+            max_output: usize::MAX,
+            reject_trailing: false,
+        }
+    }
+}
+
+impl DecodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to fail with [`DecodeError::ChecksumMismatch`] when the
+    /// output doesn't match the stored digest.
+    pub fn with_verify_checksum(mut self, verify: bool) -> Self {
+        self.verify_checksum = verify;
+        self
+    }
+
+    /// See [`decode_limited`].
+    pub fn with_max_output(mut self, max_output: usize) -> Self {
+        self.max_output = max_output;
+        self
+    }
+
+    /// Whether to fail with [`DecodeError::TrailingData`] when there are
+    /// bytes after the end of the stream.
+    pub fn with_reject_trailing(mut self, reject: bool) -> Self {
+        self.reject_trailing = reject;
+        self
+    }
+
+    pub fn verify_checksum(&self) -> bool {
+        self.verify_checksum
+    }
+
+    pub fn max_output(&self) -> usize {
+        self.max_output
+    }
+}
+
+/// Decompresses a file written by [`encode`] with the checks in `options`.
+pub fn decode_with(input: &[u8], options: &DecodeOptions) -> Result<Vec<u8>, DecodeError> {
+    let (output, rest) = decode_first(input, options)?;
+    if options.reject_trailing && !rest.is_empty() {
+        return Err(DecodeError::TrailingData);
+    }
+
+    Ok(output)
+}
+
+/// Decodes every member of a file made by appending encoded files to each
+/// other, in order. Unlike [`decode`], anything after the last member is an
+/// error.
+pub fn decode_members(input: &[u8]) -> Result<Vec<Vec<u8>>, DecodeError> {
+    let mut input = input;
+    let mut members = Vec::new();
+    while !input.is_empty() {
+        let (member, rest) = decode_first(input, &DecodeOptions::new())?;
+        members.push(member);
+        input = rest;
+    }
+
+    Ok(members)
+}
+
+/// Like [`decode_first`], but returns how many bytes of `input` the member
+/// took, header, payload and padding included, instead of the rest. The
+/// next member, if any, starts at that offset.
+pub fn decode_member(
+    input: &[u8],
+    options: &DecodeOptions,
+) -> Result<(Vec<u8>, usize), DecodeError> {
+    let (output, rest) = decode_first(input, options)?;
+    Ok((output, input.len() - rest.len()))
+}
+
+/// Decodes a stream that starts `offset` bytes into `input`, such as one
+/// stored inside a file of some other format, and returns it along with
+/// how many bytes from `offset` on it took. Whatever follows the stream is
+/// left alone. Fails with [`DecodeError::TruncatedHeader`] if `offset` is
+/// past the end of `input`.
+pub fn decode_at(input: &[u8], offset: usize) -> Result<(Vec<u8>, usize), DecodeError> {
+    let stream = input.get(offset..).ok_or(DecodeError::TruncatedHeader)?;
+    decode_member(stream, &DecodeOptions::new())
+}
+
+/// Joins two files whose data was Huffman coded with the same tree, such
+/// as files encoded with [`encode_with_model`] from one model, into a file
+/// that decodes to `a`'s data followed by `b`'s. Their codes are copied,
+/// shifted to follow each other bit by bit, rather than coded again.
+///
+/// This doesn't save decoding: both files are decoded in full, to check
+/// them, to work out the new checksum and to count the bits of their codes.
+/// What it saves is building a tree and encoding. The result keeps `a`'s
+/// comment, name and modification time, and none of `b`'s.
+///
+/// An empty file, which is always stored, adds nothing, so the other file
+/// is returned as it is. Otherwise this fails with
+/// [`DecodeError::UnjoinableMode`] unless both are [`Mode::Huffman`], and
+/// with [`DecodeError::DifferentTrees`] unless they are single members with
+/// the same tree, checksum and flags.
+pub fn concat_same_tree(a: &[u8], b: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let options = DecodeOptions::new().with_reject_trailing(true);
+    let (data_a, data_b) = (decode_with(a, &options)?, decode_with(b, &options)?);
+    if data_a.is_empty() {
+        return Ok(b.to_vec());
+    }
+    if data_b.is_empty() {
+        return Ok(a.to_vec());
+    }
+
+    let (a, b) = (CodedMember::new(a, data_a)?, CodedMember::new(b, data_b)?);
+    if a.header.checksum != b.header.checksum
+        || a.header.flags != b.header.flags
+        || a.tree.serialize_tree_bytes() != b.tree.serialize_tree_bytes()
+    {
+        return Err(DecodeError::DifferentTrees);
+    }
+
+    let lengths = a.tree.code_lengths();
+    let bits = |data: &[u8]| {
+        data.iter()
+            .map(|&byte| lengths[byte as usize])
+            .sum::<usize>()
+    };
+    let (bits_a, bits_b) = (bits(&a.data), bits(&b.data));
+
+    let data = [&a.data[..], &b.data].concat();
+    let mut out = header(&data, a.header.checksum, Mode::Huffman);
+    out.extend((data.len() as u64).to_le_bytes());
+    out.extend(a.tree.serialize_tree_bytes());
+    let start = out.len();
+    out.extend(&a.payload[..bits_a.div_ceil(8)]);
+
+    let payload_b = &b.payload[..bits_b.div_ceil(8)];
+    match bits_a % 8 {
+        0 => out.extend(payload_b),
+        shift => {
+            // a's last byte has room for the first bits of each byte of b
+            for &byte in payload_b {
+                *out.last_mut().unwrap() |= byte >> shift;
+                out.push(byte << (8 - shift));
+            }
+        }
+    }
+    out.truncate(start + (bits_a + bits_b).div_ceil(8));
+
+    let mut out = with_flags(out, a.header.flags);
+    // the fields are already known to fit, as they came from a header
+    for (bit, text) in [(HAS_COMMENT, a.header.comment), (HAS_NAME, a.header.name)] {
+        if let Some(text) = text {
+            out = with_field(out, bit, text).expect("a header field fits a header");
+        }
+    }
+    if let Some(mtime) = a.header.mtime {
+        out = with_mtime(out, mtime);
+    }
+
+    Ok(out)
+}
+
+/// A [`Mode::Huffman`] file, its decoded `data` and the parts
+/// [`concat_same_tree`] takes from it.
+struct CodedMember<'a> {
+    data: Vec<u8>,
+    header: Header<'a>,
+    tree: HuffmanNode,
+    /// The packed codes.
+    payload: &'a [u8],
+}
+
+impl<'a> CodedMember<'a> {
+    /// Splits up the one member `input` holds, which decoded to `data`.
+    fn new(input: &'a [u8], data: Vec<u8>) -> Result<Self, DecodeError> {
+        let (header, stream) = peek_header(input)?;
+        if header.mode != Mode::Huffman {
+            return Err(DecodeError::UnjoinableMode(header.mode));
+        }
+        let (_, tree) = HuffmanNode::peek_tree(stream)?;
+        let payload = &stream[size_of::<u64>() + tree.tree_header_len()..];
+
+        Ok(Self {
+            data,
+            header,
+            tree,
+            payload,
+        })
+    }
+}
+
+/// Decodes line `n` of the first member of `input`, counting from 0 and
+/// keeping its `\n`, or returns `None` if there are not that many lines.
+///
+/// For a file from [`encode_lines`] only that line is decoded, so its
+/// checksum, which covers the whole file, can't be checked. Files in other
+/// modes are decoded and checked in full first.
+pub fn decode_line(input: &[u8], n: usize) -> Result<Option<Vec<u8>>, DecodeError> {
+    let (header, stream) = peek_header(input)?;
+    if header.mode != Mode::Lines {
+        let (output, _) = decode_first(input, &DecodeOptions::new())?;
+        return Ok(output
+            .split_inclusive(|&byte| byte == b'\n')
+            .nth(n)
+            .map(<[u8]>::to_vec));
+    }
+
+    let index = LineIndex::new(stream)?;
+    let mut offset = 0;
+    for (i, bits) in index.bit_lens().enumerate() {
+        let bits = bits?;
+        if i == n {
+            return index.decode(offset, bits).map(Some);
+        }
+        offset = offset.saturating_add(bits.div_ceil(8));
+    }
+
+    Ok(None)
+}
+
+/// Decodes the member at the start of `input` with the checks in `options`
+/// except for trailing data, and returns it along with everything after it.
+pub fn decode_first<'a>(
+    input: &'a [u8],
+    options: &DecodeOptions,
+) -> Result<(Vec<u8>, &'a [u8]), DecodeError> {
+    let (header, input) = peek_header(input)?;
+    if declared_len(header.mode, input)? > options.max_output as u64 {
+        return Err(DecodeError::OutputTooLarge);
+    }
+
+    let (output, rest) = match header.mode {
+        Mode::Huffman => {
+            let (_, output, rest) = HuffmanNode::decode_with_rest(input, TreeLayout::Pairs)?;
+            (output, rest)
+        }
+        Mode::LengthTable => {
+            let (_, output, rest) = HuffmanNode::decode_with_rest(input, TreeLayout::LengthTable)?;
+            (output, rest)
+        }
+        Mode::Stored => {
+            let (output, rest) = decode_stored(input)?;
+            (output.to_vec(), rest)
+        }
+        Mode::Escaped => {
+            let (&escape, input) = input.split_first().ok_or(DecodeError::TruncatedHeader)?;
+            HuffmanNode::decode_escaped_with_rest(input, escape)?
+        }
+        Mode::Nibble => {
+            let (_, nibbles, rest) = HuffmanNode::decode_with_rest(input, TreeLayout::Pairs)?;
+            let mut output = Vec::with_capacity(nibbles.len() / 2);
+            join_nibbles(&nibbles, &mut output)?;
+            (output, rest)
+        }
+        Mode::Lines => {
+            let mut output = Vec::new();
+            let rest = LineIndex::new(input)?.decode_all(&mut output)?;
+            (output, rest)
+        }
+        Mode::Order1 => {
+            let mut output = Vec::new();
+            let rest = context_model::decode_into(input, &mut output)?;
+            (output, rest)
+        }
+    };
+
+    if options.verify_checksum && header.checksum.digest(&output) != header.digest {
+        return Err(DecodeError::ChecksumMismatch);
+    }
+
+    Ok((output, rest))
+}
+
+/// Decodes what it can of a damaged file, for recovering data after a
+/// failed [`decode`]. Returns the bytes decoded before the first problem
+/// along with that problem, or `None` if there was none. A bad checksum
+/// doesn't stop decoding, so then everything is returned, but some of it
+/// is wrong.
+pub fn decode_best_effort(input: &[u8]) -> (Vec<u8>, Option<DecodeError>) {
+    let mut output = Vec::new();
+    let result = decode_into_best_effort(input, &mut output);
+
+    (output, result.err())
+}
+
+fn decode_into_best_effort(input: &[u8], output: &mut Vec<u8>) -> Result<(), DecodeError> {
+    let (header, input) = peek_header(input)?;
+    match header.mode {
+        Mode::Huffman => HuffmanNode::decode_partial(input, TreeLayout::Pairs, output)?,
+        Mode::LengthTable => {
+            HuffmanNode::decode_partial(input, TreeLayout::LengthTable, output)?;
+        }
+        Mode::Stored => {
+            let (len, data) = input
+                .split_first_chunk::<8>()
+                .ok_or(DecodeError::TruncatedHeader)?;
+            let len = usize::try_from(u64::from_le_bytes(*len)).unwrap_or(usize::MAX);
+            output.extend_from_slice(&data[..len.min(data.len())]);
+            if data.len() < len {
+                return Err(DecodeError::TruncatedPayload);
+            }
+        }
+        Mode::Escaped => {
+            let (&escape, input) = input.split_first().ok_or(DecodeError::TruncatedHeader)?;
+            HuffmanNode::decode_escaped_partial(input, escape, output)?;
+        }
+        Mode::Nibble => {
+            let mut nibbles = Vec::new();
+            let result = HuffmanNode::decode_partial(input, TreeLayout::Pairs, &mut nibbles);
+            join_nibbles(&nibbles, output)?;
+            result?;
+        }
+        Mode::Lines => {
+            LineIndex::new(input)?.decode_all(output)?;
+        }
+        Mode::Order1 => {
+            context_model::decode_into(input, output)?;
+        }
+    }
+
+    if header.checksum.digest(output) != header.digest {
+        return Err(DecodeError::ChecksumMismatch);
+    }
+    Ok(())
+}
+
+/// Compresses `text` and encodes the result as [base64](crate::base64),
+/// for places that only take text.
+///
+/// ```
+/// use huffman_encoding::format;
+///
+/// let sentence = "the quick brown fox jumps over the lazy dog, then naps";
+/// let encoded = format::compress_str_to_base64(sentence);
+///
+/// assert_eq!(format::decompress_base64_to_string(&encoded).unwrap(), sentence);
+/// ```
+pub fn compress_str_to_base64(text: &str) -> String {
+    let encoded = encode(text.as_bytes(), Checksum::default())
+        .expect("the input's own tree has a code for each of its bytes");
+
+    base64::encode(&encoded)
+}
+
+/// Reverses [`compress_str_to_base64`].
+pub fn decompress_base64_to_string(text: &str) -> Result<String, DecodeError> {
+    let encoded = base64::decode(text).ok_or(DecodeError::InvalidBase64)?;
+
+    String::from_utf8(decode(&encoded)?).map_err(|_| DecodeError::InvalidUtf8)
+}
+
+/// How many bytes the stream for `mode` says it decodes to.
+fn declared_len(mode: Mode, stream: &[u8]) -> Result<u64, DecodeError> {
+    let stream = match mode {
+        Mode::Huffman
+        | Mode::Stored
+        | Mode::Nibble
+        | Mode::LengthTable
+        | Mode::Lines
+        | Mode::Order1 => stream,
+        Mode::Escaped => stream.get(1..).ok_or(DecodeError::TruncatedHeader)?,
+    };
+    let (len, _) = stream
+        .split_first_chunk::<8>()
+        .ok_or(DecodeError::TruncatedHeader)?;
+    let len = u64::from_le_bytes(*len);
+
+    Ok(if mode == Mode::Nibble { len / 2 } else { len })
+}
+
+/// The stored bytes and whatever follows them.
+fn decode_stored(input: &[u8]) -> Result<(&[u8], &[u8]), DecodeError> {
+    let (len, input) = input
+        .split_first_chunk::<8>()
+        .ok_or(DecodeError::TruncatedHeader)?;
+
+    usize::try_from(u64::from_le_bytes(*len))
+        .ok()
+        .and_then(|len| input.split_at_checked(len))
+        .ok_or(DecodeError::TruncatedPayload)
+}
+
+/// The parts of a [`Mode::Lines`] stream.
+struct LineIndex<'a> {
+    len: u64,
+    tree: HuffmanNode,
+    /// A little-endian `u64` bit length for each line.
+    bit_lens: &'a [u8],
+    payloads: &'a [u8],
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(stream: &'a [u8]) -> Result<Self, DecodeError> {
+        let (len, tree) = HuffmanNode::peek_tree(stream)?;
+        let stream = &stream[size_of::<u64>() + tree.tree_header_len()..];
+        let (count, stream) = stream
+            .split_first_chunk::<8>()
+            .ok_or(DecodeError::TruncatedHeader)?;
+        let (bit_lens, payloads) = usize::try_from(u64::from_le_bytes(*count))
+            .ok()
+            .and_then(|count| count.checked_mul(size_of::<u64>()))
+            .and_then(|index_len| stream.split_at_checked(index_len))
+            .ok_or(DecodeError::TruncatedHeader)?;
+
+        Ok(Self {
+            len,
+            tree,
+            bit_lens,
+            payloads,
+        })
+    }
+
+    fn bit_lens(&self) -> impl Iterator<Item = Result<usize, DecodeError>> + '_ {
+        self.bit_lens.chunks_exact(size_of::<u64>()).map(|bits| {
+            let bits = u64::from_le_bytes(bits.try_into().unwrap());
+            usize::try_from(bits).map_err(|_| DecodeError::TruncatedPayload)
+        })
+    }
+
+    /// Decodes the line whose `bits` bits of codes start `offset` bytes into
+    /// the payloads.
+    fn decode(&self, offset: usize, bits: usize) -> Result<Vec<u8>, DecodeError> {
+        let payload = offset
+            .checked_add(bits.div_ceil(8))
+            .and_then(|end| self.payloads.get(offset..end))
+            .ok_or(DecodeError::TruncatedPayload)?;
+
+        huffman::decode_with_bit_len(&self.tree, payload, bits)
+    }
+
+    /// Decodes every line onto the end of `output`, keeping the ones before
+    /// an error, and returns what follows the last.
+    fn decode_all(&self, output: &mut Vec<u8>) -> Result<&'a [u8], DecodeError> {
+        let start = output.len();
+        let mut offset = 0;
+        for bits in self.bit_lens() {
+            let bits = bits?;
+            output.extend(self.decode(offset, bits)?);
+            offset += bits.div_ceil(8);
+            if (output.len() - start) as u64 > self.len {
+                return Err(DecodeError::ImplausibleLength);
+            }
+        }
+
+        if ((output.len() - start) as u64) < self.len {
+            return Err(DecodeError::TruncatedPayload);
+        }
+        Ok(&self.payloads[offset..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{FLIP_SEED, flip_random_bits};
+
+    const INPUT: &[u8] = b"abracadabra abracadabra abracadabra abracadabra abracadabra";
+
+    fn round_trips_and_detects_bit_flips(checksum: Checksum) {
+        let encoded = encode(INPUT, checksum).unwrap();
+        assert_eq!(decode(&encoded).unwrap(), INPUT);
+
+        // flip the top bit of the first symbol in the tree, which turns it
+        // into a byte the input doesn't contain but still decodes
+        let first_symbol = FIELDS_OFFSET + checksum.digest_len() + 8 + 1;
+        let mut corrupt = encoded.clone();
+        corrupt[first_symbol] ^= 0b1000_0000;
+        assert_eq!(decode(&corrupt), Err(DecodeError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn crc32_detects_corruption() {
+        round_trips_and_detects_bit_flips(Checksum::Crc32);
+    }
+
+    #[cfg(feature = "xxhash")]
+    #[test]
+    fn xxhash_detects_corruption() {
+        round_trips_and_detects_bit_flips(Checksum::XxHash64);
+    }
+
+    #[test]
+    fn no_checksum_stores_no_digest() {
+        let encoded = encode(INPUT, Checksum::None).unwrap();
+        let crc_encoded = encode(INPUT, Checksum::Crc32).unwrap();
+
+        assert_eq!(encoded.len() + 4, crc_encoded.len());
+        assert_eq!(&encoded[..FIELDS_OFFSET], b"HUFF\x04\x00\x00\x00\x00\x00");
+        assert_eq!(decode(&encoded).unwrap(), INPUT);
+    }
+
+    #[test]
+    fn rejects_unknown_headers() {
+        let encoded = encode(INPUT, Checksum::Crc32).unwrap();
+
+        assert_eq!(decode(&encoded[6..]), Err(DecodeError::BadMagic));
+        assert_eq!(decode(&encoded[..7]), Err(DecodeError::TruncatedHeader));
+
+        let mut future_version = encoded.clone();
+        future_version[4] = FORMAT_VERSION + 1;
+        assert_eq!(
+            decode(&future_version),
+            Err(DecodeError::UnsupportedVersion {
+                found: FORMAT_VERSION + 1,
+                supported: 1..=FORMAT_VERSION,
+            })
+        );
+
+        let mut unknown_tag = encoded.clone();
+        unknown_tag[5] = 0xff;
+        assert_eq!(
+            decode(&unknown_tag),
+            Err(DecodeError::UnsupportedChecksum(0xff))
+        );
+    }
+
+    #[test]
+    fn models_must_weigh_every_input_byte() {
+        let model = crate::models::ascii_lowercase_model();
+        let mut lacking = model;
+        lacking[b'q' as usize] = 0;
+
+        assert!(encode_with_model(b"quick", &model, Checksum::Crc32).is_ok());
+        assert_eq!(
+            encode_with_model(b"quick", &lacking, Checksum::Crc32),
+            Err(EncodeError::NotInModel(vec![b'q']))
+        );
+    }
+
+    #[test]
+    fn english_model_beats_a_flat_one_on_english() {
+        use crate::models::{english_text_model, flat_model};
+
+        let text = include_bytes!("../nyi.txt");
+        let english = encode_with_model(text, &english_text_model(), Checksum::None).unwrap();
+        let flat = encode_with_model(text, &flat_model(), Checksum::None).unwrap();
+
+        assert!(english.len() < flat.len());
+        assert_eq!(decode(&english).unwrap(), text);
+    }
+
+    #[test]
+    fn stores_incompressible_input() {
+        let data: Vec<u8> = (0..=u8::MAX).collect();
+        assert!(is_incompressible(&huffman::count_frequencies(
+            data.iter().copied()
+        )));
+
+        let encoded = encode(&data, Checksum::Crc32).unwrap();
+        assert_eq!(encoded[6], Mode::Stored as u8);
+        assert_eq!(encoded.len(), FIELDS_OFFSET + 4 + 8 + data.len());
+        assert_eq!(decode(&encoded).unwrap(), data);
+
+        assert_eq!(
+            decode(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::TruncatedPayload)
+        );
+    }
+
+    #[test]
+    fn limits_the_declared_output_length() {
+        let encoded = encode(INPUT, Checksum::Crc32).unwrap();
+        assert_eq!(decode_limited(&encoded, INPUT.len()).unwrap(), INPUT);
+        assert_eq!(
+            decode_limited(&encoded, INPUT.len() - 1),
+            Err(DecodeError::OutputTooLarge)
+        );
+
+        // a bomb: a few bytes claiming to hold an absurd amount of data
+        let mut bomb = encoded.clone();
+        bomb[FIELDS_OFFSET + 4..][..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            decode_limited(&bomb, 1 << 30),
+            Err(DecodeError::OutputTooLarge)
+        );
+    }
+
+    #[test]
+    fn checksum_verification_can_be_turned_off() {
+        let mut corrupt = encode(INPUT, Checksum::Crc32).unwrap();
+        corrupt[FIELDS_OFFSET] ^= 1;
+
+        assert_eq!(decode(&corrupt), Err(DecodeError::ChecksumMismatch));
+        let unverified = DecodeOptions::new().with_verify_checksum(false);
+        assert_eq!(decode_with(&corrupt, &unverified).unwrap(), INPUT);
+    }
+
+    #[test]
+    fn trailing_data_is_only_rejected_on_request() {
+        let strict = DecodeOptions::new().with_reject_trailing(true);
+        let stored: Vec<u8> = (0..=u8::MAX).collect();
+
+        for encoded in [
+            encode(INPUT, Checksum::Crc32).unwrap(),
+            encode(&stored, Checksum::Crc32).unwrap(),
+            encode_escaped(INPUT, 11, Checksum::Crc32).unwrap(),
+        ] {
+            assert!(decode_with(&encoded, &strict).is_ok());
+
+            let padded = [&encoded[..], b"junk"].concat();
+            assert!(decode(&padded).is_ok());
+            assert_eq!(
+                decode_with(&padded, &strict),
+                Err(DecodeError::TrailingData)
+            );
+        }
+    }
+
+    #[test]
+    fn appended_files_decode_as_members() {
+        let stored: Vec<u8> = (0..=u8::MAX).collect();
+        let mut archive = encode(INPUT, Checksum::Crc32).unwrap();
+        archive.extend(encode(&stored, Checksum::None).unwrap());
+        archive.extend(encode_escaped(INPUT, 11, Checksum::Crc32).unwrap());
+
+        assert_eq!(decode_members(&archive).unwrap(), [INPUT, &stored, INPUT]);
+        // a lone file is an archive with one member
+        assert_eq!(
+            decode_members(&encode(INPUT, Checksum::Crc32).unwrap()).unwrap(),
+            [INPUT]
+        );
+        assert_eq!(decode_members(b"").unwrap(), Vec::<Vec<u8>>::new());
+
+        archive.extend(b"junk");
+        assert_eq!(decode_members(&archive), Err(DecodeError::BadMagic));
+    }
+
+    #[test]
+    fn decode_member_counts_the_bytes_it_took() {
+        let first =
+            encode_with(INPUT, &EncodeOptions::new().with_comment(Some("a comment"))).unwrap();
+        let second = encode_stored(b"second", Checksum::None);
+        let archive = [&first[..], &second].concat();
+
+        let (decoded, consumed) = decode_member(&archive, &DecodeOptions::new()).unwrap();
+        assert_eq!(decoded, INPUT);
+        assert_eq!(consumed, first.len());
+
+        let (decoded, consumed) =
+            decode_member(&archive[consumed..], &DecodeOptions::new()).unwrap();
+        assert_eq!(decoded, b"second");
+        assert_eq!(consumed, second.len());
+    }
+
+    #[test]
+    fn decodes_a_stream_embedded_at_an_offset() {
+        let encoded = encode(INPUT, Checksum::Crc32).unwrap();
+        let mut container = vec![0xaa; 100];
+        container.extend(&encoded);
+        container.extend(b"more of the container");
+
+        let (decoded, consumed) = decode_at(&container, 100).unwrap();
+        assert_eq!(decoded, INPUT);
+        assert_eq!(consumed, encoded.len());
+        assert_eq!(&container[100 + consumed..], b"more of the container");
+
+        assert_eq!(
+            decode_at(&container, container.len() + 1),
+            Err(DecodeError::TruncatedHeader)
+        );
+    }
+
+    #[test]
+    fn truncated_and_corrupt_files_are_errors_in_every_mode() {
+        let text = b"the quick brown fox\njumps over\nthe lazy dog\r\n";
+        let checksum = Checksum::Crc32;
+        let files = [
+            encode(text, checksum).unwrap(),
+            encode_stored(text, checksum),
+            encode_escaped(text, 2, checksum).unwrap(),
+            encode_nibbles(text, checksum).unwrap(),
+            encode_length_table(text, checksum).unwrap(),
+            encode_lines(text, checksum).unwrap(),
+            encode_order1(text, checksum).unwrap(),
+            with_name(encode(text, checksum).unwrap(), "name").unwrap(),
+        ];
+
+        let mut state = FLIP_SEED;
+        for file in &files {
+            // every byte counts, so anything cut off is missing some
+            for len in 0..file.len() {
+                assert!(decode(&file[..len]).is_err(), "{len} bytes of {file:?}");
+            }
+
+            // any of these may fail, but none may panic
+            for _ in 0..1000 {
+                let mut corrupt = file.clone();
+                flip_random_bits(&mut state, &mut corrupt, 3);
+                let _ = decode_members(&corrupt);
+                let _ = decode_best_effort(&corrupt);
+                let _ = decode_line(&corrupt, 1);
+            }
+        }
+
+        for garbage in [&b""[..], b"HUFF", b"HUFF\x04", &[0xff; 64]] {
+            assert!(decode(garbage).is_err());
+        }
+    }
+
+    #[test]
+    fn best_effort_keeps_what_decodes_before_damage() {
+        let text = include_bytes!("../nyi.txt");
+        let stored: Vec<u8> = (0..=u8::MAX).collect();
+
+        for data in [&text[..], &stored] {
+            let encoded = encode(data, Checksum::Crc32).unwrap();
+            assert_eq!(decode_best_effort(&encoded), (data.to_vec(), None));
+
+            let truncated = &encoded[..encoded.len() / 2];
+            let (partial, err) = decode_best_effort(truncated);
+            assert_eq!(err, Some(DecodeError::TruncatedPayload));
+            assert!(!partial.is_empty());
+            assert!(data.starts_with(&partial));
+        }
+
+        let mut corrupt = encode(INPUT, Checksum::Crc32).unwrap();
+        corrupt[FIELDS_OFFSET] ^= 1;
+        assert_eq!(
+            decode_best_effort(&corrupt),
+            (INPUT.to_vec(), Some(DecodeError::ChecksumMismatch))
+        );
+    }
+
+    #[test]
+    fn stored_option_skips_compression() {
+        let options = EncodeOptions::new().with_stored(true);
+        let encoded = encode_with(INPUT, &options).unwrap();
+
+        assert_eq!(encoded[6], Mode::Stored as u8);
+        assert_eq!(encoded, encode_stored(INPUT, Checksum::Crc32));
+        assert_eq!(decode(&encoded).unwrap(), INPUT);
+    }
+
+    #[test]
+    fn stripping_a_bom_is_recorded_only_if_there_was_one() {
+        let options = EncodeOptions::new().with_stripped_bom(true);
+        let with_bom = [&text::BOM[..], INPUT].concat();
+        let encoded = encode_with(&with_bom, &options).unwrap();
+
+        assert!(peek_header(&encoded).unwrap().0.flags.stripped_bom);
+        assert_eq!(decode(&encoded).unwrap(), INPUT);
+
+        let encoded = encode_with(INPUT, &options).unwrap();
+        assert!(!peek_header(&encoded).unwrap().0.flags.stripped_bom);
+        assert_eq!(decode(&encoded).unwrap(), INPUT);
+    }
+
+    #[test]
+    fn empty_input_decodes_to_nothing_whatever_the_options() {
+        let options = [
+            EncodeOptions::new(),
+            EncodeOptions::new().with_stored(true),
+            EncodeOptions::new().with_nibbles(true),
+            EncodeOptions::new().with_length_table(true),
+            EncodeOptions::new().with_lines(true),
+            EncodeOptions::new().with_order1(true),
+            EncodeOptions::new().with_escape_below(2),
+        ];
+
+        for options in options {
+            let encoded = encode_with(b"", &options).unwrap();
+            // no tree to read, only a length of 0
+            assert_eq!(encoded[6], Mode::Stored as u8, "{options:?}");
+            assert_eq!(
+                encoded.len(),
+                header(b"", options.checksum(), Mode::Stored).len() + 8
+            );
+            assert_eq!(decode(&encoded).unwrap(), b"");
+        }
+    }
+
+    #[test]
+    fn lists_every_mode_in_tag_order() {
+        for (tag, mode) in Mode::ALL.into_iter().enumerate() {
+            assert_eq!(mode as usize, tag);
+            assert_eq!(Mode::from_tag(tag as u8), Some(mode));
+        }
+        assert_eq!(Mode::from_tag(Mode::ALL.len() as u8), None);
+    }
+
+    #[test]
+    fn concatenates_files_sharing_a_tree() {
+        let model = huffman::count_frequencies(INPUT.iter().copied());
+        let lengths = HuffmanNode::build_tree_from_counts(&model)
+            .unwrap()
+            .code_lengths();
+        // so b starts in the middle of a byte
+        assert_ne!(
+            b"abr"
+                .map(|byte| lengths[byte as usize])
+                .iter()
+                .sum::<usize>()
+                % 8,
+            0
+        );
+        let a = encode_with_model(b"abr", &model, Checksum::Crc32).unwrap();
+        let b = encode_with_model(INPUT, &model, Checksum::Crc32).unwrap();
+
+        let joined = concat_same_tree(&a, &b).unwrap();
+        assert_eq!(decode(&joined).unwrap(), [&b"abr"[..], INPUT].concat());
+        assert_eq!(
+            joined,
+            encode_with_model(&[&b"abr"[..], INPUT].concat(), &model, Checksum::Crc32).unwrap()
+        );
+
+        let own_tree = encode(&b"aab".repeat(50), Checksum::Crc32).unwrap();
+        assert_eq!(peek_header(&own_tree).unwrap().0.mode, Mode::Huffman);
+        assert_eq!(
+            concat_same_tree(&own_tree, &b),
+            Err(DecodeError::DifferentTrees)
+        );
+        assert_eq!(
+            concat_same_tree(&encode_stored(b"abr", Checksum::Crc32), &b),
+            Err(DecodeError::UnjoinableMode(Mode::Stored))
+        );
+    }
+
+    #[test]
+    fn concatenating_keeps_the_first_files_fields() {
+        let model = huffman::count_frequencies(INPUT.iter().copied());
+        let a = encode_with_model(b"abr", &model, Checksum::Crc32).unwrap();
+        let a = with_mtime(with_comment(a, "first").unwrap(), 1_700_000_000);
+        let b = encode_with_model(INPUT, &model, Checksum::Crc32).unwrap();
+        let b = with_name(b, "second").unwrap();
+
+        let joined = concat_same_tree(&a, &b).unwrap();
+        let (header, _) = peek_header(&joined).unwrap();
+        assert_eq!(header.comment, Some("first"));
+        assert_eq!(header.name, None);
+        assert_eq!(header.mtime, Some(1_700_000_000));
+        assert_eq!(decode(&joined).unwrap(), [&b"abr"[..], INPUT].concat());
+    }
+
+    #[test]
+    fn concatenating_an_empty_file_changes_nothing() {
+        let model = huffman::count_frequencies(INPUT.iter().copied());
+        let coded = encode_with_model(INPUT, &model, Checksum::Crc32).unwrap();
+        let empty = encode(b"", Checksum::Crc32).unwrap();
+
+        assert_eq!(concat_same_tree(&empty, &coded).unwrap(), coded);
+        assert_eq!(concat_same_tree(&coded, &empty).unwrap(), coded);
+    }
+
+    #[test]
+    fn decodes_one_line_without_the_others() {
+        let input = b"first line\nsecond line\nthird line\nfourth, no newline";
+        let encoded = encode_lines(input, Checksum::Crc32).unwrap();
+
+        assert_eq!(encoded[6], Mode::Lines as u8);
+        assert_eq!(decode(&encoded).unwrap(), input);
+        assert_eq!(decode_line(&encoded, 2).unwrap().unwrap(), b"third line\n");
+        assert_eq!(
+            decode_line(&encoded, 3).unwrap().unwrap(),
+            b"fourth, no newline"
+        );
+        assert_eq!(decode_line(&encoded, 4).unwrap(), None);
+
+        // a later line's codes are never read
+        let mut damaged = encoded.clone();
+        *damaged.last_mut().unwrap() ^= 0xff;
+        assert_eq!(decode_line(&damaged, 2).unwrap().unwrap(), b"third line\n");
+        assert!(decode(&damaged).is_err());
+
+        let plain = encode(input, Checksum::Crc32).unwrap();
+        assert_eq!(decode_line(&plain, 2).unwrap().unwrap(), b"third line\n");
+    }
+
+    #[test]
+    fn order1_beats_order0_on_english_text() {
+        let text = include_bytes!("../nyi.txt").repeat(10);
+        let order1 = encode_order1(&text, Checksum::Crc32).unwrap();
+        let order0 = encode(&text, Checksum::Crc32).unwrap();
+
+        assert_eq!(order1[6], Mode::Order1 as u8);
+        assert_eq!(decode(&order1).unwrap(), text);
+        assert!(
+            order1.len() < order0.len() * 9 / 10,
+            "{} against {}",
+            order1.len(),
+            order0.len()
+        );
+    }
+
+    #[test]
+    fn length_table_beats_pairs_on_many_distinct_bytes() {
+        let input: Vec<u8> = (0..=u8::MAX).chain(INPUT.iter().copied()).collect();
+        let tree = HuffmanNode::build_tree(&input);
+
+        let pairs = [
+            header(&input, Checksum::Crc32, Mode::Huffman),
+            tree.serialize(&input).unwrap(),
+        ]
+        .concat();
+        let table = encode_length_table(&input, Checksum::Crc32).unwrap();
+
+        assert_eq!(table[6], Mode::LengthTable as u8);
+        assert_eq!(
+            pairs.len() - table.len(),
+            tree.tree_header_len() - huffman::LENGTH_TABLE_BYTES
+        );
+        assert_eq!(decode(&table).unwrap(), input);
+
+        // a few symbols fit in fewer bytes as pairs
+        assert!(
+            encode_length_table(INPUT, Checksum::Crc32).unwrap().len()
+                > encode(INPUT, Checksum::Crc32).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn nibbles_beat_bytes_on_skewed_nibbles() {
+        // every nibble independently 0 half the time, 1 a quarter, and so
+        // on, which spreads the bytes over many values
+        let mut state = 1u32;
+        let mut nibble = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 8).trailing_ones().min(15) as u8
+        };
+        let data: Vec<u8> = (0..2000).map(|_| nibble() << 4 | nibble()).collect();
+
+        let nibbles = encode_nibbles(&data, Checksum::Crc32).unwrap();
+        let bytes = encode(&data, Checksum::Crc32).unwrap();
+
+        assert_eq!(nibbles[6], Mode::Nibble as u8);
+        assert!(
+            nibbles.len() < bytes.len(),
+            "{} vs {}",
+            nibbles.len(),
+            bytes.len()
+        );
+        assert_eq!(decode(&nibbles).unwrap(), data);
+        assert_eq!(decode_best_effort(&nibbles), (data.clone(), None));
+
+        let options = EncodeOptions::new().with_nibbles(true);
+        assert_eq!(encode_with(&data, &options).unwrap(), nibbles);
+        assert_eq!(decode_limited(&nibbles, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn reads_version_1_files() {
+        // version 1 had no mode byte and was always Huffman coded
+        let tree = HuffmanNode::build_tree(INPUT);
+        let v1 = [&b"HUFF\x01\x00"[..], &tree.serialize(INPUT).unwrap()].concat();
+
+        assert_eq!(decode(&v1).unwrap(), INPUT);
+    }
+
+    #[test]
+    fn reads_version_2_files() {
+        // version 2 had no flags byte
+        let v4 = encode(INPUT, Checksum::None).unwrap();
+        let v2 = [&b"HUFF\x02\x00\x00"[..], &v4[FIELDS_OFFSET..]].concat();
+
+        assert_eq!(decode(&v2).unwrap(), INPUT);
+    }
+
+    #[test]
+    fn reads_version_3_files() {
+        // version 3 had no length of the fields, which followed the flags
+        let v4 = with_name(encode(INPUT, Checksum::Crc32).unwrap(), "a.txt").unwrap();
+        let v3 = [
+            &b"HUFF\x03"[..],
+            &v4[5..=FLAGS_OFFSET],
+            &v4[FIELDS_OFFSET..],
+        ]
+        .concat();
+
+        let (header, _) = peek_header(&v3).unwrap();
+        assert_eq!(header.name, Some("a.txt"));
+        assert_eq!(decode(&v3).unwrap(), INPUT);
+    }
+
+    #[test]
+    fn skips_fields_added_by_later_versions() {
+        let encoded = with_name(encode(INPUT, Checksum::Crc32).unwrap(), "a.txt").unwrap();
+
+        // a field this build doesn't know, after the name
+        let mut extended = encoded.clone();
+        let end = FIELDS_OFFSET + 1 + "a.txt".len();
+        extended.splice(end..end, *b"new field");
+        extended[FLAGS_OFFSET + 1] += 9;
+
+        let (header, _) = peek_header(&extended).unwrap();
+        assert_eq!(header.name, Some("a.txt"));
+        assert_eq!(decode(&extended).unwrap(), INPUT);
+
+        // known fields can't run past the length
+        let mut short = encoded.clone();
+        short[FLAGS_OFFSET + 1] -= 1;
+        assert!(decode(&short).is_err());
+    }
+
+    #[test]
+    fn flags_round_trip() {
+        let flags = Flags {
+            normalized_newlines: true,
+            stripped_bom: true,
+        };
+        let encoded = with_flags(encode(INPUT, Checksum::Crc32).unwrap(), flags);
+
+        assert_eq!(peek_header(&encoded).unwrap().0.flags, flags);
+        assert_eq!(decode(&encoded).unwrap(), INPUT);
+
+        let mut unknown = encoded.clone();
+        unknown[FLAGS_OFFSET] = 0x80;
+        assert_eq!(decode(&unknown), Err(DecodeError::UnsupportedFlags(0x80)));
+    }
+
+    #[test]
+    fn comments_round_trip_without_changing_the_output() {
+        let options = EncodeOptions::new().with_comment(Some("notes.txt, 1 of 2"));
+        let encoded = encode_with(INPUT, &options).unwrap();
+        let encoded = with_flags(
+            encoded,
+            Flags {
+                normalized_newlines: true,
+                stripped_bom: false,
+            },
+        );
+
+        let (header, _) = peek_header(&encoded).unwrap();
+        assert_eq!(header.comment, Some("notes.txt, 1 of 2"));
+        assert!(header.flags.normalized_newlines);
+        assert_eq!(decode(&encoded).unwrap(), INPUT);
+        assert_eq!(
+            peek_header(&encode(INPUT, Checksum::Crc32).unwrap())
+                .unwrap()
+                .0
+                .comment,
+            None
+        );
+
+        let too_long = "x".repeat(MAX_COMMENT_LEN + 1);
+        assert_eq!(
+            with_comment(encode(INPUT, Checksum::Crc32).unwrap(), &too_long),
+            Err(EncodeError::CommentTooLong)
+        );
+
+        let mut not_utf8 = encoded.clone();
+        not_utf8[FIELDS_OFFSET + 1] = 0xff;
+        assert_eq!(decode(&not_utf8), Err(DecodeError::InvalidComment));
+    }
+
+    #[test]
+    fn names_round_trip_and_must_stay_in_their_directory() {
+        let options = EncodeOptions::new()
+            .with_name(Some("report.txt"))
+            .with_comment(Some("weekly"));
+        let encoded = encode_with(INPUT, &options).unwrap();
+
+        let (header, _) = peek_header(&encoded).unwrap();
+        assert_eq!(header.name, Some("report.txt"));
+        assert_eq!(header.comment, Some("weekly"));
+        assert_eq!(decode(&encoded).unwrap(), INPUT);
+
+        for name in ["../evil", "/etc/passwd", "a\\b", "..", "", "C:evil"] {
+            let options = EncodeOptions::new().with_name(Some(name));
+            assert_eq!(
+                encode_with(INPUT, &options),
+                Err(EncodeError::UnsafeName),
+                "{name:?}"
+            );
+        }
+
+        // a crafted header naming `../evil.txt`
+        let mut crafted =
+            encode_with(INPUT, &EncodeOptions::new().with_name(Some("..aevil.txt"))).unwrap();
+        crafted[FIELDS_OFFSET + 3] = b'/';
+        assert_eq!(peek_header(&crafted), Err(DecodeError::UnsafeName));
+        assert_eq!(decode(&crafted), Err(DecodeError::UnsafeName));
+    }
+
+    #[test]
+    fn mtimes_follow_the_text_fields() {
+        let encoded = with_mtime(encode(INPUT, Checksum::Crc32).unwrap(), 1_700_000_000);
+        // added afterwards, but still stored before the time
+        let encoded = with_name(encoded, "a.txt").unwrap();
+
+        let (header, _) = peek_header(&encoded).unwrap();
+        assert_eq!(header.mtime, Some(1_700_000_000));
+        assert_eq!(header.name, Some("a.txt"));
+        assert_eq!(decode(&encoded).unwrap(), INPUT);
+        assert_eq!(
+            encoded[FIELDS_OFFSET + 6..][..8],
+            1_700_000_000u64.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn escapes_a_long_tail_of_rare_bytes() {
+        // a few common letters, and every other byte value once
+        let mut data = b"abcabcaabbaaccba".repeat(20);
+        data.extend((0..=u8::MAX).filter(|byte| !b"abc".contains(byte)));
+
+        let escaped = encode_escaped(&data, 2, Checksum::Crc32).unwrap();
+        let plain = encode(&data, Checksum::Crc32).unwrap();
+
+        assert_eq!(escaped[6], Mode::Escaped as u8);
+        assert!(escaped.len() < plain.len());
+        assert_eq!(decode(&escaped).unwrap(), data);
+
+        let truncated = &escaped[..escaped.len() - 1];
+        assert!(decode(truncated).is_err());
+    }
+
+    #[test]
+    fn escaping_nothing_is_plain_huffman() {
+        let escaped = encode_escaped(INPUT, 1, Checksum::Crc32).unwrap();
+
+        assert_eq!(escaped, encode(INPUT, Checksum::Crc32).unwrap());
+    }
+}
@@ -0,0 +1,53 @@
+//! `xxd`-style hex and ASCII dumps.
+
+use std::fmt::Write;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Lines of `offset: hex  ascii`, with the hex in 2-byte groups and
+/// unprintable bytes shown as `.`.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+
+    for (i, line) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let mut hex = String::new();
+        for (j, byte) in line.iter().enumerate() {
+            if j > 0 && j % 2 == 0 {
+                hex.push(' ');
+            }
+            write!(hex, "{byte:02x}").unwrap();
+        }
+
+        let ascii: String = line
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+
+        // 16 bytes take 32 hex digits plus 7 group separators
+        writeln!(dump, "{:08x}: {hex:<39}  {ascii}", i * BYTES_PER_LINE).unwrap();
+    }
+
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_xxd_layout() {
+        let dump = hex_dump(b"The quick brown fox\n\x00\xff");
+
+        assert_eq!(
+            dump,
+            "00000000: 5468 6520 7175 6963 6b20 6272 6f77 6e20  The quick brown \n\
+             00000010: 666f 780a 00ff                           fox...\n"
+        );
+    }
+}
@@ -1,3 +1,282 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{self, Read, Write};
+
+use crate::code_table::{CodeTable, PrefixTable};
+use crate::error::{DecodeError, EncodeError};
+
+/// How often each byte occurs in `items`.
+pub fn count_frequencies<I: IntoIterator<Item = u8>>(items: I) -> [usize; 256] {
+    let mut usages = [0; u8::MAX as usize + 1];
+
+    for item in items {
+        usages[item as usize] += 1;
+    }
+
+    usages
+}
+
+/// The Shannon entropy of bytes with these frequencies, in bits per byte.
+/// No prefix code can use fewer bits on average.
+pub fn entropy(freqs: &[usize; 256]) -> f64 {
+    let total: usize = freqs.iter().sum();
+
+    freqs
+        .iter()
+        .filter(|&&freq| freq > 0)
+        .map(|&freq| {
+            let p = freq as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// The longest code a tree can have. A complete tree of at most 256 leaves
+/// is at most 255 levels deep, so the methods that recurse down a tree,
+/// like [`HuffmanNode::get_depth`], never go more than 256 calls deep.
+/// Code lengths beyond this are rejected before any tree is built.
+pub const MAX_CODE_LEN: usize = 255;
+
+/// Size of the symbol count that starts every stream.
+const LENGTH_PREFIX_BYTES: usize = size_of::<u64>();
+
+fn split_length_prefix(input: &[u8]) -> Result<(u64, &[u8]), DecodeError> {
+    let (len, input) = input
+        .split_first_chunk::<LENGTH_PREFIX_BYTES>()
+        .ok_or(DecodeError::TruncatedHeader)?;
+
+    Ok((u64::from_le_bytes(*len), input))
+}
+
+/// How a stream stores its tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TreeLayout {
+    /// A `(code length, symbol)` byte pair per leaf, as written by
+    /// [`HuffmanNode::serialize_tree_bytes`]. This is the smaller one for
+    /// fewer than 80 symbols.
+    #[default]
+    Pairs,
+    /// Every byte value's code length in 5 bits, 0 for a symbol that isn't
+    /// in the tree, as written by [`HuffmanNode::length_table`].
+    LengthTable,
+}
+
+/// Size of a [`TreeLayout::LengthTable`].
+pub const LENGTH_TABLE_BYTES: usize = 256 * LENGTH_TABLE_BITS / 8;
+
+/// Bits per code length in a [`TreeLayout::LengthTable`].
+const LENGTH_TABLE_BITS: usize = 5;
+
+/// Packs a string of `'0'`s and `'1'`s into bytes, padding the last one with
+/// zero bits.
+#[cfg(test)]
+fn pack_bits(bits: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(bits.len().div_ceil(8));
+    for chunk in bits.as_bytes().chunks(8) {
+        debug_assert!(chunk.len() <= 8, "a chunk must fit in one byte");
+
+        let n = chunk.iter().fold(0_u8, |acc, &b| (acc << 1) | (b - b'0'));
+        let extra = 8 - chunk.len();
+        bytes.push(n << extra);
+    }
+
+    debug_assert_eq!(bytes.len(), bits.len().div_ceil(8));
+    bytes
+}
+
+/// The tree bytes of [`HuffmanNode::serialize_tree_bytes`] for a tree with
+/// these codes, in traversal order.
+fn tree_bytes(codes: &[(u8, Vec<bool>)]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(2 * codes.len() + 1);
+    for (val, code) in codes {
+        bytes.extend([code.len() as u8, *val]);
+    }
+    bytes.push(0);
+
+    bytes
+}
+
+/// The table of [`HuffmanNode::length_table`] for a tree with these codes.
+fn length_table(codes: &[(u8, Vec<bool>)]) -> Option<Vec<u8>> {
+    let mut lengths = [0; 256];
+    for (val, code) in codes {
+        if code.len() >= 1 << LENGTH_TABLE_BITS {
+            return None;
+        }
+        lengths[*val as usize] = code.len();
+    }
+
+    let mut bits = BitWriter::new();
+    for len in lengths {
+        bits.write_bits(len as u64, LENGTH_TABLE_BITS);
+    }
+    Some(bits.into_bytes())
+}
+
+/// Counts `items` and packs their codes, looked up in `codes`, most
+/// significant bit first and padded with zero bits to a whole byte.
+fn pack_codes<I: IntoIterator<Item = u8>>(
+    codes: &[(u8, Vec<bool>)],
+    items: I,
+) -> Result<(u64, Vec<u8>), EncodeError> {
+    let mut lookup = [None; 256];
+    for (val, code) in codes {
+        lookup[*val as usize] = Some(&code[..]);
+    }
+
+    let mut len = 0;
+    let mut packed = BitWriter::new();
+    for item in items {
+        let code = lookup[item as usize].ok_or(EncodeError::MissingSymbol(item))?;
+        packed.write_code(code);
+        len += 1;
+    }
+
+    Ok((len, packed.into_bytes()))
+}
+
+/// Packs bits most significant first, the way [`BitReader`] reads them.
+pub(crate) struct BitWriter {
+    bytes: Vec<u8>,
+    /// How many bits there are, the last byte's padding not included.
+    len: usize,
+}
+
+impl BitWriter {
+    pub(crate) fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        if self.len.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    pub(crate) fn write_code(&mut self, code: &[bool]) {
+        for &bit in code {
+            self.write_bit(bit);
+        }
+    }
+
+    /// Writes the low `n` bits of `value`, most significant first.
+    fn write_bits(&mut self, value: u64, n: usize) {
+        for i in (0..n).rev() {
+            self.write_bit(value >> i & 1 == 1);
+        }
+    }
+
+    /// The packed bits, with the last byte padded with zero bits.
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Takes the bytes whose bits are all written, keeping a partly written
+    /// last byte to carry on with.
+    pub(crate) fn take_whole_bytes(&mut self) -> Vec<u8> {
+        let whole = self.len / 8;
+        let rest = self.bytes.split_off(whole);
+        self.len %= 8;
+        std::mem::replace(&mut self.bytes, rest)
+    }
+}
+
+/// Reads packed bits most significant first, keeping up to 64 of them
+/// buffered so that several can be looked at at once.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    /// The buffered bits, starting at the top bit.
+    buffer: u64,
+    buffered: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            buffer: 0,
+            buffered: 0,
+        }
+    }
+
+    fn refill(&mut self) {
+        while self.buffered <= 56
+            && let Some((&byte, rest)) = self.bytes.split_first()
+        {
+            self.buffer |= (byte as u64) << (56 - self.buffered);
+            self.buffered += 8;
+            self.bytes = rest;
+        }
+    }
+
+    /// The next `n` bits, padded with zeros past the end. Afterwards at
+    /// least `n` bits are buffered unless the input runs out first.
+    fn peek(&mut self, n: usize) -> usize {
+        debug_assert!(n > 0 && n <= 56);
+        self.refill();
+        (self.buffer >> (64 - n)) as usize
+    }
+
+    fn consume(&mut self, n: usize) {
+        debug_assert!(n <= self.buffered);
+        self.buffer <<= n;
+        self.buffered -= n;
+    }
+
+    /// How many bits are left, buffered or not.
+    fn remaining_bits(&self) -> usize {
+        self.bytes.len() * 8 + self.buffered
+    }
+
+    /// Fails if `len` symbols couldn't fit in the bits that are left, since
+    /// every code is at least one bit long. This catches a corrupt symbol
+    /// count before decoding starts.
+    fn check_len(&self, len: u64) -> Result<(), DecodeError> {
+        if len > self.remaining_bits() as u64 {
+            return Err(DecodeError::ImplausibleLength);
+        }
+        Ok(())
+    }
+
+    /// How many bytes at the end of the input haven't been touched, not
+    /// counting the partly consumed one.
+    fn unread_bytes(&self) -> usize {
+        self.bytes.len() + self.buffered / 8
+    }
+}
+
+impl Iterator for BitReader<'_> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        self.refill();
+        if self.buffered == 0 {
+            return None;
+        }
+
+        let bit = self.buffer >> 63 == 1;
+        self.consume(1);
+        Some(bit)
+    }
+}
+
+/// What decoding a stream took, from [`HuffmanNode::decode_with_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DecodeStats {
+    /// Bits of payload read, not counting the padding after the last code.
+    pub bits: u64,
+    pub symbols: u64,
+    /// The longest code among the symbols that were decoded.
+    pub max_code_len: usize,
+}
+
 #[derive(Debug, Clone)]
 pub enum HuffmanNode {
     Node {
@@ -10,6 +289,53 @@ pub enum HuffmanNode {
     },
 }
 
+/// A tree waiting to be merged while building, ordered so that a
+/// [`BinaryHeap`] pops the lightest one first. Of two equally heavy trees the
+/// one added later comes first, where leaves are added by symbol and merged
+/// trees after them in the order they were made, so ties always break the
+/// same way.
+struct WeightedNode {
+    weight: usize,
+    /// The position the tree was added in.
+    order: usize,
+    node: HuffmanNode,
+}
+
+impl WeightedNode {
+    fn new(node: HuffmanNode, order: usize) -> Self {
+        Self {
+            weight: node.get_usage(),
+            order,
+            node,
+        }
+    }
+}
+
+impl Ord for WeightedNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .weight
+            .cmp(&self.weight)
+            .then(self.order.cmp(&other.order))
+    }
+}
+
+impl PartialOrd for WeightedNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// by weight and order only, like `Ord`; `HuffmanNode`'s own equality ignores
+// counts, which is a different question
+impl PartialEq for WeightedNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for WeightedNode {}
+
 impl PartialEq for HuffmanNode {
     // we don't care about counts. those are only useful during building.
     fn eq(&self, other: &Self) -> bool {
@@ -34,78 +360,235 @@ impl HuffmanNode {
     pub fn get_usage(&self) -> usize {
         match self {
             Self::Leaf { val: _, count } => *count,
-            Self::Node { left, right } => left.get_usage() + right.get_usage(),
+            // counts from a model needn't fit in memory, so their sum may
+            // not fit a `usize` either
+            Self::Node { left, right } => left.get_usage().saturating_add(right.get_usage()),
         }
     }
 
+    /// Counts the bytes of `items` and builds the tree from the counts, so
+    /// callers that already have them should use
+    /// [`Self::build_tree_from_counts`] instead of counting twice.
+    ///
+    /// # Panics
+    ///
+    /// If `items` is empty. Use [`Self::from_byte_iter`] to get `None`
+    /// instead.
     pub fn build_tree(items: &[u8]) -> Self {
-        if items.is_empty() {
-            panic!("Cannot build a tree over an empty string!")
-        }
-
-        let mut usages = [0; u8::MAX as usize + 1];
+        Self::from_byte_iter(items.iter().copied())
+            .expect("Cannot build a tree over an empty string!")
+    }
 
-        for item in items {
-            usages[*item as usize] += 1;
-        }
+    /// Counts frequencies while consuming `items`, so the source never has
+    /// to be collected into a slice. Returns `None` if `items` is empty.
+    pub fn from_byte_iter<I: IntoIterator<Item = u8>>(items: I) -> Option<Self> {
+        Self::build_tree_from_counts(&count_frequencies(items))
+    }
 
-        let mut trees = Vec::new();
-        for (i, use_count) in usages.into_iter().enumerate() {
+    /// Builds a tree from how often each byte occurs. Returns `None` if every
+    /// count is zero.
+    ///
+    /// The remaining trees are kept in a heap ordered by [`WeightedNode`], so
+    /// this takes `O(n log n)` for `n` distinct symbols, and the cost never
+    /// depends on the length of the input.
+    pub fn build_tree_from_counts(usages: &[usize; 256]) -> Option<Self> {
+        let mut leaves = Vec::new();
+        for (i, &use_count) in usages.iter().enumerate() {
             if use_count > 0 {
-                trees.push(HuffmanNode::Leaf {
+                leaves.push(HuffmanNode::Leaf {
                     val: i as u8,
                     count: use_count,
                 })
             }
         }
 
-        while trees.len() > 1 {
-            trees.sort_by(|a, b| b.get_usage().cmp(&a.get_usage()));
+        // a lone symbol still needs a 1-bit code, so pair it with an unused one
+        if let [HuffmanNode::Leaf { val, .. }] = leaves[..] {
+            leaves.push(HuffmanNode::Leaf {
+                val: val.wrapping_add(1),
+                count: 0,
+            });
+        }
 
-            // last 2 will be smallest
+        let mut order = 0..;
+        let mut trees: BinaryHeap<_> = leaves
+            .into_iter()
+            .map(|leaf| WeightedNode::new(leaf, order.next().unwrap()))
+            .collect();
+        while trees.len() > 1 {
             let a = trees.pop().unwrap();
             let b = trees.pop().unwrap();
 
-            let new = HuffmanNode::Node {
-                left: Box::new(a),
-                right: Box::new(b),
-            };
-
-            trees.push(new);
+            trees.push(WeightedNode {
+                weight: a.weight.saturating_add(b.weight),
+                order: order.next().unwrap(),
+                node: HuffmanNode::Node {
+                    left: Box::new(a.node),
+                    right: Box::new(b.node),
+                },
+            });
         }
 
-        let tree = trees.pop().unwrap();
+        let tree = trees.pop()?.node;
 
         if let HuffmanNode::Leaf { .. } = tree {
             panic!("somehow, palpatine returned");
         } else {
-            tree
+            Some(tree.into_canonical())
         }
     }
 
-    pub fn serialize(&self, s: &[u8]) -> Vec<u8> {
-        let tree = self.serialize_tree();
-        let encoded = s
-            .iter()
-            .map(|n| {
-                let s = self.encode(*n).unwrap();
-                s
-            })
-            .fold("".to_string(), |s, n| s + &n);
+    /// The stream layout is:
+    ///
+    /// - the number of encoded symbols, as a little-endian `u64`
+    /// - the tree, as written by [`Self::serialize_tree_bytes`]
+    /// - the code of every symbol, packed most significant bit first and
+    ///   padded with zero bits to a whole byte
+    ///
+    /// The tree ends on a byte boundary, so this is [`Self::serialize_payload`]
+    /// with the tree inserted after its length.
+    ///
+    /// Fails if `s` contains a byte that is not in the tree, which never
+    /// happens for a tree built from `s` itself.
+    pub fn serialize(&self, s: &[u8]) -> Result<Vec<u8>, EncodeError> {
+        self.serialize_iter(s.iter().copied())
+    }
 
-        let total = tree + &encoded;
+    /// Like [`Self::serialize`], but consumes the symbols from an iterator,
+    /// e.g. to watch how far encoding has got.
+    pub fn serialize_iter<I: IntoIterator<Item = u8>>(
+        &self,
+        items: I,
+    ) -> Result<Vec<u8>, EncodeError> {
+        let codes = self.codes();
+        let (len, payload) = pack_codes(&codes, items)?;
 
-        total
-            .as_bytes()
-            .chunks(8)
-            .map(|chunk| {
-                let n = chunk.iter().fold(0_u8, |acc, &b| (acc << 1) | (b - b'0'));
-                let extra = 8 - chunk.len();
-                n << extra
-            })
-            .collect()
+        Ok([&len.to_le_bytes()[..], &tree_bytes(&codes), &payload].concat())
+    }
+
+    /// Like [`Self::serialize`], but with the tree stored as `layout` says.
+    /// Fails with [`EncodeError::CodeTooLong`] if it is a
+    /// [`TreeLayout::LengthTable`] and a code is longer than 31 bits.
+    pub fn serialize_with_layout(
+        &self,
+        s: &[u8],
+        layout: TreeLayout,
+    ) -> Result<Vec<u8>, EncodeError> {
+        let codes = self.codes();
+        let tree = match layout {
+            TreeLayout::Pairs => tree_bytes(&codes),
+            TreeLayout::LengthTable => length_table(&codes).ok_or(EncodeError::CodeTooLong)?,
+        };
+        let (len, payload) = pack_codes(&codes, s.iter().copied())?;
+
+        Ok([&len.to_le_bytes()[..], &tree, &payload].concat())
+    }
+
+    /// The code length of every byte value, in order, packed into
+    /// [`LENGTH_TABLE_BYTES`] bytes at 5 bits each, most significant bit
+    /// first. The tree is canonical, so the lengths are all it takes to
+    /// rebuild it. Returns `None` if a code is longer than 31 bits.
+    pub fn length_table(&self) -> Option<Vec<u8>> {
+        length_table(&self.codes())
     }
 
+    /// A `(code length, symbol)` byte pair per leaf in traversal order,
+    /// terminated by a `0` byte. Read it back with [`Self::decode_tree_bytes`].
+    pub fn serialize_tree_bytes(&self) -> Vec<u8> {
+        tree_bytes(&self.codes())
+    }
+
+    /// The length of what [`Self::serialize_tree_bytes`] returns, from the
+    /// number of leaves alone.
+    pub fn tree_header_len(&self) -> usize {
+        2 * self.leaf_count() + 1
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        match self {
+            HuffmanNode::Leaf { .. } => 1,
+            HuffmanNode::Node { left, right } => left.leaf_count() + right.leaf_count(),
+        }
+    }
+
+    /// The length of what [`Self::serialize`] would return for `s`, worked
+    /// out from the code lengths without encoding anything. Bytes without a
+    /// code, which make `serialize` fail, count as zero bits.
+    pub fn serialized_len(&self, s: &[u8]) -> usize {
+        let lengths = self.code_lengths();
+        let payload_bits: usize = s.iter().map(|&item| lengths[item as usize]).sum();
+
+        LENGTH_PREFIX_BYTES + self.tree_header_len() + payload_bits.div_ceil(8)
+    }
+
+    /// The tree and then the codes for `s` as lines of `0`s and `1`s, for
+    /// teaching. This is not a storage format: there is no symbol count and
+    /// nothing can read it back.
+    pub fn bit_string(&self, s: &[u8]) -> Result<String, EncodeError> {
+        let table = CodeTable::new(self);
+        let mut payload = String::new();
+        for &item in s {
+            let code = table
+                .encode_symbol(item)
+                .ok_or(EncodeError::MissingSymbol(item))?;
+            payload.extend(code.bits().map(|bit| if bit { '1' } else { '0' }));
+        }
+
+        Ok(format!(
+            "tree:    {}\npayload: {payload}\n",
+            self.serialize_tree()
+        ))
+    }
+
+    /// The symbol count followed by the packed codes, without the tree, so
+    /// the two can be stored apart. Read it back with [`Self::decode_payload`].
+    pub fn serialize_payload(&self, s: &[u8]) -> Result<Vec<u8>, EncodeError> {
+        self.serialize_payload_iter(s.iter().copied())
+    }
+
+    pub fn serialize_payload_iter<I: IntoIterator<Item = u8>>(
+        &self,
+        items: I,
+    ) -> Result<Vec<u8>, EncodeError> {
+        let (len, payload) = pack_codes(&self.codes(), items)?;
+
+        Ok([&len.to_le_bytes()[..], &payload].concat())
+    }
+
+    /// Like [`Self::serialize`], but any byte without a code of its own is
+    /// written as the code for `escape` followed by the byte's 8 bits, and so
+    /// is `escape` itself. Read it back with [`Self::decode_escaped`].
+    ///
+    /// This keeps rare bytes out of the tree: build it with a single `escape`
+    /// leaf standing in for all of them.
+    pub fn serialize_escaped(&self, s: &[u8], escape: u8) -> Result<Vec<u8>, EncodeError> {
+        let codes = self.codes();
+        let mut lookup = [None; 256];
+        for (val, code) in &codes {
+            lookup[*val as usize] = Some(&code[..]);
+        }
+        let escape_code = lookup[escape as usize].ok_or(EncodeError::MissingSymbol(escape))?;
+
+        let mut bits = BitWriter::new();
+        for &n in s {
+            match lookup[n as usize] {
+                Some(code) if n != escape => bits.write_code(code),
+                _ => {
+                    bits.write_code(escape_code);
+                    bits.write_bits(n.into(), 8);
+                }
+            }
+        }
+
+        Ok([
+            &(s.len() as u64).to_le_bytes()[..],
+            &tree_bytes(&codes),
+            &bits.into_bytes(),
+        ]
+        .concat())
+    }
+
+    #[cfg(test)]
     fn encode(&self, n: u8) -> Option<String> {
         let (left, right) = match self {
             Self::Node { left, right } => (left, right),
@@ -120,25 +603,36 @@ impl HuffmanNode {
             return Some("1".to_string() + &s);
         }
 
-        return None;
+        None
+    }
+
+    /// Every leaf's symbol and code, left to right.
+    pub(crate) fn codes(&self) -> Vec<(u8, Vec<bool>)> {
+        let mut codes = Vec::new();
+        self.in_order_traversal(&mut Vec::new(), &mut codes);
+        codes
     }
 
-    fn in_order_traversal(&self, pre: String) -> Vec<(u8, String)> {
+    /// `code` is the path to `self`; bits are pushed on the way down and
+    /// popped on the way back up, so only the leaves allocate.
+    fn in_order_traversal(&self, code: &mut Vec<bool>, codes: &mut Vec<(u8, Vec<bool>)>) {
         match self {
-            Self::Leaf { val, .. } => vec![(*val, pre)],
+            Self::Leaf { val, .. } => codes.push((*val, code.clone())),
             Self::Node { left, right } => {
-                let left = left.in_order_traversal(pre.clone() + "0");
-
-                let right = right.in_order_traversal(pre + "1");
+                code.push(false);
+                left.in_order_traversal(code, codes);
+                code.pop();
 
-                [left, right].concat()
+                code.push(true);
+                right.in_order_traversal(code, codes);
+                code.pop();
             }
         }
     }
 
     fn serialize_tree(&self) -> String {
         // collect codes
-        let codes = self.in_order_traversal("".into());
+        let codes = self.codes();
 
         let mut codes = codes
             .into_iter()
@@ -150,110 +644,1521 @@ impl HuffmanNode {
         codes
     }
 
-    pub fn decode(input: &[u8]) -> (Self, Vec<u8>) {
-        let input: &[_] = &input
-            .iter()
-            .flat_map(|n| (0..8).map(move |i| (n >> (7 - i)) & 1 == 1))
-            .chain(std::iter::once(false)) // avoid slice issues
-            .collect::<Vec<_>>();
+    /// Decoding stops once the declared number of symbols has been read;
+    /// padding bits and any bytes appended after the stream are ignored.
+    pub fn decode(input: &[u8]) -> Result<(Self, Vec<u8>), DecodeError> {
+        let (tree, items, _) = Self::decode_with_rest(input, TreeLayout::Pairs)?;
+
+        Ok((tree, items))
+    }
+
+    /// Like [`Self::decode`], but also counts what it decoded. The counting
+    /// happens once decoding is done, so [`Self::decode`] pays nothing for it.
+    pub fn decode_with_stats(input: &[u8]) -> Result<(Self, Vec<u8>, DecodeStats), DecodeError> {
+        let (len, input) = split_length_prefix(input)?;
+
+        let (tree, input) = Self::decode_tree(input)?;
+        let mut bits = BitReader::new(input);
+        let items = tree.decode_items(&mut bits, len)?;
+
+        let lengths = tree.code_lengths();
+        let stats = DecodeStats {
+            bits: (input.len() * 8 - bits.remaining_bits()) as u64,
+            symbols: items.len() as u64,
+            max_code_len: items
+                .iter()
+                .map(|&item| lengths[item as usize])
+                .max()
+                .unwrap_or(0),
+        };
+
+        Ok((tree, items, stats))
+    }
+
+    /// Like [`Self::decode`], but for a stream whose tree is stored as
+    /// `layout`, and also returns the bytes after the payload.
+    pub(crate) fn decode_with_rest(
+        input: &[u8],
+        layout: TreeLayout,
+    ) -> Result<(Self, Vec<u8>, &[u8]), DecodeError> {
+        let (len, input) = split_length_prefix(input)?;
+
+        let (tree, input) = Self::decode_tree_with_layout(input, layout)?;
+        let mut bits = BitReader::new(input);
+        let items = tree.decode_items(&mut bits, len)?;
+
+        Ok((tree, items, &input[input.len() - bits.unread_bytes()..]))
+    }
+
+    /// Reads the symbol count and tree at the start of a stream from
+    /// [`Self::serialize`] without decoding any symbols.
+    pub fn peek_tree(input: &[u8]) -> Result<(u64, Self), DecodeError> {
+        Self::peek_tree_with_layout(input, TreeLayout::Pairs)
+    }
+
+    /// [`Self::peek_tree`] for a stream whose tree is stored as `layout`.
+    pub fn peek_tree_with_layout(
+        input: &[u8],
+        layout: TreeLayout,
+    ) -> Result<(u64, Self), DecodeError> {
+        let (len, input) = split_length_prefix(input)?;
+
+        Ok((len, Self::decode_tree_with_layout(input, layout)?.0))
+    }
 
-        let (tree, mut input) = Self::decode_tree(input);
+    /// Decodes a stream from [`Self::serialize_escaped`].
+    pub fn decode_escaped(input: &[u8], escape: u8) -> Result<Vec<u8>, DecodeError> {
+        Ok(Self::decode_escaped_with_rest(input, escape)?.0)
+    }
+
+    /// Like [`Self::decode_escaped`], but also returns the bytes after the
+    /// payload.
+    pub(crate) fn decode_escaped_with_rest(
+        input: &[u8],
+        escape: u8,
+    ) -> Result<(Vec<u8>, &[u8]), DecodeError> {
+        let (len, input) = split_length_prefix(input)?;
+        let (tree, input) = Self::decode_tree(input)?;
 
+        let mut bits = BitReader::new(input);
+        bits.check_len(len)?;
         let mut items = Vec::new();
-        while input.len() > 1 {
-            let (item, inp) = tree.decode_item(input);
-            input = inp;
-            if item == 0 {
-                break;
+        tree.decode_escaped_items_into(&mut bits, len, escape, &mut items)?;
+
+        Ok((items, &input[input.len() - bits.unread_bytes()..]))
+    }
+
+    /// Decodes as much of a stream from [`Self::serialize`] as it can into
+    /// `items`, for recovering what's left of a damaged one. Unlike
+    /// [`Self::decode`] it goes on until the bits run out even if the
+    /// symbol count is implausible.
+    pub(crate) fn decode_partial(
+        input: &[u8],
+        layout: TreeLayout,
+        items: &mut Vec<u8>,
+    ) -> Result<(), DecodeError> {
+        let (len, input) = split_length_prefix(input)?;
+        let (tree, input) = Self::decode_tree_with_layout(input, layout)?;
+
+        tree.decode_items_into(&mut BitReader::new(input), len, items)
+    }
+
+    /// [`Self::decode_partial`] for a stream from [`Self::serialize_escaped`].
+    pub(crate) fn decode_escaped_partial(
+        input: &[u8],
+        escape: u8,
+        items: &mut Vec<u8>,
+    ) -> Result<(), DecodeError> {
+        let (len, input) = split_length_prefix(input)?;
+        let (tree, input) = Self::decode_tree(input)?;
+
+        tree.decode_escaped_items_into(&mut BitReader::new(input), len, escape, items)
+    }
+
+    fn decode_escaped_items_into(
+        &self,
+        bits: &mut BitReader,
+        len: u64,
+        escape: u8,
+        items: &mut Vec<u8>,
+    ) -> Result<(), DecodeError> {
+        for _ in 0..len {
+            let mut item = self.decode_item(bits)?;
+            if item == escape {
+                item = bits.peek(8) as u8;
+                if bits.buffered < 8 {
+                    return Err(DecodeError::TruncatedPayload);
+                }
+                bits.consume(8);
             }
             items.push(item);
         }
 
-        (tree, items)
+        Ok(())
+    }
+
+    pub fn decode_tree_bytes(input: &[u8]) -> Result<Self, DecodeError> {
+        Ok(Self::decode_tree(input)?.0)
+    }
+
+    /// Decodes a payload from [`Self::serialize_payload`] that was encoded
+    /// with this tree.
+    pub fn decode_payload(&self, payload: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        let (len, payload) = split_length_prefix(payload)?;
+
+        self.decode_items(&mut BitReader::new(payload), len)
+    }
+
+    fn decode_items(&self, bits: &mut BitReader, len: u64) -> Result<Vec<u8>, DecodeError> {
+        bits.check_len(len)?;
+        let mut items = Vec::new();
+        self.decode_items_into(bits, len, &mut items)?;
+
+        Ok(items)
+    }
+
+    /// Decodes up to `len` symbols onto the end of `items`, keeping the ones
+    /// before an error.
+    fn decode_items_into(
+        &self,
+        bits: &mut BitReader,
+        len: u64,
+        items: &mut Vec<u8>,
+    ) -> Result<(), DecodeError> {
+        let table = PrefixTable::new(self);
+
+        for _ in 0..len {
+            let item = match table.decode_symbol(bits.peek(table.bits())) {
+                Some((item, code_len)) if code_len <= bits.buffered => {
+                    bits.consume(code_len);
+                    item
+                }
+                // the tree walk handles codes too long for the table, and
+                // reports truncation
+                _ => self.decode_item(bits)?,
+            };
+            items.push(item);
+        }
+
+        Ok(())
     }
 
-    fn decode_item<'a>(&self, mut input: &'a [bool]) -> (u8, &'a [bool]) {
+    /// Walks the tree from the root, one bit per node.
+    pub(crate) fn decode_item<I: Iterator<Item = bool>>(
+        &self,
+        bits: &mut I,
+    ) -> Result<u8, DecodeError> {
         let mut node = self;
         loop {
-            let n = input[0];
-
             match node {
-                HuffmanNode::Leaf { val, .. } => {
-                    return (*val, input);
-                }
+                HuffmanNode::Leaf { val, .. } => return Ok(*val),
                 HuffmanNode::Node { left, right } => {
-                    if n {
+                    if bits.next().ok_or(DecodeError::TruncatedPayload)? {
                         node = right;
                     } else {
                         node = left;
                     }
-                    input = &input[1..];
                 }
             }
         }
     }
 
-    fn decode_tree(mut input: &[bool]) -> (Self, &[bool]) {
-        fn get_byte(input: &[bool]) -> u8 {
-            let mut byte = 0;
-            for (i, &bit) in input.iter().take(8).enumerate() {
-                if bit {
-                    byte |= 1 << (7 - i);
-                }
+    /// The tree is byte aligned, so it is read straight from the bytes.
+    fn decode_tree(mut input: &[u8]) -> Result<(Self, &[u8]), DecodeError> {
+        fn get_byte(input: &mut &[u8]) -> Result<u8, DecodeError> {
+            let (&byte, rest) = input.split_first().ok_or(DecodeError::TruncatedHeader)?;
+            *input = rest;
+
+            Ok(byte)
+        }
+
+        let mut leaves = Vec::new();
+        let mut seen = [false; 256];
+        loop {
+            let len = get_byte(&mut input)?;
+            if len == 0 {
+                break;
             }
-            byte
+            let char = get_byte(&mut input)?;
+            // one of the codes could never be decoded to
+            if std::mem::replace(&mut seen[char as usize], true) {
+                return Err(DecodeError::DuplicateSymbol(char));
+            }
+            leaves.push((len as usize, char, 0));
         }
 
-        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-        struct CodePoint {
-            len: u8,
-            data: u8,
+        if leaves.is_empty() {
+            return Err(DecodeError::EmptyTree);
         }
+        // a `0` length ends the list, so every leaf is at least one bit deep
+        // and decoding a symbol always reads a bit and ends at a leaf
+        let tree = Self::from_leaves(&leaves).ok_or(DecodeError::InvalidTree)?;
 
-        let mut codes = Vec::new();
-        while get_byte(input) != 0 {
-            let len = get_byte(input);
-            input = &input[8..];
-            let char = get_byte(input);
-            input = &input[8..];
-            codes.push(CodePoint { len, data: char });
-        }
-        input = &input[8..]; // go past the 0
-
-        fn build_tree(items: &[CodePoint], index: &mut usize, depth: u8) -> HuffmanNode {
-            if items[*index].len == depth {
-                let codepoint = items[*index];
-                *index += 1;
-                return HuffmanNode::Leaf {
-                    val: codepoint.data,
-                    count: 0,
-                };
-            }
+        Ok((tree, input))
+    }
+
+    fn decode_tree_with_layout(
+        input: &[u8],
+        layout: TreeLayout,
+    ) -> Result<(Self, &[u8]), DecodeError> {
+        match layout {
+            TreeLayout::Pairs => Self::decode_tree(input),
+            TreeLayout::LengthTable => Self::decode_length_table(input),
+        }
+    }
 
-            let left = build_tree(items, index, depth + 1);
-            let right = build_tree(items, index, depth + 1);
+    /// Reads a tree written by [`Self::length_table`].
+    fn decode_length_table(input: &[u8]) -> Result<(Self, &[u8]), DecodeError> {
+        let (table, input) = input
+            .split_first_chunk::<LENGTH_TABLE_BYTES>()
+            .ok_or(DecodeError::TruncatedHeader)?;
 
-            HuffmanNode::Node {
-                left: Box::new(left),
-                right: Box::new(right),
-            }
+        let mut bits = BitReader::new(table);
+        let mut lengths = [0; 256];
+        for len in &mut lengths {
+            *len = bits.peek(LENGTH_TABLE_BITS);
+            bits.consume(LENGTH_TABLE_BITS);
         }
 
-        let tree = build_tree(&codes, &mut 0, 0);
+        if lengths.iter().all(|&len| len == 0) {
+            return Err(DecodeError::EmptyTree);
+        }
+        let tree = Self::from_code_lengths(&lengths).ok_or(DecodeError::InvalidTree)?;
 
-        (tree, input)
+        Ok((tree, input))
     }
 
-    #[allow(unused)]
-    pub fn get_depth(&self) -> usize {
-        match self {
-            HuffmanNode::Leaf { .. } => 1,
-            HuffmanNode::Node { left, right } => {
-                let l = left.get_depth();
-                let r = right.get_depth();
+    /// Rebuilds a tree from its leaves listed left to right as
+    /// `(depth, symbol, count)`, or returns `None` if they don't fill exactly
+    /// one tree.
+    fn from_leaves(leaves: &[(usize, u8, usize)]) -> Option<Self> {
+        // the recursion goes as deep as the deepest leaf claims to be
+        if leaves.iter().any(|&(depth, ..)| depth > MAX_CODE_LEN) {
+            return None;
+        }
 
-                l.max(r) + 1
+        let mut index = 0;
+        let tree = Self::from_leaf_depths(leaves, &mut index, 0)?;
+
+        (index == leaves.len()).then_some(tree)
+    }
+
+    fn from_leaf_depths(
+        leaves: &[(usize, u8, usize)],
+        index: &mut usize,
+        depth: usize,
+    ) -> Option<Self> {
+        let &(leaf_depth, val, count) = leaves.get(*index)?;
+        if leaf_depth == depth {
+            *index += 1;
+            return Some(HuffmanNode::Leaf { val, count });
+        }
+        // every leaf below here is deeper, so this one can never be placed
+        if leaf_depth < depth {
+            return None;
+        }
+
+        let left = Self::from_leaf_depths(leaves, index, depth + 1)?;
+        let right = Self::from_leaf_depths(leaves, index, depth + 1)?;
+
+        Some(HuffmanNode::Node {
+            left: Box::new(left),
+            right: Box::new(right),
+        })
+    }
+
+    /// Reshapes the tree so that its codes are the canonical Huffman codes
+    /// for its code lengths: leaves sorted by code length and then by symbol,
+    /// which is the order [`CodeTable`](crate::code_table::CodeTable) assigns
+    /// codes in.
+    fn into_canonical(self) -> Self {
+        fn collect_leaves(node: HuffmanNode, depth: usize, leaves: &mut Vec<(usize, u8, usize)>) {
+            match node {
+                HuffmanNode::Leaf { val, count } => leaves.push((depth, val, count)),
+                HuffmanNode::Node { left, right } => {
+                    collect_leaves(*left, depth + 1, leaves);
+                    collect_leaves(*right, depth + 1, leaves);
+                }
             }
         }
+
+        let mut leaves = Vec::new();
+        collect_leaves(self, 0, &mut leaves);
+        leaves.sort_by_key(|&(depth, val, _)| (depth, val));
+
+        Self::from_leaves(&leaves).expect("sorting the leaves of a tree keeps it a tree")
+    }
+
+    /// Builds the canonical tree for the given code lengths, which hold 0 for
+    /// absent symbols. Counts are not known, so every leaf's is zero.
+    /// Returns `None` if the lengths don't describe a complete tree.
+    pub fn from_code_lengths(lengths: &[usize; 256]) -> Option<Self> {
+        let mut leaves: Vec<_> = (0..=u8::MAX)
+            .filter(|&val| lengths[val as usize] > 0)
+            .map(|val| (lengths[val as usize], val, 0))
+            .collect();
+        leaves.sort_by_key(|&(depth, val, _)| (depth, val));
+
+        Self::from_leaves(&leaves)
+    }
+
+    /// Every symbol's code as a string of `'0'`s and `'1'`s, which is handy
+    /// for debugging and teaching but slow to encode with; use a
+    /// [`CodeTable`](crate::code_table::CodeTable) for that.
+    ///
+    /// ```
+    /// use huffman_encoding::huffman::HuffmanNode;
+    ///
+    /// let codes = HuffmanNode::build_tree(b"aaabbc").code_map();
+    ///
+    /// assert_eq!(codes[&b'a'], "0");
+    /// assert_eq!(codes[&b'b'], "10");
+    /// assert_eq!(codes[&b'c'], "11");
+    /// ```
+    pub fn code_map(&self) -> HashMap<u8, String> {
+        self.codes()
+            .into_iter()
+            .map(|(val, code)| {
+                let code = code
+                    .iter()
+                    .map(|&bit| if bit { '1' } else { '0' })
+                    .collect();
+                (val, code)
+            })
+            .collect()
+    }
+
+    /// The code length of every symbol, or 0 for symbols not in the tree.
+    pub fn code_lengths(&self) -> [usize; 256] {
+        let mut lengths = [0; 256];
+        for (val, code) in self.codes() {
+            lengths[val as usize] = code.len();
+        }
+        lengths
+    }
+
+    /// How many symbols have each code length, indexed by length. Codes can
+    /// be up to 255 bits long, so this is only as long as the longest code
+    /// needs.
+    pub fn code_length_histogram(&self) -> Vec<usize> {
+        let mut histogram = Vec::new();
+        for (_, code) in self.codes() {
+            if histogram.len() <= code.len() {
+                histogram.resize(code.len() + 1, 0);
+            }
+            histogram[code.len()] += 1;
+        }
+        histogram
+    }
+
+    /// A hash of the shape and symbols of the tree, ignoring counts like
+    /// `==` does, for keying caches. It is 64-bit FNV-1a over
+    /// [`Self::serialize_tree_bytes`], whose leaf depths in traversal order
+    /// pin down the shape, so it's the same on every run and platform.
+    pub fn fingerprint(&self) -> u64 {
+        self.serialize_tree_bytes()
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+            })
+    }
+
+    /// The length of the code for `symbol` in bits, or `None` if it isn't
+    /// in the tree.
+    pub fn depth_of(&self, symbol: u8) -> Option<usize> {
+        match self {
+            Self::Leaf { val, .. } => (*val == symbol).then_some(0),
+            Self::Node { left, right } => left
+                .depth_of(symbol)
+                .or_else(|| right.depth_of(symbol))
+                .map(|depth| depth + 1),
+        }
+    }
+
+    pub fn get_depth(&self) -> usize {
+        match self {
+            HuffmanNode::Leaf { .. } => 1,
+            HuffmanNode::Node { left, right } => {
+                let l = left.get_depth();
+                let r = right.get_depth();
+
+                l.max(r) + 1
+            }
+        }
+    }
+
+    /// How much longer the longest code is than it would be in a balanced
+    /// tree with as many leaves, whose codes are all `log2(leaf_count)` bits
+    /// rounded up. A balanced tree gives 1.0, and the more skewed the counts
+    /// the tree was built from, the higher it gets.
+    pub fn skew(&self) -> f64 {
+        let balanced_depth = self.leaf_count().next_power_of_two().trailing_zeros();
+        if balanced_depth == 0 {
+            return 1.0;
+        }
+
+        (self.get_depth() - 1) as f64 / f64::from(balanced_depth)
+    }
+
+    /// The frequency-weighted mean code length in bits, over the symbols in
+    /// this tree. Compare it against the entropy of `freqs` to see how close
+    /// the tree is to optimal.
+    pub fn average_code_length(&self, freqs: &[usize; 256]) -> f64 {
+        let (bits, count) = self
+            .codes()
+            .into_iter()
+            .map(|(val, code)| (freqs[val as usize], code.len()))
+            .fold((0, 0), |(bits, count), (freq, len)| {
+                (bits + freq * len, count + freq)
+            });
+
+        if count == 0 {
+            0.0
+        } else {
+            bits as f64 / count as f64
+        }
+    }
+}
+
+/// Encodes a sequence of token IDs, the symbols of some application-level
+/// alphabet of up to 256 tokens, with a tree built over those IDs. Mapping
+/// tokens to IDs and back is up to the caller, and so is storing `tree`:
+/// the result is a symbol count and the packed codes, as
+/// [`HuffmanNode::serialize_payload`] writes them. Read it back with
+/// [`decode_tokens`].
+///
+/// ```
+/// use huffman_encoding::huffman::{self, HuffmanNode};
+///
+/// let text = "the cat saw the dog and the dog saw the cat";
+/// let mut vocabulary: Vec<&str> = Vec::new();
+/// let ids: Vec<u8> = text
+///     .split(' ')
+///     .map(|word| {
+///         let id = vocabulary.iter().position(|&known| known == word);
+///         id.unwrap_or_else(|| {
+///             vocabulary.push(word);
+///             vocabulary.len() - 1
+///         }) as u8
+///     })
+///     .collect();
+///
+/// let tree = HuffmanNode::build_tree(&ids);
+/// let encoded = huffman::encode_tokens(&ids, &tree).unwrap();
+/// let decoded = huffman::decode_tokens(&encoded, &tree).unwrap();
+///
+/// let words: Vec<_> = decoded.iter().map(|&id| vocabulary[id as usize]).collect();
+/// assert_eq!(words.join(" "), text);
+/// ```
+pub fn encode_tokens(ids: &[u8], tree: &HuffmanNode) -> Result<Vec<u8>, EncodeError> {
+    tree.serialize_payload(ids)
+}
+
+/// Decodes the token IDs written by [`encode_tokens`] with the same `tree`.
+pub fn decode_tokens(encoded: &[u8], tree: &HuffmanNode) -> Result<Vec<u8>, DecodeError> {
+    tree.decode_payload(encoded)
+}
+
+/// Decodes `len` symbols from a payload-only stream: packed codes with no
+/// length prefix or tree, for callers that store the tree elsewhere.
+pub fn decode_with_tree(
+    tree: &HuffmanNode,
+    payload: &[u8],
+    len: usize,
+) -> Result<Vec<u8>, DecodeError> {
+    tree.decode_items(&mut BitReader::new(payload), len as u64)
+}
+
+/// Decodes the first `bit_len` bits of a payload-only stream, for framings
+/// that track the bit length rather than a symbol count. Bits after
+/// `bit_len`, such as padding, are never read as symbols. A tree that is a
+/// lone leaf has no codes to read, so it is [`DecodeError::InvalidTree`].
+pub fn decode_with_bit_len(
+    tree: &HuffmanNode,
+    payload: &[u8],
+    bit_len: usize,
+) -> Result<Vec<u8>, DecodeError> {
+    // each symbol would take no bits, and so never reach `bit_len`
+    if matches!(tree, HuffmanNode::Leaf { .. }) {
+        return Err(DecodeError::InvalidTree);
+    }
+    if bit_len > payload.len() * 8 {
+        return Err(DecodeError::TruncatedPayload);
+    }
+
+    let mut bits = BitReader::new(payload);
+    let end = payload.len() * 8 - bit_len;
+    let mut items = Vec::new();
+    while bits.remaining_bits() > end {
+        items.push(tree.decode_item(&mut bits)?);
+    }
+
+    // the last code ran on past the end
+    if bits.remaining_bits() < end {
+        return Err(DecodeError::TruncatedPayload);
+    }
+    Ok(items)
+}
+
+/// Magic bytes that start a standalone tree file.
+pub const TREE_MAGIC: &[u8; 4] = b"HTRE";
+
+/// Version of the tree file layout written by [`write_tree`].
+pub const TREE_FILE_VERSION: u8 = 1;
+
+/// Writes `tree` on its own, for sharing one tree between many payloads:
+/// [`TREE_MAGIC`], a [`TREE_FILE_VERSION`] byte, and the tree as written by
+/// [`HuffmanNode::serialize_tree_bytes`].
+pub fn write_tree<W: Write>(tree: &HuffmanNode, mut w: W) -> io::Result<()> {
+    w.write_all(TREE_MAGIC)?;
+    w.write_all(&[TREE_FILE_VERSION])?;
+    w.write_all(&tree.serialize_tree_bytes())
+}
+
+/// Reads a tree file written by [`write_tree`].
+pub fn read_tree<R: Read>(mut r: R) -> Result<HuffmanNode, DecodeError> {
+    let mut input = Vec::new();
+    r.read_to_end(&mut input)?;
+
+    let (magic, input) = input
+        .split_first_chunk::<4>()
+        .ok_or(DecodeError::TruncatedHeader)?;
+    if magic != TREE_MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    let (&version, input) = input.split_first().ok_or(DecodeError::TruncatedHeader)?;
+    if version != TREE_FILE_VERSION {
+        return Err(DecodeError::UnsupportedVersion {
+            found: version,
+            supported: TREE_FILE_VERSION..=TREE_FILE_VERSION,
+        });
+    }
+
+    HuffmanNode::decode_tree_bytes(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_table::CodeTable;
+    use crate::test_util::{FLIP_SEED, flip_random_bits, tree_from_codes};
+
+    #[test]
+    fn crafted_tree_round_trips() {
+        let tree = tree_from_codes(&[(b'a', "0"), (b'b', "10"), (b'c', "11")]);
+
+        let (decoded_tree, output) =
+            HuffmanNode::decode(&tree.serialize(b"abcab").unwrap()).unwrap();
+
+        assert_eq!(decoded_tree, tree);
+        assert_eq!(output, b"abcab");
+    }
+
+    #[test]
+    fn decodes_hand_written_payload() {
+        let tree = tree_from_codes(&[(b'a', "0"), (b'b', "10"), (b'c', "11")]);
+
+        // the symbol count, (len, symbol) pairs in traversal order, the
+        // terminating 0, then "a a b c" as 0 0 10 11 padded to a byte
+        let input = [
+            4,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            1,
+            b'a',
+            2,
+            b'b',
+            2,
+            b'c',
+            0,
+            0b0010_1100,
+        ];
+        let (decoded_tree, output) = HuffmanNode::decode(&input).unwrap();
+
+        assert_eq!(decoded_tree, tree);
+        assert_eq!(output, b"aabc");
+    }
+
+    #[test]
+    fn length_prefix_is_little_endian_u64() {
+        let input = b"ab".repeat(129);
+        let tree = HuffmanNode::build_tree(&input);
+
+        let encoded = tree.serialize(&input).unwrap();
+
+        assert_eq!(encoded[..8], [0x02, 0x01, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(HuffmanNode::decode(&encoded).unwrap().1, input);
+    }
+
+    #[test]
+    fn average_code_length_of_skewed_distribution() {
+        let mut freqs = [0; 256];
+        for (val, count) in [(b'a', 8), (b'b', 4), (b'c', 2), (b'd', 1), (b'e', 1)] {
+            freqs[val as usize] = count;
+        }
+        let input: Vec<_> = (0..=255u8)
+            .flat_map(|val| std::iter::repeat_n(val, freqs[val as usize]))
+            .collect();
+        let tree = HuffmanNode::build_tree(&input);
+
+        // a: 1 bit, b: 2 bits, c: 3 bits, d and e: 4 bits, so
+        // (8 + 4 * 2 + 2 * 3 + 4 + 4) / 16
+        assert_eq!(tree.average_code_length(&freqs), 1.875);
+    }
+
+    #[test]
+    fn built_trees_use_canonical_codes() {
+        let input = b"it was the best of times, it was the worst of times";
+        let tree = HuffmanNode::build_tree(input);
+        let table = CodeTable::new(&tree);
+
+        for &symbol in input {
+            let code: String = table
+                .encode_symbol(symbol)
+                .unwrap()
+                .bits()
+                .map(|bit| if bit { '1' } else { '0' })
+                .collect();
+            assert_eq!(tree.encode(symbol).unwrap(), code);
+        }
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+    fn bench_code_table_against_tree_walk() {
+        let input = include_bytes!("../nyi.txt").repeat(100);
+        let tree = HuffmanNode::build_tree(&input);
+        let table = CodeTable::new(&tree);
+
+        let start = std::time::Instant::now();
+        let searched: usize = input.iter().map(|&n| tree.encode(n).unwrap().len()).sum();
+        let search_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let looked_up: usize = input
+            .iter()
+            .map(|&n| table.encode_symbol(n).unwrap().bits().count())
+            .sum();
+        let lookup_time = start.elapsed();
+        assert_eq!(searched, looked_up);
+
+        let encoded = tree.serialize(&input).unwrap();
+        let (_, decoded) = HuffmanNode::decode(&encoded).unwrap();
+        assert_eq!(decoded, input);
+
+        let (_, payload) = HuffmanNode::decode_tree(&encoded[LENGTH_PREFIX_BYTES..]).unwrap();
+
+        let start = std::time::Instant::now();
+        let mut rest = BitReader::new(payload);
+        for _ in 0..input.len() {
+            tree.decode_item(&mut rest).unwrap();
+        }
+        let walk_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let mut rest = BitReader::new(payload);
+        for _ in 0..input.len() {
+            table.decode_symbol(&mut rest).unwrap();
+        }
+        let table_decode_time = start.elapsed();
+
+        println!("encode: tree search {search_time:?}, code table {lookup_time:?}");
+        println!("decode: tree walk {walk_time:?}, code table {table_decode_time:?}");
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+    fn bench_in_order_traversal() {
+        // the string-building traversal this replaced
+        fn string_traversal(node: &HuffmanNode, pre: String) -> Vec<(u8, String)> {
+            match node {
+                HuffmanNode::Leaf { val, .. } => vec![(*val, pre)],
+                HuffmanNode::Node { left, right } => {
+                    let left = string_traversal(left, pre.clone() + "0");
+                    let right = string_traversal(right, pre + "1");
+                    [left, right].concat()
+                }
+            }
+        }
+
+        let input: Vec<_> = (0..=255u8)
+            .flat_map(|val| std::iter::repeat_n(val, 1 + val as usize % 17))
+            .collect();
+        let tree = HuffmanNode::build_tree(&input);
+        assert_eq!(tree.codes().len(), 256);
+
+        let start = std::time::Instant::now();
+        for _ in 0..1000 {
+            std::hint::black_box(string_traversal(&tree, "".into()));
+        }
+        let string_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for _ in 0..1000 {
+            std::hint::black_box(tree.codes());
+        }
+        let backtracking_time = start.elapsed();
+
+        println!("256 leaves x1000: strings {string_time:?}, backtracking {backtracking_time:?}");
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+    fn bench_serialize_against_tree_search() {
+        let input = include_bytes!("../nyi.txt").repeat(100);
+        let tree = HuffmanNode::build_tree(&input);
+
+        // what `serialize` did before: one traversal for the header and one
+        // search of the tree per symbol
+        let start = std::time::Instant::now();
+        let searched: String = input.iter().map(|&n| tree.encode(n).unwrap()).collect();
+        let before = [
+            &(input.len() as u64).to_le_bytes()[..],
+            &pack_bits(&tree.serialize_tree()),
+            &pack_bits(&searched),
+        ]
+        .concat();
+        let search_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let after = tree.serialize(&input).unwrap();
+        let single_walk_time = start.elapsed();
+        assert_eq!(before, after);
+
+        println!(
+            "{} bytes: {} tree walks {search_time:?}, 1 tree walk {single_walk_time:?}",
+            input.len(),
+            input.len() + 1
+        );
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+    fn bench_string_serialize_against_packed_bits() {
+        // the string-concatenating `serialize` this replaced, which spelled
+        // out every bit as a `'0'` or `'1'` before packing them
+        fn string_serialize(tree: &HuffmanNode, input: &[u8]) -> (Vec<u8>, usize) {
+            let codes = tree.code_map();
+            let bits = input.iter().fold(String::new(), |bits, n| bits + &codes[n]);
+            let serialized = [
+                &(input.len() as u64).to_le_bytes()[..],
+                &pack_bits(&tree.serialize_tree()),
+                &pack_bits(&bits),
+            ]
+            .concat();
+            (serialized, bits.len())
+        }
+
+        let text = include_bytes!("../nyi.txt");
+        let input = text.repeat((5 << 20) / text.len() + 1);
+        let tree = HuffmanNode::build_tree(&input);
+
+        let start = std::time::Instant::now();
+        let (before, bit_string_len) = string_serialize(&tree, &input);
+        let string_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let after = tree.serialize(&input).unwrap();
+        let packed_time = start.elapsed();
+        assert_eq!(before, after);
+
+        // the bit string is the largest buffer either of them holds
+        println!(
+            "{} bytes: strings {string_time:?} with a {bit_string_len}-byte bit string, \
+             packed bits {packed_time:?} with a {}-byte output",
+            input.len(),
+            after.len()
+        );
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+    fn bench_prefix_table_against_tree_walk() {
+        let input = include_bytes!("../nyi.txt").repeat(100);
+        let tree = HuffmanNode::build_tree(&input);
+        let encoded = tree.serialize_payload(&input).unwrap();
+        let payload = &encoded[LENGTH_PREFIX_BYTES..];
+
+        let start = std::time::Instant::now();
+        let mut bits = BitReader::new(payload);
+        let walked: Vec<_> = (0..input.len())
+            .map(|_| tree.decode_item(&mut bits).unwrap())
+            .collect();
+        let walk_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let looked_up = tree
+            .decode_items(&mut BitReader::new(payload), input.len() as u64)
+            .unwrap();
+        let table_time = start.elapsed();
+
+        assert_eq!(walked, input);
+        assert_eq!(looked_up, input);
+        println!("decode: tree walk {walk_time:?}, prefix table {table_time:?}");
+    }
+
+    #[test]
+    fn decodes_tree_and_payload_stored_apart() {
+        let input = b"the tree goes in one place and the payload in another";
+        let tree = HuffmanNode::build_tree(input);
+
+        let tree_bytes = tree.serialize_tree_bytes();
+        let payload = tree.serialize_payload(input).unwrap();
+        assert_eq!(
+            tree_bytes.len() + payload.len(),
+            tree.serialize(input).unwrap().len()
+        );
+
+        let decoded_tree = HuffmanNode::decode_tree_bytes(&tree_bytes).unwrap();
+        assert_eq!(decoded_tree, tree);
+        assert_eq!(decoded_tree.decode_payload(&payload).unwrap(), input);
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let input = b"abcabcaab";
+        let encoded = HuffmanNode::build_tree(input).serialize(input).unwrap();
+
+        // inside the length prefix, between a code's length and its
+        // symbol, and just before the terminating 0
+        for len in [3, LENGTH_PREFIX_BYTES + 3, LENGTH_PREFIX_BYTES + 6] {
+            assert_eq!(
+                HuffmanNode::decode(&encoded[..len]),
+                Err(DecodeError::TruncatedHeader)
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_payload_with_out_of_band_tree() {
+        let input = b"the receiver already has the tree";
+        let tree = HuffmanNode::build_tree(input);
+        let payload = tree.serialize_payload(input).unwrap();
+        let codes = &payload[LENGTH_PREFIX_BYTES..];
+
+        assert_eq!(decode_with_tree(&tree, codes, input.len()).unwrap(), input);
+        assert_eq!(
+            decode_with_tree(&tree, codes, input.len() + 10),
+            Err(DecodeError::TruncatedPayload)
+        );
+    }
+
+    #[test]
+    fn rejects_a_symbol_count_the_payload_cannot_hold() {
+        let input = b"abracadabra";
+        let mut encoded = HuffmanNode::build_tree(input).serialize(input).unwrap();
+        encoded[..LENGTH_PREFIX_BYTES].copy_from_slice(&(1u64 << 40).to_le_bytes());
+
+        assert_eq!(
+            HuffmanNode::decode(&encoded).map(|(_, items)| items),
+            Err(DecodeError::ImplausibleLength)
+        );
+    }
+
+    #[test]
+    fn decodes_an_explicit_bit_length() {
+        let tree = tree_from_codes(&[(b'a', "0"), (b'b', "10"), (b'c', "11")]);
+        // `aaaab` is 6 bits, so the two padding bits would read as `aa`
+        let payload = [0b0000_1000];
+
+        assert_eq!(decode_with_bit_len(&tree, &payload, 6).unwrap(), b"aaaab");
+        assert_eq!(decode_with_bit_len(&tree, &payload, 8).unwrap(), b"aaaabaa");
+        assert_eq!(decode_with_bit_len(&tree, &payload, 0).unwrap(), b"");
+
+        // stopping in the middle of `b`'s code
+        assert_eq!(
+            decode_with_bit_len(&tree, &payload, 5),
+            Err(DecodeError::TruncatedPayload)
+        );
+        assert_eq!(
+            decode_with_bit_len(&tree, &payload, 9),
+            Err(DecodeError::TruncatedPayload)
+        );
+    }
+
+    #[test]
+    fn a_bit_length_with_a_lone_leaf_is_an_invalid_tree() {
+        let tree = HuffmanNode::Leaf {
+            val: b'a',
+            count: 1,
+        };
+
+        assert_eq!(
+            decode_with_bit_len(&tree, &[0], 8),
+            Err(DecodeError::InvalidTree)
+        );
+    }
+
+    #[test]
+    fn equal_frequencies_build_a_balanced_tree() {
+        let counts = [1000; 256];
+
+        let tree = HuffmanNode::build_tree_from_counts(&counts).unwrap();
+
+        assert_eq!(tree.code_lengths(), [8; 256]);
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+    fn bench_build_tree_with_ties() {
+        for symbols in [16, 32, 64, 128, 256] {
+            let mut counts = [0; 256];
+            counts[..symbols].fill(1000);
+
+            let start = std::time::Instant::now();
+            for _ in 0..100 {
+                std::hint::black_box(HuffmanNode::build_tree_from_counts(&counts));
+            }
+            println!(
+                "{symbols:>3} equal symbols: {:?} per tree",
+                start.elapsed() / 100
+            );
+        }
+    }
+
+    #[test]
+    fn counts_and_bytes_build_the_same_tree() {
+        let input = include_bytes!("../nyi.txt");
+        let from_bytes = HuffmanNode::build_tree(input);
+        let from_counts = HuffmanNode::build_tree_from_counts(&count_frequencies(*input)).unwrap();
+
+        assert_eq!(from_bytes, from_counts);
+        assert_eq!(from_bytes.get_usage(), from_counts.get_usage());
+    }
+
+    #[test]
+    fn skewed_counts_make_a_skewed_tree() {
+        let uniform: Vec<u8> = (0..64).cycle().take(64 * 10).collect();
+        let uniform = HuffmanNode::build_tree(&uniform);
+        assert_eq!(uniform.leaf_count(), 64);
+        assert_eq!(uniform.skew(), 1.0);
+
+        // counts doubling from one symbol to the next give a code of every
+        // length from 1 to 15
+        let mut skewed = Vec::new();
+        for symbol in 0..16 {
+            skewed.extend(std::iter::repeat_n(symbol, 1 << symbol));
+        }
+        let skewed = HuffmanNode::build_tree(&skewed);
+        assert_eq!(skewed.get_depth() - 1, 15);
+        assert_eq!(skewed.skew(), 15.0 / 4.0);
+    }
+
+    #[test]
+    fn builds_trees_from_counts_too_large_to_sum() {
+        // a lone symbol with more occurrences than any input could hold
+        let mut counts = [0; 256];
+        counts[b'a' as usize] = usize::MAX / 2 + 1;
+        let tree = HuffmanNode::build_tree_from_counts(&counts).unwrap();
+        assert_eq!(tree.code_lengths()[b'a' as usize], 1);
+        assert_eq!(tree.get_usage(), usize::MAX / 2 + 1);
+
+        // adding these up overflows, so the weights saturate instead
+        counts[b'b' as usize] = usize::MAX / 2 + 1;
+        counts[b'c' as usize] = usize::MAX;
+        counts[b'd' as usize] = 1;
+        let tree = HuffmanNode::build_tree_from_counts(&counts).unwrap();
+        assert_eq!(tree.get_usage(), usize::MAX);
+        // `c` still gets the shortest code
+        let lengths = tree.code_lengths();
+        assert_eq!(
+            [b'a', b'b', b'c', b'd'].map(|b| lengths[b as usize]),
+            [2, 3, 1, 3]
+        );
+        assert_eq!(
+            tree.serialize(b"abcd")
+                .map(|encoded| HuffmanNode::decode(&encoded).unwrap().1),
+            Ok(b"abcd".to_vec())
+        );
+    }
+
+    #[test]
+    fn builds_tree_from_byte_iter() {
+        let tree = HuffmanNode::from_byte_iter("hello".bytes()).unwrap();
+
+        assert_eq!(tree, HuffmanNode::build_tree(b"hello"));
+        assert_eq!(tree.get_usage(), 5);
+        assert_eq!(HuffmanNode::from_byte_iter(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn serialized_len_matches_serialize() {
+        let nyi = include_bytes!("../nyi.txt");
+        let inputs: [&[u8]; 5] = [nyi, b"aaaaaaab", b"aaaaaaaab", b"zzzz", b"abracadabra"];
+
+        for input in inputs {
+            let tree = HuffmanNode::build_tree(input);
+            assert_eq!(
+                tree.serialize(input).unwrap().len(),
+                tree.serialized_len(input)
+            );
+        }
+
+        let tree = HuffmanNode::build_tree(nyi);
+        assert_eq!(tree.serialize(b"").unwrap().len(), tree.serialized_len(b""));
+    }
+
+    #[test]
+    fn tree_header_len_matches_serialize_tree_bytes() {
+        let nyi = include_bytes!("../nyi.txt");
+        let every_byte: Vec<u8> = (0..=u8::MAX).collect();
+        let inputs: [&[u8]; 4] = [nyi, &every_byte, b"ab", b"abracadabra"];
+
+        for input in inputs {
+            let tree = HuffmanNode::build_tree(input);
+            assert_eq!(tree.tree_header_len(), tree.serialize_tree_bytes().len());
+        }
+    }
+
+    #[test]
+    fn the_deepest_trees_fit_on_a_small_stack() {
+        // codes of every length from 1 to 255, the last two sharing one
+        let mut lengths = [0; 256];
+        for (symbol, len) in lengths.iter_mut().enumerate() {
+            *len = (symbol + 1).min(MAX_CODE_LEN);
+        }
+
+        let deepest = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                let tree = HuffmanNode::from_code_lengths(&lengths).unwrap();
+                assert_eq!(tree.get_depth(), MAX_CODE_LEN + 1);
+                assert_eq!(tree.code_lengths(), lengths);
+                assert_eq!(tree.depth_of(255), Some(MAX_CODE_LEN));
+
+                let input: Vec<u8> = (0..=u8::MAX).rev().collect();
+                let encoded = tree.serialize(&input).unwrap();
+                let (decoded, output) = HuffmanNode::decode(&encoded).unwrap();
+                assert_eq!((decoded.fingerprint(), output), (tree.fingerprint(), input));
+                drop(tree.clone());
+            });
+        deepest.unwrap().join().unwrap();
+
+        // a length the recursion would never come back from
+        lengths[0] = usize::MAX;
+        assert_eq!(HuffmanNode::from_code_lengths(&lengths), None);
+    }
+
+    #[test]
+    fn decodes_codes_longer_than_the_prefix_table() {
+        // Fibonacci counts give the deepest tree for their number of symbols
+        let mut counts = [0; 256];
+        let (mut a, mut b) = (1, 1);
+        for count in &mut counts[..30] {
+            *count = a;
+            (a, b) = (b, a + b);
+        }
+        let tree = HuffmanNode::build_tree_from_counts(&counts).unwrap();
+        assert!(tree.get_depth() > 2 * crate::code_table::MAX_PREFIX_BITS);
+
+        let input: Vec<u8> = (0..30).rev().chain(0..30).collect();
+        let encoded = tree.serialize(&input).unwrap();
+        assert_eq!(HuffmanNode::decode(&encoded).unwrap(), (tree, input));
+    }
+
+    #[test]
+    fn decode_stats_count_bits_and_symbols() {
+        let input = b"aaaabbc";
+        let tree = HuffmanNode::build_tree(input);
+        let encoded = tree.serialize(input).unwrap();
+
+        let (decoded, items, stats) = HuffmanNode::decode_with_stats(&encoded).unwrap();
+        assert_eq!(decoded, tree);
+        assert_eq!(items, input);
+        // a: 1 bit, b and c: 2 bits
+        assert_eq!(
+            stats,
+            DecodeStats {
+                bits: 4 + 2 * 2 + 2,
+                symbols: 7,
+                max_code_len: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn length_table_rebuilds_the_same_tree() {
+        let nyi = include_bytes!("../nyi.txt");
+        let inputs: [&[u8]; 4] = [nyi, b"zzzz", b"abracadabra", &[0, 255, 255]];
+
+        for input in inputs {
+            let tree = HuffmanNode::build_tree(input);
+            let stream = tree
+                .serialize_with_layout(input, TreeLayout::LengthTable)
+                .unwrap();
+
+            let (decoded, items, rest) =
+                HuffmanNode::decode_with_rest(&stream, TreeLayout::LengthTable).unwrap();
+            assert_eq!(decoded, tree);
+            assert_eq!(items, input);
+            assert!(rest.is_empty());
+        }
+    }
+
+    #[test]
+    fn length_table_rejects_long_codes_and_empty_tables() {
+        let mut lengths = [0; 256];
+        for (symbol, len) in lengths.iter_mut().zip(1..=32) {
+            *symbol = len;
+        }
+        lengths[32] = 32;
+        let tree = HuffmanNode::from_code_lengths(&lengths).unwrap();
+
+        assert_eq!(tree.length_table(), None);
+        assert_eq!(
+            tree.serialize_with_layout(b"a", TreeLayout::LengthTable),
+            Err(EncodeError::CodeTooLong)
+        );
+
+        let no_codes = [&1u64.to_le_bytes()[..], &[0; LENGTH_TABLE_BYTES]].concat();
+        assert_eq!(
+            HuffmanNode::peek_tree_with_layout(&no_codes, TreeLayout::LengthTable),
+            Err(DecodeError::EmptyTree)
+        );
+    }
+
+    #[test]
+    fn bit_string_spells_out_the_packed_stream() {
+        let input = b"abracadabra";
+        let tree = HuffmanNode::build_tree(input);
+        let bits = tree.bit_string(input).unwrap();
+
+        let packed: String = tree.serialize_payload(input).unwrap()[LENGTH_PREFIX_BYTES..]
+            .iter()
+            .map(|byte| format!("{byte:08b}"))
+            .collect();
+        let tree_bits: String = tree
+            .serialize_tree_bytes()
+            .iter()
+            .map(|byte| format!("{byte:08b}"))
+            .collect();
+        let (tree_line, payload_line) = bits.split_once('\n').unwrap();
+
+        assert_eq!(tree_line, format!("tree:    {tree_bits}"));
+        let payload = payload_line.strip_prefix("payload: ").unwrap().trim_end();
+        assert!(packed.starts_with(payload));
+        assert_eq!(payload.len().div_ceil(8), packed.len() / 8);
+
+        assert_eq!(
+            tree.bit_string(b"xyz"),
+            Err(EncodeError::MissingSymbol(b'x'))
+        );
+    }
+
+    #[test]
+    fn depth_of_is_the_code_length() {
+        let tree = tree_from_codes(&[(b'a', "0"), (b'b', "10"), (b'c', "110"), (b'd', "111")]);
+
+        assert_eq!(tree.depth_of(b'a'), Some(1));
+        assert_eq!(tree.depth_of(b'b'), Some(2));
+        assert_eq!(tree.depth_of(b'c'), Some(3));
+        assert_eq!(tree.depth_of(b'd'), Some(3));
+        assert_eq!(tree.depth_of(b'e'), None);
+    }
+
+    #[test]
+    fn fingerprints_follow_equality() {
+        let tree = HuffmanNode::build_tree(b"aaaabbc");
+        let rescaled = HuffmanNode::build_tree(b"aaaaaaaabbbbcc");
+        let other = HuffmanNode::build_tree(b"abbbbcc");
+
+        assert_eq!(tree, rescaled);
+        assert_eq!(tree.fingerprint(), rescaled.fingerprint());
+        assert_ne!(tree, other);
+        assert_ne!(tree.fingerprint(), other.fingerprint());
+
+        // pinned, so a change to the hash or the layout shows up
+        assert_eq!(tree.fingerprint(), 0xb957_7f95_0c30_df2c);
+    }
+
+    #[test]
+    fn pads_partial_final_byte() {
+        let tree = tree_from_codes(&[(b'a', "0"), (b'b', "1")]);
+        // the symbol count, two (len, symbol) pairs and the terminating 0
+        let header_len = 13;
+
+        let seven = tree.serialize(b"aaaaaab").unwrap();
+        assert_eq!(seven.len(), header_len + 1);
+        assert_eq!(seven[header_len..], [0b0000_0010]);
+
+        let eight = tree.serialize(b"aaaaaaab").unwrap();
+        assert_eq!(eight.len(), header_len + 1);
+        assert_eq!(eight[header_len..], [0b0000_0001]);
+
+        let nine = tree.serialize(b"aaaaaaaab").unwrap();
+        assert_eq!(nine.len(), header_len + 2);
+        assert_eq!(nine[header_len..], [0b0000_0000, 0b1000_0000]);
+    }
+
+    #[test]
+    fn ignores_trailing_garbage() {
+        let input = b"the quick brown fox";
+        let tree = HuffmanNode::build_tree(input);
+        let mut encoded = tree.serialize(input).unwrap();
+        encoded.extend_from_slice(b"\xff\x00 appended junk \xff");
+
+        let (_, output) = HuffmanNode::decode(&encoded).unwrap();
+
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn packs_the_same_bits_as_strings_did() {
+        let input = include_bytes!("../nyi.txt");
+        // anything but lowercase letters has to be escaped
+        let letters: Vec<_> = input
+            .iter()
+            .copied()
+            .filter(u8::is_ascii_lowercase)
+            .collect();
+        let tree = HuffmanNode::build_tree(&letters);
+        let codes = tree.code_map();
+
+        // the `'0'`/`'1'` strings the escaped stream and the length table
+        // used to be spelled out as before packing
+        let escape = b'e';
+        let bits: String = input
+            .iter()
+            .map(|n| match codes.get(n) {
+                Some(code) if *n != escape => code.clone(),
+                _ => format!("{}{n:08b}", codes[&escape]),
+            })
+            .collect();
+        let escaped = tree.serialize_escaped(input, escape).unwrap();
+        assert!(escaped.ends_with(&pack_bits(&bits)));
+
+        let lengths = tree.code_lengths();
+        let bits: String = lengths.iter().map(|len| format!("{len:05b}")).collect();
+        assert_eq!(tree.length_table().unwrap(), pack_bits(&bits));
+    }
+
+    #[test]
+    fn a_single_distinct_byte_gets_a_one_bit_code() {
+        let input = vec![b'a'; 5000];
+        let tree = HuffmanNode::build_tree(&input);
+        assert_eq!(tree.code_lengths()[b'a' as usize], 1);
+
+        let serialized = tree.serialize(&input).unwrap();
+        // the length, two leaves, and 5000 bits of codes
+        assert_eq!(serialized.len(), 8 + 5 + 5000 / 8);
+        let (_, output) = HuffmanNode::decode(&serialized).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn control_and_high_bytes_round_trip() {
+        // the old format ended the payload at the first '\0', so make sure
+        // no byte value is special anymore
+        let mut input: Vec<u8> = (0..0x20).chain([0x7f, 0x80, 0xfe, 0xff]).collect();
+        input = input.repeat(5);
+        input.extend(b"\0\n\r\t\xff".repeat(3));
+        input.push(0);
+
+        let tree = HuffmanNode::build_tree(&input);
+        let (_, output) = HuffmanNode::decode(&tree.serialize(&input).unwrap()).unwrap();
+
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn rejects_headers_that_are_not_trees() {
+        let with_tree = |tree: &[u8]| [&[1, 0, 0, 0, 0, 0, 0, 0][..], tree, &[0]].concat();
+
+        // a missing sibling, one leaf too many, and a leaf shallower than
+        // the one before it
+        for tree in [
+            &[1, b'a'][..],
+            &[1, b'a', 1, b'b', 1, b'c'],
+            &[2, b'a', 1, b'b'],
+        ] {
+            assert_eq!(
+                HuffmanNode::decode(&with_tree(tree)),
+                Err(DecodeError::InvalidTree)
+            );
+        }
+        assert_eq!(HuffmanNode::from_code_lengths(&[3; 256]), None);
+    }
+
+    #[test]
+    fn rejects_headers_with_a_symbol_twice() {
+        // a complete tree, but with two codes for `a`
+        let forged = [&[2, 0, 0, 0, 0, 0, 0, 0][..], &[1, b'a', 1, b'a', 0, 0]].concat();
+
+        assert_eq!(
+            HuffmanNode::decode(&forged),
+            Err(DecodeError::DuplicateSymbol(b'a'))
+        );
+    }
+
+    #[test]
+    fn weighted_nodes_pop_lightest_then_latest_first() {
+        let leaf = |val, count| HuffmanNode::Leaf { val, count };
+        let mut heap = BinaryHeap::from([
+            WeightedNode::new(leaf(b'a', 5), 0),
+            WeightedNode::new(leaf(b'b', 2), 1),
+            WeightedNode::new(leaf(b'c', 2), 2),
+            WeightedNode::new(
+                HuffmanNode::Node {
+                    left: Box::new(leaf(b'd', 1)),
+                    right: Box::new(leaf(b'e', 1)),
+                },
+                3,
+            ),
+        ]);
+
+        let popped: Vec<_> = std::iter::from_fn(|| heap.pop())
+            .map(|node| (node.weight, node.order))
+            .collect();
+        assert_eq!(popped, [(2, 3), (2, 2), (2, 1), (5, 0)]);
+    }
+
+    #[test]
+    fn heap_builds_the_trees_sorting_did() {
+        // the build this replaced, which re-sorted the trees before every merge
+        fn sorting_build(counts: &[usize; 256]) -> HuffmanNode {
+            let mut trees: Vec<_> = (0..=255u8)
+                .filter(|&val| counts[val as usize] > 0)
+                .map(|val| HuffmanNode::Leaf {
+                    val,
+                    count: counts[val as usize],
+                })
+                .collect();
+            while trees.len() > 1 {
+                trees.sort_by_key(|tree| std::cmp::Reverse(tree.get_usage()));
+                let a = trees.pop().unwrap();
+                let b = trees.pop().unwrap();
+                trees.push(HuffmanNode::Node {
+                    left: Box::new(a),
+                    right: Box::new(b),
+                });
+            }
+            trees.pop().unwrap().into_canonical()
+        }
+
+        // small counts, so there are plenty of ties
+        let mut state = 7_u32;
+        for _ in 0..50 {
+            let mut counts = [0; 256];
+            for count in &mut counts {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                *count = (state >> 16) as usize % 6;
+            }
+            counts[0] = 1;
+            counts[1] = 1;
+
+            let built = HuffmanNode::build_tree_from_counts(&counts).unwrap();
+            assert!(built == sorting_build(&counts));
+        }
+    }
+
+    #[test]
+    fn rejects_a_tree_with_no_codes() {
+        let no_codes = [&1u64.to_le_bytes()[..], &[0, 0xff]].concat();
+
+        assert_eq!(HuffmanNode::decode(&no_codes), Err(DecodeError::EmptyTree));
+        assert_eq!(
+            HuffmanNode::peek_tree(&no_codes),
+            Err(DecodeError::EmptyTree)
+        );
+    }
+
+    #[test]
+    fn rejects_symbols_missing_from_the_tree() {
+        let tree = HuffmanNode::build_tree(b"abc");
+
+        assert_eq!(
+            tree.serialize(b"abcd"),
+            Err(EncodeError::MissingSymbol(b'd'))
+        );
+    }
+
+    #[test]
+    fn never_panics_on_corrupt_input() {
+        let input = b"the quick brown fox jumps over the lazy dog";
+        let encoded = HuffmanNode::build_tree(input).serialize(input).unwrap();
+
+        for len in 0..encoded.len() {
+            let _ = HuffmanNode::decode(&encoded[..len]);
+        }
+
+        // flip bits all over the stream, including the length prefix
+        let mut state = FLIP_SEED;
+        for _ in 0..10_000 {
+            let mut corrupt = encoded.clone();
+            flip_random_bits(&mut state, &mut corrupt, 3);
+            let _ = HuffmanNode::decode(&corrupt);
+        }
+    }
+
+    #[test]
+    fn tree_file_round_trips() {
+        let tree = HuffmanNode::build_tree(b"tree files hold only the tree");
+
+        let mut file = io::Cursor::new(Vec::new());
+        write_tree(&tree, &mut file).unwrap();
+        assert!(file.get_ref().starts_with(b"HTRE\x01"));
+
+        file.set_position(0);
+        assert_eq!(read_tree(file).unwrap(), tree);
+    }
+
+    #[test]
+    fn rejects_foreign_tree_files() {
+        let mut file = Vec::new();
+        write_tree(&HuffmanNode::build_tree(b"abc"), &mut file).unwrap();
+
+        let mut wrong_magic = file.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(read_tree(&wrong_magic[..]), Err(DecodeError::BadMagic));
+
+        let mut wrong_version = file.clone();
+        wrong_version[4] = 9;
+        assert_eq!(
+            read_tree(&wrong_version[..]),
+            Err(DecodeError::UnsupportedVersion {
+                found: 9,
+                supported: 1..=1
+            })
+        );
+
+        assert_eq!(read_tree(&file[..3]), Err(DecodeError::TruncatedHeader));
+    }
+
+    #[test]
+    fn io_errors_surface_as_decode_errors() {
+        struct BrokenPipe;
+        impl Read for BrokenPipe {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+        }
+        impl Write for BrokenPipe {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // `?` turns the writer's error into a `DecodeError` too
+        fn copy_tree(from: impl Read, to: impl Write) -> Result<(), DecodeError> {
+            write_tree(&read_tree(from)?, to)?;
+            Ok(())
+        }
+        let mut file = Vec::new();
+        write_tree(&HuffmanNode::build_tree(b"abc"), &mut file).unwrap();
+
+        let err = copy_tree(BrokenPipe, Vec::new()).unwrap_err();
+        assert_eq!(err, DecodeError::Io(io::ErrorKind::BrokenPipe));
+        assert_eq!(err.to_string(), "I/O error: broken pipe");
+        assert_eq!(
+            copy_tree(&file[..], BrokenPipe),
+            Err(DecodeError::Io(io::ErrorKind::BrokenPipe))
+        );
+    }
+
+    #[test]
+    fn entropy_of_uniform_and_skewed_bytes() {
+        let uniform = count_frequencies(*b"abcd");
+        let skewed = count_frequencies(*b"aaaaaaab");
+
+        assert_eq!(entropy(&uniform), 2.0);
+        assert!(
+            entropy(&skewed) < HuffmanNode::build_tree(b"aaaaaaab").average_code_length(&skewed)
+        );
+        assert_eq!(entropy(&[0; 256]), 0.0);
+    }
+
+    #[test]
+    fn counts_codes_of_each_length() {
+        let tree = tree_from_codes(&[(b'a', "0"), (b'b', "10"), (b'c', "110"), (b'd', "111")]);
+
+        assert_eq!(tree.code_length_histogram(), [0, 1, 1, 2]);
     }
 }
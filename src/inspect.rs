@@ -0,0 +1,111 @@
+//! A human-readable summary of a compressed file, for `--inspect`.
+
+use std::fmt::Write;
+
+use huffman_encoding::context_model;
+use huffman_encoding::error::DecodeError;
+use huffman_encoding::format::{self, Mode};
+use huffman_encoding::huffman::{HuffmanNode, TreeLayout};
+
+/// Describes the header and tree of `input` without decoding the payload.
+pub fn inspect(input: &[u8]) -> Result<String, DecodeError> {
+    let (header, stream) = format::peek_header(input)?;
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "format version {}, {} checksum, {} mode",
+        header.version,
+        header.checksum.name(),
+        header.mode.name()
+    )
+    .unwrap();
+    if let Some(name) = header.name {
+        writeln!(out, "name: {name}").unwrap();
+    }
+    if let Some(mtime) = header.mtime {
+        writeln!(out, "modified: {mtime} seconds after the Unix epoch").unwrap();
+    }
+    if let Some(comment) = header.comment {
+        writeln!(out, "comment: {comment}").unwrap();
+    }
+    if header.flags.normalized_newlines {
+        writeln!(out, "newlines: normalized from CRLF").unwrap();
+    }
+    if header.flags.stripped_bom {
+        writeln!(out, "byte order mark: stripped").unwrap();
+    }
+    let (stream, layout) = match header.mode {
+        Mode::Huffman | Mode::Nibble | Mode::Lines => (stream, TreeLayout::Pairs),
+        Mode::LengthTable => (stream, TreeLayout::LengthTable),
+        Mode::Stored => return Ok(out),
+        Mode::Order1 => {
+            let (len, trees, _) = context_model::peek_trees(stream)?;
+            let contexts = trees.iter().flatten().count();
+            writeln!(out, "symbols: {len}, contexts with a tree: {contexts}").unwrap();
+            return Ok(out);
+        }
+        Mode::Escaped => {
+            let (&escape, stream) = stream.split_first().ok_or(DecodeError::TruncatedHeader)?;
+            writeln!(out, "escape: 0x{escape:02x}").unwrap();
+            (stream, TreeLayout::Pairs)
+        }
+    };
+
+    let (len, tree) = HuffmanNode::peek_tree_with_layout(stream, layout)?;
+    let mut codes: Vec<_> = tree.code_map().into_iter().collect();
+    codes.sort_by_key(|(symbol, code)| (code.len(), *symbol));
+
+    writeln!(out, "symbols: {len}, distinct: {}", codes.len()).unwrap();
+
+    writeln!(out, "code lengths:").unwrap();
+    for (len, &count) in tree.code_length_histogram().iter().enumerate() {
+        if count > 0 {
+            let plural = if count == 1 { "" } else { "s" };
+            writeln!(out, "  length {len}: {count} symbol{plural}").unwrap();
+        }
+    }
+
+    writeln!(out, "codes:").unwrap();
+    for (symbol, code) in codes {
+        let shown = shown(symbol);
+        writeln!(out, "  0x{symbol:02x} {shown:<6} {code}").unwrap();
+    }
+
+    Ok(out)
+}
+
+/// `symbol` as a quoted character, escaped unless it is printable ASCII.
+pub fn shown(symbol: u8) -> String {
+    if symbol.is_ascii_graphic() {
+        format!("'{}'", symbol as char)
+    } else {
+        format!("{:?}", symbol as char)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use huffman_encoding::checksum::Checksum;
+
+    #[test]
+    fn lists_code_lengths_and_codes() {
+        let encoded = format::encode(b"aaaaaaaabbbbcc\n", Checksum::Crc32).unwrap();
+
+        assert_eq!(
+            inspect(&encoded).unwrap(),
+            "format version 4, crc32 checksum, huffman mode\n\
+             symbols: 15, distinct: 4\n\
+             code lengths:\n  \
+               length 1: 1 symbol\n  \
+               length 2: 1 symbol\n  \
+               length 3: 2 symbols\n\
+             codes:\n  \
+               0x61 'a'    0\n  \
+               0x62 'b'    10\n  \
+               0x0a '\\n'   110\n  \
+               0x63 'c'    111\n"
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! A prompt that codes each line typed into it, for `--interactive`.
+
+use std::io::{self, BufRead, Write};
+
+use huffman_encoding::huffman::{self, HuffmanNode};
+
+/// Reads lines from `input` until it ends and writes the codes of a tree
+/// built from each one, its bits and how much smaller they are, to
+/// `output`. The prompt goes to `prompt`, so that `output` holds only the
+/// results.
+pub fn run(
+    input: impl BufRead,
+    output: &mut impl Write,
+    prompt: &mut impl Write,
+) -> io::Result<()> {
+    write!(prompt, "> ")?;
+    prompt.flush()?;
+    for line in input.lines() {
+        output.write_all(describe(line?.as_bytes()).as_bytes())?;
+        output.flush()?;
+        write!(prompt, "> ")?;
+        prompt.flush()?;
+    }
+    writeln!(prompt)
+}
+
+/// The codes for `line`, every bit of it coded with them, and how many bits
+/// that is against 8 per byte.
+fn describe(line: &[u8]) -> String {
+    let counts = huffman::count_frequencies(line.iter().copied());
+    let Some(tree) = HuffmanNode::build_tree_from_counts(&counts) else {
+        return "nothing to code in an empty line\n".into();
+    };
+    let mut codes: Vec<_> = tree.code_map().into_iter().collect();
+    codes.sort_by_key(|(symbol, code)| (code.len(), *symbol));
+
+    let mut out = String::from("codes:\n");
+    let mut bits = 0;
+    for (symbol, code) in codes {
+        let count = counts[symbol as usize];
+        if count == 0 {
+            // the unused partner of a lone symbol
+            continue;
+        }
+        bits += count * code.len();
+        let shown = format!("{:?}", symbol as char);
+        out += &format!("  {shown:<6} {code}\n");
+    }
+    out += &tree
+        .bit_string(line)
+        .expect("the tree has a code for every byte of the line");
+
+    let ratio = 100.0 * bits as f64 / (8 * line.len()) as f64;
+    out += &format!(
+        "{bits} bits instead of {}, {ratio:.1}%, not counting the tree\n",
+        8 * line.len()
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_each_line_until_the_input_ends() {
+        let mut output = Vec::new();
+        run(&b"aab\n\n"[..], &mut output, &mut io::sink()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "codes:\n  \
+               'a'    0\n  \
+               'b'    1\n\
+             tree:    0000000101100001000000010110001000000000\n\
+             payload: 001\n\
+             3 bits instead of 24, 12.5%, not counting the tree\n\
+             nothing to code in an empty line\n"
+        );
+    }
+}
@@ -0,0 +1,46 @@
+//! Huffman coding over bytes.
+//!
+//! [`compress`] and [`decompress`] cover the common case, writing and
+//! reading the same files as the command line tool. The modules below them
+//! give control over the format, the tree and the checksum.
+//!
+//! ```
+//! # fn main() -> Result<(), huffman_encoding::error::DecodeError> {
+//! let compressed = huffman_encoding::compress(b"hello");
+//! assert_eq!(huffman_encoding::decompress(&compressed)?, b"hello");
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod atomic_file;
+pub mod base64;
+pub mod checksum;
+pub mod code_table;
+pub mod context_model;
+pub mod error;
+pub mod files;
+pub mod format;
+pub mod huffman;
+pub mod models;
+pub mod stream;
+#[cfg(test)]
+mod test_util;
+pub mod text;
+pub mod workspace;
+
+use error::DecodeError;
+
+/// Compresses `input` into a file like the command line tool writes by
+/// default: Huffman coded with its own tree, or stored as it is if that
+/// wouldn't be smaller, with a CRC-32 of it in the header.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    format::encode(input, checksum::Checksum::default())
+        .expect("the input's own tree has a code for each of its bytes")
+}
+
+/// Decompresses a file from [`compress`], or any other file the command
+/// line tool wrote, checking it against its checksum. Damaged or foreign
+/// input is an error rather than a panic.
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    format::decode(input)
+}
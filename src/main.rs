@@ -1,89 +1,1865 @@
-use std::io::Write;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-mod huffman;
-use huffman::HuffmanNode;
+use huffman_encoding::atomic_file::AtomicFile;
+use huffman_encoding::checksum::Checksum;
+use huffman_encoding::error::{CompressError, DecodeError};
+use huffman_encoding::format::{self, DecodeOptions, EncodeOptions, Flags, Mode};
+use huffman_encoding::huffman::{self, HuffmanNode};
+use huffman_encoding::models;
+use huffman_encoding::stream;
+use huffman_encoding::text;
 
+mod benchmark;
+mod explain;
+mod hex_dump;
+mod inspect;
+mod interactive;
+mod member_map;
+mod progress;
+mod self_test;
+mod stages;
+mod stats;
+mod symbol_csv;
+// the library's test helpers, which only name items the binary imports too
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "test_util.rs"]
+mod test_util;
+mod tree_cache;
+mod tree_diff;
+mod version_info;
+use member_map::MemberSpan;
+use progress::Progress;
+use stages::Stages;
+use tree_cache::TreeCache;
+
+const USAGE: &str = "\
+usage: huffman_encoding [OPTIONS] [INPUT] (stdin if INPUT is - or missing)
+       huffman_encoding [OPTIONS] INPUT... (each to INPUT.huff)
+       huffman_encoding [OPTIONS] --append INPUT ARCHIVE
+       huffman_encoding [OPTIONS] --recompress INPUT
+       huffman_encoding --compare-trees FIRST SECOND
+       huffman_encoding --self-test
+
+options:
+  -d             decode INPUT instead of encoding it
+  --recompress   decode the compressed INPUT and encode it again with OPTIONS
+  --hex          with -d, write a hex dump of the decoded bytes
+  --normalize-newlines
+                 turn CRLF line endings into LF before encoding
+  --restore-newlines
+                 with -d, turn LF back into CRLF if it was normalized
+  --strip-bom    remove a leading UTF-8 byte order mark before encoding
+  --restore-bom  with -d, put the byte order mark back if it was removed
+  --recover      with -d, write what can be decoded of a damaged INPUT
+  --max-output BYTES
+                 with -d, fail instead of decoding more than BYTES bytes
+  --line N       with -d, write only line N of INPUT, counting from 1
+  --map-file FILE
+                 with -d, write where each member of INPUT starts and ends,
+                 compressed and decoded, to FILE
+  -o OUTPUT      write to OUTPUT instead of stdout, which - also means
+  --split SIZE   write OUTPUT as volumes OUTPUT.001, OUTPUT.002, ... of at
+                 most SIZE bytes each, which -d reads back from OUTPUT.001
+  --name         store INPUT's file name, which -d then writes to without -o
+  --comment TEXT store up to 255 bytes of TEXT in the header
+  --timestamp    store INPUT's modification time in the header
+  --force        compress INPUT even if it is already compressed
+  --require-compression RATIO
+                 fail unless the output is at most RATIO times INPUT's size
+  --restore-timestamp
+                 with -d, give OUTPUT the stored modification time
+  -j, --jobs N   compress up to N of several INPUTs at once (default 1)
+  --buffer-size SIZE
+                 read and write SIZE bytes at a time when streaming a file
+                 (default 65536)
+  --append INPUT ARCHIVE
+                 compress INPUT onto the end of the compressed file ARCHIVE
+  -p, --mkdir    create missing parent directories of OUTPUT
+  -q, --quiet    do not show progress, notes or timings
+  -v, --verbose  print how long each stage took
+  --compare      print the compressed size next to the input size
+  --stats        after encoding, print the sizes, ratio and code lengths to
+                 stderr
+  --benchmark    time encoding and decoding INPUT in memory, writing nothing
+  --inspect      describe the header and codes of the compressed INPUT
+  --explain      show what each byte of the compressed INPUT's header means
+  --compare-trees FIRST SECOND
+                 print how the code lengths of the two inputs' trees differ
+  --checksum none|crc32|xxhash
+                 checksum to store for the input (default crc32)
+  --fixed        store INPUT as it is, without building a tree
+  --nibble       code the 4-bit halves of bytes instead of whole bytes
+  --length-table store the tree as all 256 code lengths, for many distinct bytes
+  --by-line      code each line on its own, so that --line can decode it alone
+  --order1       code each byte with a tree for the byte before it, for text
+  --escape-below N
+                 code bytes seen fewer than N times as an escape and the byte
+  --model-from CORPUS
+                 build the tree from CORPUS instead of from INPUT
+  --freq BYTE:WEIGHT
+                 build the tree from these weights, one --freq per byte value
+  --model-json FILE
+                 build the tree from the byte counts in the JSON FILE
+  --bits         write the tree and codes as 0s and 1s, for teaching
+  --dump-model   write the byte counts of INPUT as JSON for --model-json
+  --csv          write the count, code length and code of each byte of INPUT
+                 as CSV
+  --self-test    round-trip some built-in samples and report the result
+  --version-info print the format version, modes and checksums this build
+                 supports
+  --interactive  show the codes and bits of each line typed in, until EOF
+  --rm           remove INPUT after a verified compression
+  --keep         keep INPUT (the default, overrides --rm)
+  --no-cache     neither read nor write the HUFFMAN_TREE_CACHE tree cache
+  --force-tree-rebuild
+                 rebuild the tree even if its cache entry exists";
+
+/// Flags applied before the command line ones, for setting defaults in CI.
+const OPTS_VAR: &str = "HUFFMAN_OPTS";
+
+/// The input or output that means stdin or stdout.
+const STDIO: &str = "-";
+
+/// What errors call stdout.
+const STDOUT_NAME: &str = "stdout";
+
+/// Where the tree comes from when it isn't built from the input.
+#[derive(Clone)]
+enum Model {
+    /// A sample file whose byte frequencies are counted.
+    Corpus(String),
+    /// Byte counts from a file written by `--dump-model`.
+    Json(String),
+    /// Byte counts given with `--freq`.
+    Inline(Box<[usize; 256]>),
+}
+
+/// What a run does, picked by at most one flag. The other flags that only
+/// make sense for some commands list them in [`Command::taking`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    /// Compressing INPUT, or each of several, the default.
+    Encode,
+    Decode,
+    Recompress,
+    Compare,
+    Benchmark,
+    CompareTrees,
+    Inspect,
+    Explain,
+    DumpModel,
+    Csv,
+    Bits,
+    Append,
+    SelfTest,
+    VersionInfo,
+    Interactive,
+}
+
+impl Command {
+    /// The command `flag` picks, if it picks one.
+    fn from_flag(flag: &str) -> Option<Self> {
+        Some(match flag {
+            "-d" => Self::Decode,
+            "--recompress" => Self::Recompress,
+            "--compare" => Self::Compare,
+            "--benchmark" => Self::Benchmark,
+            "--compare-trees" => Self::CompareTrees,
+            "--inspect" => Self::Inspect,
+            "--explain" => Self::Explain,
+            "--dump-model" => Self::DumpModel,
+            "--csv" => Self::Csv,
+            "--bits" => Self::Bits,
+            "--append" => Self::Append,
+            "--self-test" => Self::SelfTest,
+            "--version-info" => Self::VersionInfo,
+            "--interactive" => Self::Interactive,
+            _ => return None,
+        })
+    }
+
+    /// The commands `flag` works with, or `None` if it works with all of
+    /// them. A flag that is missing here is taken everywhere, so a new one
+    /// that changes the encoding or the decoding belongs in a list.
+    fn taking(flag: &str) -> Option<&'static [Self]> {
+        Some(match flag {
+            "--hex"
+            | "--max-output"
+            | "--recover"
+            | "--restore-newlines"
+            | "--restore-bom"
+            | "--restore-timestamp"
+            | "--line"
+            | "--map-file" => &[Self::Decode],
+            // the encoding options, which also shape what is timed or
+            // recompressed
+            "--comment" | "--fixed" | "--nibble" | "--length-table" | "--by-line" | "--order1"
+            | "--escape-below" => &[
+                Self::Encode,
+                Self::Compare,
+                Self::Append,
+                Self::Recompress,
+                Self::Benchmark,
+            ],
+            // `--recompress` keeps the input's text changes
+            "--normalize-newlines" | "--strip-bom" => &[
+                Self::Encode,
+                Self::Compare,
+                Self::Append,
+                Self::Benchmark,
+                Self::Bits,
+            ],
+            "--model-from" | "--model-json" | "--freq" => &[
+                Self::Encode,
+                Self::Compare,
+                Self::Append,
+                Self::Recompress,
+                Self::Bits,
+            ],
+            // these read the input with the check for compressed input
+            "--force" => &[Self::Encode, Self::Compare, Self::Append, Self::Bits],
+            "--name" | "--timestamp" => &[Self::Encode, Self::Compare, Self::Append],
+            "--require-compression" => &[Self::Encode, Self::Append],
+            "--rm" | "--split" | "--stats" => &[Self::Encode],
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone)]
 struct Settings {
-    decode: bool,
+    command: Command,
+    hex: bool,
+    /// The changes recorded in the header to undo when decoding.
+    restore: Flags,
+    recover: bool,
+    /// Whether to describe the encoding on stderr once it is written.
+    stats: bool,
+    /// The second input of [`Command::CompareTrees`], `input` being the
+    /// first.
+    compare_trees: Option<String>,
+    remove_input: bool,
+    make_dirs: bool,
+    /// Whether to store the input's file name in the header.
+    store_name: bool,
+    /// Whether to store the input's modification time in the header.
+    store_mtime: bool,
+    /// Whether to compress input that is already compressed.
+    force: bool,
+    /// Whether to give the decoded file the stored modification time.
+    restore_mtime: bool,
+    quiet: bool,
+    verbose: bool,
+    use_cache: bool,
+    rebuild_tree: bool,
+    model: Option<Model>,
+    max_output: Option<usize>,
+    /// The one line to decode, counting from 1.
+    line: Option<usize>,
+    /// Where to write the offsets of each decoded member.
+    map_file: Option<String>,
+    /// The largest output size allowed, as a fraction of the input size.
+    required_ratio: Option<f64>,
+    encode_options: EncodeOptions,
     input: String,
     output: Option<String>,
+    /// The most bytes each volume of `output` may hold, if it is split.
+    split: Option<usize>,
+    /// The file [`Command::Append`] adds `input` to.
+    append_to: Option<String>,
+    /// All inputs, `input` included, when there are several, each to be
+    /// compressed to its own file.
+    batch: Vec<String>,
+    jobs: usize,
+    /// How many bytes streaming reads and writes at a time.
+    buffer_size: usize,
 }
 
 impl Settings {
-    pub fn parse() -> Self {
-        let mut decode = false;
-        let mut input = None;
+    fn stages(&self) -> Stages {
+        Stages::new(self.verbose && !self.quiet)
+    }
+
+    /// Flags and the input path may appear in any order, so `-o out in`,
+    /// `in -o out` and `-d -o out in` are all equivalent.
+    ///
+    /// The whitespace-separated flags in `HUFFMAN_OPTS` come first, and the
+    /// command line overrides them.
+    pub fn parse() -> Result<Self, String> {
+        let env_opts = std::env::var(OPTS_VAR).unwrap_or_default();
+        let env_opts = env_opts.split_whitespace().map(String::from);
+
+        Self::parse_from(env_opts, std::env::args().skip(1))
+    }
+
+    fn parse_from(
+        env_opts: impl Iterator<Item = String>,
+        args: impl Iterator<Item = String>,
+    ) -> Result<Self, String> {
+        let mut command = None;
+        let mut normalize_newlines = false;
+        let mut restore_newlines = false;
+        let mut strip_bom = false;
+        let mut restore_bom = false;
+        let mut recover = false;
+        let mut hex = false;
+        let mut stats = false;
+        let mut remove_input = false;
+        let mut keep = false;
+        let mut make_dirs = false;
+        let mut store_name = false;
+        let mut store_mtime = false;
+        let mut force = false;
+        let mut split = None;
+        let mut buffer_size = stream::DEFAULT_BUFFER_LEN;
+        let mut restore_mtime = false;
+        let mut quiet = false;
+        let mut verbose = false;
+        let mut use_cache = true;
+        let mut rebuild_tree = false;
+        let mut model = None;
+        let mut coding = None;
+        let mut line = None;
+        let mut map_file = None;
+        let mut escape_below = None;
+        let mut max_output = None;
+        let mut required_ratio = None;
+        let mut checksum = Checksum::default();
+        let mut inputs = Vec::new();
+        let mut jobs = 1;
         let mut output = None;
+        let mut output_from_env = false;
+        let mut append = None;
+        let mut comment = None;
+        // the flags given that only some commands take
+        let mut restricted = Vec::new();
 
-        let mut args = std::env::args().skip(1);
+        let mut args = env_opts
+            .map(|arg| (true, arg))
+            .chain(args.map(|arg| (false, arg)))
+            .peekable();
 
-        while let Some(arg) = args.next() {
+        while let Some((from_env, arg)) = args.next() {
+            // a flag's value has to come from the same place as the flag
+            let mut value = || {
+                args.next_if(|&(env, _)| env == from_env)
+                    .map(|(_, arg)| arg)
+            };
+            if let Some(picked) = Command::from_flag(&arg) {
+                match &command {
+                    Some((other, flag)) if *other != picked => {
+                        return Err(format!(
+                            "`{flag}` and `{arg}` are different commands, pick one"
+                        ));
+                    }
+                    _ => command = Some((picked, arg.clone())),
+                }
+                if picked == Command::Append {
+                    let Some(file) = value() else {
+                        return Err("`--append` must be followed by the file to add".into());
+                    };
+                    append = Some(file);
+                }
+                continue;
+            }
+            if let Some(commands) = Command::taking(&arg) {
+                restricted.push((arg.clone(), commands));
+            }
             match arg.as_str() {
                 "-o" => {
-                    let output_file = args.next().expect("Cant have -o followed by nothing!");
-                    if output.is_some() {
-                        panic!("Cant have multiple output files");
+                    let Some(output_file) = value() else {
+                        return Err("`-o` must be followed by an output file".into());
+                    };
+                    if output.is_some() && !output_from_env {
+                        return Err("cannot have multiple output files".into());
                     }
                     output = Some(output_file);
+                    output_from_env = from_env;
                 }
-                "-d" => decode = true,
-                _ => {
-                    if input.is_some() {
-                        panic!("Cannot have multiple inputs");
+                "--comment" => {
+                    let Some(text) = value() else {
+                        return Err("`--comment` must be followed by the comment".into());
+                    };
+                    if text.len() > format::MAX_COMMENT_LEN {
+                        return Err(format!(
+                            "`--comment` can be at most {} bytes long",
+                            format::MAX_COMMENT_LEN
+                        ));
                     }
+                    comment = Some(text);
+                }
+                "--model-from" | "--model-json" => {
+                    let Some(path) = value() else {
+                        return Err(format!("`{arg}` must be followed by a file"));
+                    };
+                    if model.is_some() {
+                        return Err("cannot have multiple models".into());
+                    }
+                    model = Some(if arg == "--model-from" {
+                        Model::Corpus(path)
+                    } else {
+                        Model::Json(path)
+                    });
+                }
+                "--escape-below" => {
+                    let threshold = value().and_then(|n| n.parse().ok());
+                    let Some(threshold) = threshold else {
+                        return Err("`--escape-below` must be followed by a count".into());
+                    };
+                    escape_below = Some(threshold);
+                }
+                "-j" | "--jobs" => {
+                    let n = value().and_then(|n| n.parse().ok()).filter(|&n| n > 0);
+                    let Some(n) = n else {
+                        return Err(format!("`{arg}` must be followed by a thread count"));
+                    };
+                    jobs = n;
+                }
+                "--split" => {
+                    let size = value().and_then(|n| n.parse().ok()).filter(|&n| n > 0);
+                    let Some(size) = size else {
+                        return Err("`--split` must be followed by a size in bytes".into());
+                    };
+                    split = Some(size);
+                }
+                "--buffer-size" => {
+                    let size = value().and_then(|n| n.parse().ok()).filter(|&n| n > 0);
+                    let Some(size) = size else {
+                        return Err("`--buffer-size` must be followed by a size in bytes".into());
+                    };
+                    buffer_size = size;
+                }
+                "--require-compression" => {
+                    let ratio = value()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n: &f64| n > 0.0);
+                    let Some(ratio) = ratio else {
+                        return Err(
+                            "`--require-compression` must be followed by a ratio such as 0.9"
+                                .into(),
+                        );
+                    };
+                    required_ratio = Some(ratio);
+                }
+                "--line" => {
+                    let n = value().and_then(|n| n.parse().ok()).filter(|&n| n > 0);
+                    let Some(n) = n else {
+                        return Err("`--line` must be followed by a line number".into());
+                    };
+                    line = Some(n);
+                }
+                "--map-file" => {
+                    let Some(file) = value() else {
+                        return Err("`--map-file` must be followed by a file".into());
+                    };
+                    map_file = Some(file);
+                }
+                "--max-output" => {
+                    let limit = value().and_then(|n| n.parse().ok());
+                    let Some(limit) = limit else {
+                        return Err("`--max-output` must be followed by a size in bytes".into());
+                    };
+                    max_output = Some(limit);
+                }
+                "--checksum" => {
+                    let Some(name) = value() else {
+                        return Err("`--checksum` must be followed by none, crc32 or xxhash".into());
+                    };
+                    checksum = match Checksum::from_name(&name) {
+                        Some(checksum) => checksum,
+                        None if name == "xxhash" => {
+                            return Err(
+                                "this build has no xxhash support (enable the `xxhash` feature)"
+                                    .into(),
+                            );
+                        }
+                        None => return Err(format!("unknown checksum `{name}`")),
+                    };
+                }
+                "--freq" => {
+                    let Some((byte, weight)) = value().as_deref().and_then(parse_freq) else {
+                        return Err(
+                            "`--freq` must be followed by a byte value and its weight, like 97:100"
+                                .into(),
+                        );
+                    };
+                    let Model::Inline(counts) =
+                        model.get_or_insert_with(|| Model::Inline(Box::new([0; 256])))
+                    else {
+                        return Err("cannot have multiple models".into());
+                    };
+                    if counts[byte as usize] > 0 {
+                        return Err(format!("`--freq` gives byte {byte} twice"));
+                    }
+                    counts[byte as usize] = weight;
+                }
+                "--hex" => hex = true,
+                "--normalize-newlines" => normalize_newlines = true,
+                "--restore-newlines" => restore_newlines = true,
+                "--strip-bom" => strip_bom = true,
+                "--restore-bom" => restore_bom = true,
+                "--recover" => recover = true,
+                "--stats" => stats = true,
+                "--fixed" | "--nibble" | "--length-table" | "--by-line" | "--order1" => {
+                    match coding {
+                        Some(other) if other != arg => {
+                            return Err(format!(
+                                "`{other}` and `{arg}` are different codings, pick one"
+                            ));
+                        }
+                        _ => coding = Some(arg),
+                    }
+                }
+                "--rm" => remove_input = true,
+                "--keep" => keep = true,
+                "-p" | "--mkdir" => make_dirs = true,
+                "--name" => store_name = true,
+                "--timestamp" => store_mtime = true,
+                "--force" => force = true,
+                "--restore-timestamp" => restore_mtime = true,
+                "-q" | "--quiet" => quiet = true,
+                "-v" | "--verbose" => verbose = true,
+                "--no-cache" => use_cache = false,
+                "--force-tree-rebuild" => rebuild_tree = true,
+                flag if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(format!("unknown flag `{flag}`"));
+                }
+                _ if from_env => {
+                    return Err(format!("`{OPTS_VAR}` can only hold flags, not `{arg}`"));
+                }
+                _ => inputs.push(arg),
+            }
+        }
+
+        let (command, command_flag) = command.unzip();
+        let command = command.unwrap_or(Command::Encode);
+        // keeping the input is the default, and `--keep` always wins
+        let remove_input = remove_input && !keep;
+        if !remove_input {
+            restricted.retain(|(flag, _)| flag != "--rm");
+        }
+        for (flag, commands) in &restricted {
+            if !commands.contains(&command) {
+                let when = if commands.contains(&Command::Decode) {
+                    "decoding"
+                } else {
+                    "encoding"
+                };
+                return Err(match &command_flag {
+                    Some(other) => format!("`{flag}` only works when {when}, not with `{other}`"),
+                    None => format!("`{flag}` only works when {when}"),
+                });
+            }
+        }
+        let output = output.filter(|output| output != STDIO);
 
-                    input = Some(arg);
+        // what each command takes besides the flags that name it
+        let mut compare_trees = None;
+        let mut append_to = None;
+        match command {
+            Command::SelfTest | Command::VersionInfo | Command::Interactive => {
+                if let Some(input) = inputs.first() {
+                    let flag = command_flag.expect("only a flag picks these");
+                    return Err(format!("`{flag}` takes no input, not `{input}`"));
                 }
             }
+            Command::CompareTrees => {
+                let [first, second] = <[String; 2]>::try_from(inputs).map_err(|_| {
+                    "`--compare-trees` must be followed by exactly two input files".to_owned()
+                })?;
+                if output.is_some() {
+                    return Err("`--compare-trees` prints to stdout, so it can't have `-o`".into());
+                }
+                inputs = vec![first];
+                compare_trees = Some(second);
+            }
+            Command::Append => {
+                if output.is_some() {
+                    return Err("`--append` writes to the archive, so it can't have `-o`".into());
+                }
+                // the positional argument is the archive, and the file to
+                // add followed the flag
+                let file = append.expect("`--append` always has a file");
+                append_to = Some(inputs.first().cloned().unwrap_or_else(|| STDIO.to_owned()));
+                inputs = vec![file];
+            }
+            Command::Benchmark if output.is_some() || inputs.len() > 1 => {
+                return Err("`--benchmark` times a single INPUT and writes no output".into());
+            }
+            _ => {}
+        }
+        let batch = if inputs.len() > 1 {
+            inputs.clone()
+        } else {
+            Vec::new()
+        };
+        if !batch.is_empty() && (command != Command::Encode || remove_input || output.is_some()) {
+            return Err(format!(
+                "cannot have multiple inputs (`{}` and `{}`) except when compressing each to its own file",
+                batch[0], batch[1]
+            ));
+        }
+        if batch.iter().any(|input| input == STDIO) {
+            return Err(format!(
+                "`{STDIO}` can't be one of several inputs, which are each compressed to INPUT.huff"
+            ));
+        }
+        let input = inputs
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| STDIO.to_owned());
+
+        // what the flags need besides the right command
+        if store_mtime && input == STDIO {
+            return Err("`--timestamp` needs an input file, stdin has no modification time".into());
+        }
+        if store_name && (input == STDIO || !file_name(&input).is_some_and(format::is_safe_name)) {
+            return Err(format!(
+                "`{input}` has no file name that `--name` could store"
+            ));
+        }
+        if let Some(coding) = &coding
+            && (model.is_some() || escape_below.is_some())
+        {
+            return Err(if coding == "--fixed" {
+                "`--fixed` builds no tree, so it takes no model or `--escape-below`".into()
+            } else {
+                format!("`{coding}` only works with the input's own tree")
+            });
+        }
+        if escape_below.is_some() && model.is_some() {
+            return Err("`--escape-below` only works with the input's own tree".into());
+        }
+        if line.is_some() && (recover || restore_newlines || restore_bom) {
+            return Err("`--line` only works without `--recover` or `--restore-*`".into());
+        }
+        if map_file.is_some() && line.is_some() {
+            return Err("`--map-file` only works when decoding whole files".into());
+        }
+        if split.is_some() && output.is_none() {
+            return Err("`--split` needs an output file given with `-o`".into());
+        }
+        if remove_input && output.is_none() {
+            return Err("`--rm` needs an output file given with `-o`".into());
+        }
+        if remove_input && input == STDIO {
+            return Err("`--rm` needs an input file to remove, not stdin".into());
+        }
+        if stats && !batch.is_empty() {
+            return Err("`--stats` only works when encoding a single INPUT".into());
         }
 
-        let Some(input) = input else {
-            panic!("Give an input file")
+        let mut encode_options = EncodeOptions::new()
+            .with_checksum(checksum)
+            .with_normalized_newlines(normalize_newlines)
+            .with_stripped_bom(strip_bom)
+            .with_comment(comment.as_deref());
+        encode_options = match coding.as_deref() {
+            Some("--fixed") => encode_options.with_stored(true),
+            Some("--nibble") => encode_options.with_nibbles(true),
+            Some("--length-table") => encode_options.with_length_table(true),
+            Some("--by-line") => encode_options.with_lines(true),
+            Some("--order1") => encode_options.with_order1(true),
+            _ => encode_options,
         };
+        if let Some(threshold) = escape_below {
+            encode_options = encode_options.with_escape_below(threshold);
+        }
 
-        Self {
-            decode,
+        Ok(Self {
+            command,
+            hex,
+            restore: Flags {
+                normalized_newlines: restore_newlines,
+                stripped_bom: restore_bom,
+            },
+            recover,
+            stats,
+            compare_trees,
+            remove_input,
+            make_dirs,
+            store_name,
+            store_mtime,
+            force,
+            restore_mtime,
+            quiet,
+            verbose,
+            use_cache,
+            rebuild_tree,
+            model,
+            max_output,
+            line,
+            map_file,
+            required_ratio,
+            encode_options,
             input,
             output,
-        }
+            split,
+            append_to,
+            batch,
+            jobs,
+            buffer_size,
+        })
     }
 }
 
 fn main() {
-    let settings = Settings::parse();
+    let settings = match Settings::parse() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("error: {err}");
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let output_path = match &settings.output {
+        // a single line isn't the stored file
+        None if settings.command == Command::Decode && settings.line.is_none() => {
+            stored_name_path(&settings.input)
+        }
+        output => output.clone(),
+    };
+    if settings.restore_mtime && output_path.is_none() {
+        eprintln!("error: `--restore-timestamp` needs an output file (pass -o)");
+        std::process::exit(2);
+    }
+    let output_path = output_path.as_deref();
+
+    match settings.command {
+        Command::SelfTest => {
+            let passed = self_test::run();
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        Command::VersionInfo => {
+            write_output(None, version_info::version_info().as_bytes(), false);
+        }
+        Command::Interactive => {
+            let stdin = std::io::stdin().lock();
+            if let Err(err) =
+                interactive::run(stdin, &mut std::io::stdout(), &mut std::io::stderr())
+            {
+                eprintln!("error: {err}");
+                std::process::exit(1);
+            }
+        }
+        Command::Compare => {
+            let input = read_input_or_exit(&settings);
+            let input_len = input.len();
+            let output = encode(&input, &settings);
+
+            print_comparison(input_len, output.len());
+        }
+        Command::Benchmark => {
+            let input = read_file(&settings.input);
+            match benchmark::run(&input, &settings.encode_options) {
+                Ok(report) => write_output(None, report.as_bytes(), false),
+                Err(err) => {
+                    eprintln!("error: `{}` did not round-trip: {err}", settings.input);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::CompareTrees => {
+            let second = settings
+                .compare_trees
+                .as_ref()
+                .expect("parsing took two inputs");
+            let [first, second] = [&settings.input, second].map(|path| {
+                let counts = huffman::count_frequencies(read_file(path));
+                HuffmanNode::build_tree_from_counts(&counts).unwrap_or_else(|| {
+                    eprintln!("error: `{path}` is empty, so it has no tree");
+                    std::process::exit(1);
+                })
+            });
+            let diff = tree_diff::compare_trees(&first, &second);
+            write_output(None, diff.as_bytes(), false);
+        }
+        Command::Append => {
+            let archive = settings
+                .append_to
+                .as_ref()
+                .expect("parsing took the archive");
+            append(archive, &settings);
+        }
+        Command::Inspect | Command::Explain => inspect_input(&settings, output_path),
+        Command::Csv => write_csv(&settings, output_path),
+        Command::DumpModel => {
+            let input = read_file(&settings.input);
+            let json = models::model_to_json(&huffman::count_frequencies(input));
 
-    let mut output_stream: Box<dyn Write> = if let Some(output) = settings.output {
-        Box::new(std::fs::File::create(output).unwrap())
+            write_output(output_path, json.as_bytes(), settings.make_dirs);
+        }
+        Command::Recompress => recompress_input(&settings, output_path),
+        Command::Decode => match settings.line {
+            Some(n) => decode_line(&settings, n, output_path),
+            None if streams_decoding(&settings) => {
+                let stages = settings.stages();
+                stages.time("decoding", || decode_streaming(&settings, output_path));
+            }
+            None => decode_input(&settings, output_path),
+        },
+        Command::Encode if !settings.batch.is_empty() => {
+            let passed = encode_batch(&settings);
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        Command::Encode if streams_encoding(&settings) => {
+            encode_streaming(&settings, output_path);
+        }
+        Command::Encode | Command::Bits => encode_input(&settings, output_path),
+    }
+}
+
+/// Writes a description of the compressed input, its codes for
+/// [`Command::Inspect`] or the bytes of its header for
+/// [`Command::Explain`].
+fn inspect_input(settings: &Settings, output_path: Option<&str>) {
+    let input = read_compressed(&settings.input);
+    let summary = if settings.command == Command::Explain {
+        explain::explain_header(&input)
     } else {
-        Box::new(std::io::stdout())
+        inspect::inspect(&input)
     };
+    let summary = summary.unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    });
+
+    write_output(output_path, summary.as_bytes(), settings.make_dirs);
+}
+
+/// Writes the count, code length and code of each byte of the input.
+fn write_csv(settings: &Settings, output_path: Option<&str>) {
+    let input = read_file(&settings.input);
+    let csv = symbol_csv::symbol_csv(&input).unwrap_or_else(|| {
+        eprintln!("error: `{}` is empty, so it has no codes", settings.input);
+        std::process::exit(1);
+    });
+
+    write_output(output_path, csv.as_bytes(), settings.make_dirs);
+}
 
-    if settings.decode {
-        let input = std::fs::read(settings.input).unwrap();
-        let output = decode(input);
+/// Decodes the compressed input and encodes it again with the settings,
+/// reporting the change in size.
+fn recompress_input(settings: &Settings, output_path: Option<&str>) {
+    let input = read_compressed(&settings.input);
+    let output = recompress(&input, settings).unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    });
 
-        output_stream.write_all(output.as_bytes()).unwrap();
+    write_output(output_path, &output, settings.make_dirs);
+    if !settings.quiet {
+        let change = 100.0 * (output.len() as f64 / input.len() as f64 - 1.0);
+        eprintln!(
+            "recompressed {} bytes to {} ({change:+.1}%)",
+            input.len(),
+            output.len()
+        );
+    }
+}
+
+/// Decodes only line `n` of the compressed input, counting from 1.
+fn decode_line(settings: &Settings, n: usize, output_path: Option<&str>) {
+    let input = read_compressed(&settings.input);
+    let line = format::decode_line(&input, n - 1).unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    });
+    let Some(line) = line else {
+        eprintln!("error: `{}` has fewer than {n} lines", settings.input);
+        std::process::exit(1);
+    };
+
+    if settings.hex {
+        let dump = hex_dump::hex_dump(&line);
+        write_output(output_path, dump.as_bytes(), settings.make_dirs);
     } else {
-        let input = std::fs::read_to_string(settings.input).unwrap();
-        let output = encode(input);
+        write_output(output_path, &line, settings.make_dirs);
+    }
+}
 
-        output_stream.write_all(&output).unwrap();
+/// Decodes all of the compressed input at once, for the settings that
+/// [`decode_streaming`] can't handle.
+fn decode_input(settings: &Settings, output_path: Option<&str>) {
+    let stages = settings.stages();
+    let input = stages.time("reading", || read_compressed(&settings.input));
+    let max_output = settings.max_output.unwrap_or(usize::MAX);
+    let mut spans = Vec::new();
+    let decoded = stages.time("decoding", || {
+        decode(&input, settings.restore, max_output, &mut spans)
+    });
+    let (output, err) = match decoded {
+        Ok(output) => (output, None),
+        Err((partial, err)) if settings.recover => (partial, Some(err)),
+        Err((_, err)) => {
+            eprintln!("error: {err}");
+            std::process::exit(1);
+        }
+    };
+
+    stages.time("writing", || {
+        if settings.hex {
+            let dump = hex_dump::hex_dump(&output);
+            write_output(output_path, dump.as_bytes(), settings.make_dirs);
+        } else {
+            write_output(output_path, &output, settings.make_dirs);
+        }
+    });
+    if let Some(map_file) = &settings.map_file {
+        let map = member_map::render(&spans);
+        write_output(Some(map_file), map.as_bytes(), settings.make_dirs);
+    }
+
+    if settings.restore_mtime
+        && let Ok((header, _)) = format::peek_header(&input)
+        && let Some(mtime) = header.mtime
+    {
+        let path = output_path.expect("the output was checked");
+        set_mtime(path, mtime).unwrap_or_else(|err| {
+            eprintln!("error: cannot set the modification time of `{path}`: {err}");
+            std::process::exit(1);
+        });
     }
 
-    output_stream.flush().unwrap();
+    if let Some(err) = err {
+        eprintln!("error: {err}");
+        eprintln!("recovered {} bytes", output.len());
+        std::process::exit(1);
+    }
 }
 
-fn encode(mut input: String) -> Vec<u8> {
-    input.push('\0');
-    let input = input.as_bytes();
+/// Compresses all of the input at once, or spells out its codes for
+/// [`Command::Bits`].
+fn encode_input(settings: &Settings, output_path: Option<&str>) {
+    let stages = settings.stages();
+    let input = stages.time("reading", || read_input_or_exit(settings));
+    let output = if settings.command == Command::Bits {
+        bit_string(&input, settings).into_bytes()
+    } else {
+        encode(&input, settings)
+    };
+    if let Err(err) = require_compression(settings, input.len(), output.len()) {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
 
-    let tree = HuffmanNode::build_tree(input);
+    stages.time("writing", || match (settings.split, output_path) {
+        (Some(size), Some(path)) => write_volumes(path, &output, size, settings.make_dirs),
+        _ => write_output(output_path, &output, settings.make_dirs),
+    });
 
-    tree.serialize(input)
+    if settings.stats {
+        eprint!("{}", stats::stats(&input, &output));
+    }
+    if settings.remove_input {
+        remove_verified_input(settings, &input);
+    }
+}
+
+/// The last component of `path`, if it has one.
+fn file_name(path: &str) -> Option<&str> {
+    Path::new(path).file_name()?.to_str()
+}
+
+/// The name of the `n`th volume, counting from 1, of the split file `path`.
+fn volume_path(path: &str, n: usize) -> String {
+    format!("{path}.{n:03}")
 }
 
-fn decode(input: Vec<u8>) -> String {
-    let (_tree, output) = HuffmanNode::decode(&input);
+/// Writes `data` to volumes of `path` holding at most `size` bytes each.
+fn write_volumes(path: &str, data: &[u8], size: usize, make_dirs: bool) {
+    for (i, volume) in data.chunks(size).enumerate() {
+        write_file(&volume_path(path, i + 1), volume, make_dirs);
+    }
+}
 
-    String::from_utf8(output).unwrap()
+/// Reads the compressed file at `path`. If it is the first volume of a split
+/// file, `NAME.001`, the volumes after it are read too, up to the first one
+/// that is missing.
+fn read_compressed(path: &str) -> Vec<u8> {
+    let mut input = read_file(path);
+    if let Some(base) = path.strip_suffix(".001") {
+        for n in 2.. {
+            match std::fs::read(volume_path(base, n)) {
+                Ok(volume) => input.extend(volume),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => break,
+                Err(err) => {
+                    eprintln!("error: cannot read volume {n} of `{base}`: {err}");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    input
+}
+
+/// The modification time of `path` in seconds since the Unix epoch, if the
+/// platform records one and it isn't before the epoch.
+fn mtime(path: &str) -> Option<u64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// Sets the modification time of `path` to `mtime` seconds after the Unix
+/// epoch.
+fn set_mtime(path: &str, mtime: u64) -> std::io::Result<()> {
+    let file = std::fs::OpenOptions::new().write(true).open(path)?;
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))
+}
+
+/// Where `-d` without `-o` writes a compressed file whose header stores a
+/// file name: that name, next to the compressed file. Exits if something
+/// is already there rather than overwriting it.
+fn stored_name_path(input: &str) -> Option<String> {
+    // stdin can only be read once, and there is no directory to put it in
+    if input == STDIO {
+        return None;
+    }
+    // the name is near the start, after at most a 255-byte comment
+    let mut header = Vec::new();
+    let file = std::fs::File::open(input).ok()?;
+    file.take(1024).read_to_end(&mut header).ok()?;
+
+    let (header, _) = format::peek_header(&header).ok()?;
+    let path = Path::new(input).with_file_name(header.name?);
+    if path.exists() {
+        eprintln!(
+            "error: `{}` already exists (pass -o to decode elsewhere)",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+
+    Some(path.to_str()?.to_owned())
+}
+
+/// Writes `data` to the file at `path`, or to stdout if there is none. A file
+/// only takes the place of what was at `path` once all of it is written.
+fn write_output(path: Option<&str>, data: &[u8], make_dirs: bool) {
+    let Some(path) = path else {
+        // Rust's stdout never translates newlines, not even on Windows, so
+        // compressed bytes can be piped as they are
+        let mut stdout = std::io::stdout().lock();
+        if let Err(err) = stdout.write_all(data).and_then(|()| stdout.flush()) {
+            exit_with(CompressError::write(Path::new(STDOUT_NAME), &err));
+        }
+        return;
+    };
+
+    write_file(path, data, make_dirs);
+}
+
+/// Writes `data` to the file at `path`, exiting with an error that names it
+/// if that fails.
+fn write_file(path: &str, data: &[u8], make_dirs: bool) {
+    let mut file = create_output(path, make_dirs);
+    if let Err(err) = file.write_all(data).and_then(|()| file.commit()) {
+        exit_with(CompressError::write(Path::new(path), &err));
+    }
+}
+
+/// Reads the file at `path`, exiting with an error that names it if that
+/// fails.
+fn read_file(path: &str) -> Vec<u8> {
+    read_path(path).unwrap_or_else(|err| exit_with(CompressError::read(Path::new(path), &err)))
+}
+
+/// Reads all of the file at `path`, or of stdin if it is [`STDIO`].
+fn read_path(path: &str) -> std::io::Result<Vec<u8>> {
+    if path != STDIO {
+        return std::fs::read(path);
+    }
+    let mut data = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn exit_with(err: CompressError) -> ! {
+    eprintln!("error: {err}");
+    std::process::exit(1);
+}
+
+/// `File::create` only reports a bare "not found" for a missing parent
+/// directory, so check for it up front and either create it or say which
+/// directory is missing.
+fn create_output(path: &str, make_dirs: bool) -> AtomicFile {
+    let parent = Path::new(path).parent();
+    if let Some(parent) = parent
+        && !parent.as_os_str().is_empty()
+        && !parent.is_dir()
+    {
+        if make_dirs {
+            if let Err(err) = std::fs::create_dir_all(parent) {
+                exit_with(CompressError::write(parent, &err));
+            }
+        } else {
+            eprintln!(
+                "error: output directory `{}` does not exist (pass --mkdir to create it)",
+                parent.display()
+            );
+            std::process::exit(1);
+        }
+    }
+
+    AtomicFile::create(Path::new(path))
+        .unwrap_or_else(|err| exit_with(CompressError::write(Path::new(path), &err)))
+}
+
+/// Whether `-d` can decode the input as it reads it rather than all at
+/// once, which it can unless something needs all of the output or of the
+/// input, or the input is split into volumes.
+fn streams_decoding(settings: &Settings) -> bool {
+    !settings.recover
+        && !settings.hex
+        && settings.map_file.is_none()
+        && settings.restore == Flags::default()
+        && !settings.restore_mtime
+        && !settings.input.ends_with(".001")
+}
+
+/// Decodes the input into `output`, or stdout, a buffer at a time, so that
+/// memory doesn't limit the size of the file.
+fn decode_streaming(settings: &Settings, output: Option<&str>) {
+    let input: Box<dyn Read> =
+        if settings.input == STDIO {
+            Box::new(std::io::stdin().lock())
+        } else {
+            let file = std::fs::File::open(&settings.input);
+            Box::new(file.unwrap_or_else(|err| {
+                exit_with(CompressError::read(Path::new(&settings.input), &err))
+            }))
+        };
+    let options = DecodeOptions::new().with_max_output(settings.max_output.unwrap_or(usize::MAX));
+
+    stream_output(output, settings.make_dirs, |out| {
+        stream::decode_stream_with(input, out, &options, settings.buffer_size, &mut ()).map(drop)
+    });
+}
+
+/// Whether encoding can read the input twice rather than hold it in
+/// memory: it has to be a file, and the options the kind that
+/// [`stream::encode_stream`] writes, with no tree from elsewhere and
+/// nothing to check or do with the output once it is written.
+fn streams_encoding(settings: &Settings) -> bool {
+    settings.input != STDIO
+        && settings.encode_options.is_plain()
+        && settings.model.is_none()
+        && TreeCache::from_env()
+            .filter(|_| settings.use_cache)
+            .is_none()
+        && !(settings.command == Command::Bits || settings.store_name || settings.store_mtime)
+        && !settings.remove_input
+        && settings.split.is_none()
+        && settings.required_ratio.is_none()
+        && !settings.stats
+        && std::fs::metadata(&settings.input).is_ok_and(|meta| meta.is_file())
+}
+
+/// Compresses the input file into `output`, or stdout, the way [`encode`]
+/// would with the same settings, but reading it a buffer at a time.
+fn encode_streaming(settings: &Settings, output: Option<&str>) {
+    let path = &settings.input;
+    let mut input = std::fs::File::open(path)
+        .unwrap_or_else(|err| exit_with(CompressError::read(Path::new(path), &err)));
+    if !settings.force && format::read_header(&mut input).is_ok() {
+        eprintln!("error: `{path}` is already compressed (pass --force to compress it again)");
+        std::process::exit(1);
+    }
+    let checksum = settings.encode_options.checksum();
+    let len = input
+        .metadata()
+        .unwrap_or_else(|err| exit_with(CompressError::read(Path::new(path), &err)))
+        .len();
+    let mut watch = StreamWatch {
+        stages: settings.stages(),
+        progress: Progress::new(usize::try_from(len).unwrap_or(usize::MAX), settings.quiet),
+        current: None,
+    };
+
+    stream_output(output, settings.make_dirs, |out| {
+        input.rewind()?;
+        let mut out = TimedWriter {
+            output: out,
+            spent: Duration::ZERO,
+        };
+        let stats = stream::encode_stream_with(
+            &mut input,
+            &mut out,
+            checksum,
+            settings.buffer_size,
+            &mut watch,
+        )?;
+        watch.finish(out.spent);
+        if stats.mode == Mode::Stored && !settings.quiet {
+            eprintln!("note: input appears incompressible; stored verbatim");
+        }
+        Ok::<_, std::io::Error>(())
+    });
+}
+
+/// Shows the progress and times the stages of [`stream::encode_stream_with`]
+/// the way [`encode_bytes`] does its own.
+struct StreamWatch {
+    stages: Stages,
+    progress: Progress,
+    current: Option<(stream::Stage, Instant, progress::Meter)>,
+}
+
+impl StreamWatch {
+    /// Ends the last stage, of which `writing` went to writing the output.
+    fn finish(&mut self, writing: Duration) {
+        if let Some((stage, start, mut meter)) = self.current.take() {
+            meter.finish();
+            let elapsed = start.elapsed();
+            self.stages
+                .report(stage.name(), elapsed.saturating_sub(writing));
+            self.stages.report("writing", writing);
+        }
+    }
+}
+
+impl stream::Observer for StreamWatch {
+    fn start(&mut self, stage: stream::Stage) {
+        if let Some((stage, start, mut meter)) = self.current.take() {
+            meter.finish();
+            self.stages.report(stage.name(), start.elapsed());
+        }
+        let meter = self.progress.meter(stage.name());
+        self.current = Some((stage, Instant::now(), meter));
+    }
+
+    fn advance(&mut self, len: usize) {
+        if let Some((_, _, meter)) = &mut self.current {
+            meter.advance(len);
+        }
+    }
+}
+
+/// Counts how long writes to `output` take.
+struct TimedWriter<'a> {
+    output: &'a mut dyn Write,
+    spent: Duration,
+}
+
+impl Write for TimedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let start = Instant::now();
+        let result = self.output.write(buf);
+        self.spent += start.elapsed();
+        result
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let start = Instant::now();
+        let result = self.output.flush();
+        self.spent += start.elapsed();
+        result
+    }
+}
+
+/// Has `write` write to the file at `path`, or to stdout if there is none,
+/// exiting with its error if it fails. As with [`write_output`], the file
+/// only takes the place of what was at `path` once all of it is written.
+fn stream_output<E: std::fmt::Display>(
+    path: Option<&str>,
+    make_dirs: bool,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), E>,
+) {
+    let result = match path {
+        Some(path) => {
+            let mut file = create_output(path, make_dirs);
+            match stream_to(&mut file, Path::new(path), write) {
+                Ok(()) => file
+                    .commit()
+                    .map_err(|err| CompressError::write(Path::new(path), &err).to_string()),
+                // dropping the file removes what was written of it
+                Err(err) => Err(err),
+            }
+        }
+        None => {
+            let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+            stream_to(&mut stdout, Path::new(STDOUT_NAME), write)
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+/// Runs `write` on `output` and flushes it. A failed write is reported as
+/// a write to `name`, whatever error `write` made of it, so that failing to
+/// write reads the same wherever it happens.
+fn stream_to<E: std::fmt::Display>(
+    output: &mut dyn Write,
+    name: &Path,
+    write: impl FnOnce(&mut dyn Write) -> Result<(), E>,
+) -> Result<(), String> {
+    let mut output = WriteErrors {
+        output,
+        failed: None,
+    };
+    let result = write(&mut output);
+    if result.is_ok() {
+        let _ = output.flush();
+    }
+    if let Some(kind) = output.failed {
+        return Err(CompressError::write(name, &kind.into()).to_string());
+    }
+    result.map_err(|err| err.to_string())
+}
+
+/// A writer that remembers how its last failed write failed.
+struct WriteErrors<'a> {
+    output: &'a mut dyn Write,
+    failed: Option<std::io::ErrorKind>,
+}
+
+impl WriteErrors<'_> {
+    fn record<T>(&mut self, result: std::io::Result<T>) -> std::io::Result<T> {
+        // an interrupted write is tried again rather than failing
+        if let Err(err) = &result
+            && err.kind() != std::io::ErrorKind::Interrupted
+        {
+            self.failed = Some(err.kind());
+        }
+        result
+    }
+}
+
+impl Write for WriteErrors<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let result = self.output.write(buf);
+        self.record(result)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let result = self.output.flush();
+        self.record(result)
+    }
+}
+
+/// Compresses every input of a batch to `INPUT.huff`, `settings.jobs` at a
+/// time. Errors are reported per file, in the order the inputs were given,
+/// once all of them are done. Returns whether every file was compressed.
+fn encode_batch(settings: &Settings) -> bool {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<_> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..settings.jobs.min(settings.batch.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = settings.batch.get(i) else {
+                            break;
+                        };
+                        done.push((i, encode_file(path, settings)));
+                    }
+                    done
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|&(i, _)| i);
+
+    let mut passed = true;
+    for (i, result) in results {
+        if let Err(err) = result {
+            eprintln!("error: `{}`: {err}", settings.batch[i]);
+            passed = false;
+        }
+    }
+    passed
+}
+
+fn encode_file(path: &str, settings: &Settings) -> Result<(), String> {
+    let settings = Settings {
+        input: path.to_owned(),
+        ..settings.clone()
+    };
+    let input = read_input(path, settings.force)?;
+    let output = encode(&input, &settings);
+    require_compression(&settings, input.len(), output.len())?;
+
+    let target = format!("{path}.huff");
+    let write = || {
+        let mut file = AtomicFile::create(Path::new(&target))?;
+        file.write_all(&output)?;
+        file.commit()
+    };
+    write().map_err(|err| CompressError::write(Path::new(&target), &err).to_string())
+}
+
+/// Fails if `--require-compression` was given and the output is bigger than
+/// it allows.
+fn require_compression(
+    settings: &Settings,
+    input_len: usize,
+    output_len: usize,
+) -> Result<(), String> {
+    let Some(required) = settings.required_ratio else {
+        return Ok(());
+    };
+    let ratio = output_len as f64 / input_len.max(1) as f64;
+    if ratio > required {
+        return Err(format!(
+            "`{}` only compressed to {:.1}% of its size, but {:.1}% was required",
+            settings.input,
+            100.0 * ratio,
+            100.0 * required
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reads the bytes at `path` for encoding, whether they are text or not.
+/// Unless `force` is set, input that is already compressed is refused,
+/// since compressing it again would only make it bigger.
+fn read_input(path: &str, force: bool) -> Result<Vec<u8>, String> {
+    let input =
+        read_path(path).map_err(|err| CompressError::read(Path::new(path), &err).to_string())?;
+    if !force && format::is_compressed(&input) {
+        return Err(format!(
+            "`{path}` is already compressed (pass --force to compress it again)"
+        ));
+    }
+
+    Ok(input)
+}
+
+/// [`read_input`] for the input of `settings`, exiting on an error.
+fn read_input_or_exit(settings: &Settings) -> Vec<u8> {
+    read_input(&settings.input, settings.force).unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    })
+}
+
+/// Compresses the input onto the end of `archive`, after checking that every
+/// member already in it decodes. The archive is rewritten whole rather than
+/// appended to, so a failed write leaves it as it was.
+fn append(archive: &str, settings: &Settings) {
+    let existing = std::fs::read(archive)
+        .unwrap_or_else(|err| exit_with(CompressError::read(Path::new(archive), &err)));
+    if let Err(err) = format::decode_members(&existing) {
+        eprintln!("error: `{archive}` is not a valid archive: {err}");
+        std::process::exit(1);
+    }
+
+    let input = read_input_or_exit(settings);
+    let output = encode(&input, settings);
+    if let Err(err) = require_compression(settings, input.len(), output.len()) {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+
+    let mut file = create_output(archive, false);
+    let written = file
+        .write_all(&existing)
+        .and_then(|()| file.write_all(&output))
+        .and_then(|()| file.commit());
+    if let Err(err) = written {
+        exit_with(CompressError::write(Path::new(archive), &err));
+    }
+}
+
+/// Reads the freshly written output back and only removes the input if it
+/// decodes to exactly the original contents. Any earlier I/O error has
+/// already aborted the program, so this never runs after a failed write.
+fn remove_verified_input(settings: &Settings, input: &[u8]) {
+    let output = settings
+        .output
+        .as_ref()
+        .expect("--rm always has an output file");
+
+    let written = match settings.split {
+        Some(_) => read_compressed(&volume_path(output, 1)),
+        None => std::fs::read(output).unwrap_or_else(|err| {
+            eprintln!(
+                "error: cannot read `{output}` back: {err}, keeping `{}`",
+                settings.input
+            );
+            std::process::exit(1);
+        }),
+    };
+    // restoring is the only way changed text can match the input
+    let restore = Flags {
+        normalized_newlines: true,
+        stripped_bom: true,
+    };
+    let decoded = decode(&written, restore, usize::MAX, &mut Vec::new());
+    if decoded.ok().as_deref() != Some(input) {
+        eprintln!(
+            "error: `{output}` does not decode back to `{}`, keeping it",
+            settings.input
+        );
+        std::process::exit(1);
+    }
+
+    if let Err(err) = std::fs::remove_file(&settings.input) {
+        eprintln!("error: cannot remove `{}`: {err}", settings.input);
+        std::process::exit(1);
+    }
+}
+
+/// Prints a small size table to stderr. Nothing is written to the output.
+fn print_comparison(input_len: usize, huffman_len: usize) {
+    eprintln!("{:<8} {:>12} {:>8}", "format", "bytes", "ratio");
+    for (name, len) in [("input", input_len), ("huffman", huffman_len)] {
+        let ratio = 100.0 * len as f64 / input_len.max(1) as f64;
+        eprintln!("{name:<8} {len:>12} {ratio:>7.1}%");
+    }
+}
+
+fn encode(input: &[u8], settings: &Settings) -> Vec<u8> {
+    let options = &settings.encode_options;
+    let flags = options.flags(input);
+
+    let mut encoded = format::with_flags(encode_prepared(&options.prepare(input), settings), flags);
+    if let Some(comment) = options.comment() {
+        encoded = format::with_comment(encoded, comment).expect("the comment's length was checked");
+    }
+    if settings.store_name {
+        let name = file_name(&settings.input).expect("the name was checked");
+        encoded = format::with_name(encoded, name).expect("the name was checked");
+    }
+    if settings.store_mtime
+        && let Some(mtime) = mtime(&settings.input)
+    {
+        encoded = format::with_mtime(encoded, mtime);
+    }
+    encoded
+}
+
+/// Encodes `input` as it is, without the text changes the options ask for,
+/// leaving the flags unset.
+fn encode_prepared(input: &[u8], settings: &Settings) -> Vec<u8> {
+    let options = &settings.encode_options;
+    if options.escape_below().is_some()
+        || options.stored()
+        || options.nibbles()
+        || options.length_table()
+        || options.lines()
+        || options.order1()
+    {
+        let options = options
+            .clone()
+            .with_normalized_newlines(false)
+            .with_stripped_bom(false)
+            .with_comment(None);
+        return settings.stages().time("encoding", || {
+            format::encode_with(input, &options).unwrap_or_else(|err| exit_with(err.into()))
+        });
+    }
+
+    encode_bytes(input, settings)
+}
+
+/// Decodes every member of `input` and encodes it again with the current
+/// options, e.g. to move files written by an older version to a better
+/// mode. The decoded bytes still have the text changes the member's flags
+/// record, so those are kept as they are, and so are its name, its
+/// modification time and its comment unless `--comment` replaces it.
+fn recompress(input: &[u8], settings: &Settings) -> Result<Vec<u8>, DecodeError> {
+    let mut output = Vec::new();
+    let mut input = input;
+    loop {
+        let (header, _) = format::peek_header(input)?;
+        let (decoded, rest) = format::decode_first(input, &DecodeOptions::new())?;
+        let mut encoded = format::with_flags(encode_prepared(&decoded, settings), header.flags);
+        if let Some(comment) = settings.encode_options.comment().or(header.comment) {
+            encoded = format::with_comment(encoded, comment)
+                .expect("the comment's length was checked or read from a header");
+        }
+        if let Some(name) = header.name {
+            encoded = format::with_name(encoded, name).expect("names in headers are safe");
+        }
+        if let Some(mtime) = header.mtime {
+            encoded = format::with_mtime(encoded, mtime);
+        }
+        output.extend(encoded);
+
+        input = rest;
+        if input.is_empty() {
+            break;
+        }
+    }
+
+    Ok(output)
+}
+
+/// Encodes `input`, already [prepared](EncodeOptions::prepare), with the
+/// tree cache or model corpus and a progress display, none of which
+/// [`format::encode_with`] knows about.
+fn encode_bytes(input: &[u8], settings: &Settings) -> Vec<u8> {
+    let checksum = settings.encode_options.checksum();
+    let progress = Progress::new(input.len(), settings.quiet);
+    let stages = settings.stages();
+
+    let counts = stages.time("counting", || {
+        huffman::count_frequencies(progress.track("counting", input.iter().copied()))
+    });
+    // a model's tree can still pay off where the input's own wouldn't
+    if settings.model.is_none() && format::is_incompressible(&counts) {
+        if !settings.quiet {
+            eprintln!("note: input appears incompressible; stored verbatim");
+        }
+        return format::encode_stored(input, checksum);
+    }
+
+    let tree = stages.time("building tree", || match &settings.model {
+        Some(model) => model_tree(model, &counts, settings),
+        None => build_tree(&counts, settings).expect("empty input is incompressible, so stored"),
+    });
+
+    let stream = stages.time("encoding", || {
+        tree.serialize_iter(progress.track("encoding", input.iter().copied()))
+            .expect("the tree has a code for every byte of the input")
+    });
+
+    [format::header(input, checksum, Mode::Huffman), stream].concat()
+}
+
+/// The tree and codes `encode` would use, spelled out bit by bit.
+fn bit_string(input: &[u8], settings: &Settings) -> String {
+    let input = settings.encode_options.prepare(input);
+    let counts = huffman::count_frequencies(input.iter().copied());
+    let tree = match &settings.model {
+        Some(model) => model_tree(model, &counts, settings),
+        None => match build_tree(&counts, settings) {
+            Some(tree) => tree,
+            // nothing to code, so no tree and no bits
+            None => return "tree:    \npayload: \n".to_owned(),
+        },
+    };
+
+    tree.bit_string(&input)
+        .expect("the tree has a code for every byte of the input")
+}
+
+fn build_tree(counts: &[usize; 256], settings: &Settings) -> Option<HuffmanNode> {
+    let cache = TreeCache::from_env().filter(|_| settings.use_cache);
+    match cache {
+        Some(cache) => cache
+            .get_or_build(counts, settings.rebuild_tree)
+            .map(|(tree, _hit)| tree),
+        None => HuffmanNode::build_tree_from_counts(counts),
+    }
+}
+
+/// Parses the `BYTE:WEIGHT` of `--freq`.
+fn parse_freq(arg: &str) -> Option<(u8, usize)> {
+    let (byte, weight) = arg.split_once(':')?;
+    Some((byte.parse().ok()?, weight.parse().ok()?))
+}
+
+/// Builds the tree from the frequencies in `model` rather than the input's
+/// own. The tree is still stored in the output, so decoding needs nothing
+/// extra, but every byte of the input must have a code in it.
+fn model_tree(model: &Model, counts: &[usize; 256], settings: &Settings) -> HuffmanNode {
+    let (model, source) = match model {
+        Model::Corpus(corpus) => {
+            let sample = std::fs::read(corpus)
+                .unwrap_or_else(|err| exit_with(CompressError::read(Path::new(corpus), &err)));
+            let corpus_counts = huffman::count_frequencies(sample);
+            (corpus_counts, format!("model corpus `{corpus}`"))
+        }
+        Model::Json(path) => {
+            let model = models::load_model_json(Path::new(path)).unwrap_or_else(|err| {
+                eprintln!("error: cannot read model `{path}`: {err}");
+                std::process::exit(1);
+            });
+            (model, format!("model `{path}`"))
+        }
+        Model::Inline(counts) => (**counts, "the `--freq` weights".to_owned()),
+    };
+    let Some(tree) = build_tree(&model, settings) else {
+        eprintln!("error: {source} is empty");
+        std::process::exit(1);
+    };
+
+    let missing: Vec<_> = (0..=u8::MAX)
+        .filter(|&byte| counts[byte as usize] > 0 && model[byte as usize] == 0)
+        .map(|byte| format!("0x{byte:02x}"))
+        .collect();
+    if !missing.is_empty() {
+        eprintln!(
+            "error: `{}` contains bytes that never occur in {source}: {}",
+            settings.input,
+            missing.join(", ")
+        );
+        std::process::exit(1);
+    }
+
+    tree
+}
+
+/// Decodes every member of `input` one after the other, at most
+/// `max_output` bytes in all. Of the changes a member's [`Flags`] record,
+/// those also set in `restore` are undone.
+///
+/// The span of each member decoded in full is pushed onto `spans`.
+///
+/// On failure, returns the error along with the members before the damaged
+/// one and whatever [`format::decode_best_effort`] salvages of it.
+fn decode(
+    input: &[u8],
+    restore: Flags,
+    max_output: usize,
+    spans: &mut Vec<MemberSpan>,
+) -> Result<Vec<u8>, (Vec<u8>, DecodeError)> {
+    let mut output = Vec::new();
+    let whole = input;
+    let mut input = input;
+    loop {
+        let options = DecodeOptions::new().with_max_output(max_output.saturating_sub(output.len()));
+        let member = format::peek_header(input)
+            .and_then(|(header, _)| Ok((header.flags, format::decode_first(input, &options)?)));
+        let (flags, (mut decoded, rest)) = match member {
+            Ok(member) => member,
+            Err(err) => {
+                let (mut partial, _) = format::decode_best_effort(input);
+                partial.truncate(max_output.saturating_sub(output.len()));
+                output.extend(partial);
+                return Err((output, err));
+            }
+        };
+
+        if restore.normalized_newlines && flags.normalized_newlines {
+            decoded = text::restore_newlines(&decoded);
+        }
+        if restore.stripped_bom && flags.stripped_bom {
+            decoded.splice(0..0, *text::BOM);
+        }
+        let start = output.len();
+        output.extend(decoded);
+        spans.push(MemberSpan {
+            compressed: whole.len() - input.len()..whole.len() - rest.len(),
+            decoded: start..output.len(),
+        });
+
+        input = rest;
+        if input.is_empty() {
+            break;
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(env_opts: &str, args: &str) -> Result<Settings, String> {
+        let split = |line: &str| {
+            line.split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+
+        Settings::parse_from(split(env_opts).into_iter(), split(args).into_iter())
+    }
+
+    #[test]
+    fn command_line_overrides_env_opts() {
+        let settings = parse(
+            "-q -o env.huff --checksum none",
+            "--checksum crc32 -o cli.huff in",
+        )
+        .unwrap();
+
+        assert!(settings.quiet);
+        assert_eq!(settings.output.as_deref(), Some("cli.huff"));
+        assert_eq!(settings.encode_options.checksum(), Checksum::Crc32);
+        assert_eq!(settings.input, "in");
+
+        let settings = parse("-o env.huff", "in").unwrap();
+        assert_eq!(settings.output.as_deref(), Some("env.huff"));
+    }
+
+    #[test]
+    fn recompressing_picks_a_better_mode() {
+        let text = "abracadabra ".repeat(8);
+        let stored = format::with_flags(
+            format::encode_stored(text.as_bytes(), Checksum::Crc32),
+            Flags {
+                normalized_newlines: true,
+                stripped_bom: false,
+            },
+        );
+
+        let settings = parse("", "-q --recompress in.huff").unwrap();
+        let recompressed = recompress(&stored, &settings).unwrap();
+        let (header, _) = format::peek_header(&recompressed).unwrap();
+
+        assert_eq!(header.mode, Mode::Huffman);
+        assert!(header.flags.normalized_newlines);
+        assert!(recompressed.len() < stored.len());
+        assert_eq!(format::decode(&recompressed).unwrap(), text.as_bytes());
+    }
+
+    #[test]
+    fn builds_the_tree_from_inline_weights() {
+        let settings = parse(
+            "",
+            "-q --no-cache --freq 97:100 --freq 98:50 --freq 10:1 in",
+        )
+        .unwrap();
+        let mut model = [0; 256];
+        model[b'a' as usize] = 100;
+        model[b'b' as usize] = 50;
+        model[b'\n' as usize] = 1;
+
+        let encoded = encode(b"abba\n", &settings);
+        assert_eq!(
+            encoded,
+            format::encode_with_model(b"abba\n", &model, Checksum::Crc32).unwrap()
+        );
+        assert_eq!(format::decode(&encoded).unwrap(), b"abba\n");
+
+        assert!(parse("", "--freq 256:1 in").is_err());
+        assert!(parse("", "--freq 97 in").is_err());
+        assert!(parse("", "--freq 97:1 --freq 97:2 in").is_err());
+        assert!(parse("", "--freq 97:1 --model-json model.json in").is_err());
+    }
+
+    #[test]
+    fn env_opts_only_hold_flags() {
+        assert!(parse("in", "other").is_err());
+        // `-o` at the end of the variable can't take its value from the
+        // command line
+        assert!(parse("-o", "in").is_err());
+        assert!(parse("", "-o a -o b in").is_err());
+    }
+
+    #[test]
+    fn flags_are_checked_against_the_command() {
+        assert_eq!(parse("", "-d in.huff").unwrap().command, Command::Decode);
+        assert_eq!(parse("", "in").unwrap().command, Command::Encode);
+        assert!(parse("", "-d --inspect in.huff").is_err());
+        // repeating the same command is harmless
+        assert!(parse("", "-d -d in.huff").is_ok());
+
+        assert!(parse("", "-d --hex in.huff").is_ok());
+        assert!(parse("", "--hex in").is_err());
+        assert!(parse("", "--bits --hex in").is_err());
+        assert!(parse("", "--name x in").is_ok());
+        assert!(parse("", "-d --name x in.huff").is_err());
+        assert!(parse("", "--fixed --nibble in").is_err());
+        assert!(parse("", "--self-test in").is_err());
+    }
 }
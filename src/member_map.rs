@@ -0,0 +1,57 @@
+//! Where each member of a decoded file came from, for `--map-file`.
+
+use std::fmt::Write;
+use std::ops::Range;
+
+/// The bytes a member took up in the compressed input and the ones it
+/// decoded to in the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberSpan {
+    pub compressed: Range<usize>,
+    pub decoded: Range<usize>,
+}
+
+/// One line per member, numbered from 1, with half-open byte ranges:
+///
+/// ```text
+/// member 1: compressed 0..57, decoded 0..120
+/// ```
+pub fn render(spans: &[MemberSpan]) -> String {
+    let mut out = String::new();
+    for (n, span) in spans.iter().enumerate() {
+        writeln!(
+            out,
+            "member {}: compressed {:?}, decoded {:?}",
+            n + 1,
+            span.compressed,
+            span.decoded
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_members_from_one() {
+        let spans = [
+            MemberSpan {
+                compressed: 0..57,
+                decoded: 0..120,
+            },
+            MemberSpan {
+                compressed: 57..80,
+                decoded: 120..130,
+            },
+        ];
+
+        assert_eq!(
+            render(&spans),
+            "member 1: compressed 0..57, decoded 0..120\n\
+             member 2: compressed 57..80, decoded 120..130\n"
+        );
+    }
+}
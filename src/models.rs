@@ -0,0 +1,216 @@
+//! Built-in frequency models for compressing without counting first, e.g.
+//! with [`format::encode_with_model`](crate::format::encode_with_model).
+//!
+//! Every byte value gets a count of at least 1, so a tree built from any of
+//! these models can encode any input; bytes the model doesn't expect just
+//! get long codes.
+//!
+//! Models can also be kept in JSON files, as an object mapping byte values
+//! to counts, e.g. `{"97": 812, "98": 149}`. Bytes that aren't listed have
+//! a count of 0.
+
+use std::io;
+use std::path::Path;
+
+/// English letter frequencies in thousandths of a percent, `a` to `z`, as
+/// published by Lewand in *Cryptological Mathematics* (2000).
+const LETTER_FREQUENCIES: [usize; 26] = [
+    8167, 1492, 2782, 4253, 12702, 2228, 2015, 6094, 6966, 153, 772, 4025, 2406, 6749, 7507, 1929,
+    95, 5987, 6327, 9056, 2758, 978, 2360, 150, 1974, 74,
+];
+
+/// A model with no preference for any byte, which gives every byte an 8-bit
+/// code.
+pub fn flat_model() -> [usize; 256] {
+    [1; 256]
+}
+
+/// Lowercase letters and spaces, as in normalized or lowercased text.
+///
+/// Letters follow Lewand's published English frequencies, per 100,000
+/// letters. The space count assumes the average English word length of
+/// about 4.7 letters.
+pub fn ascii_lowercase_model() -> [usize; 256] {
+    let mut model = flat_model();
+    for (letter, &count) in (b'a'..=b'z').zip(&LETTER_FREQUENCIES) {
+        model[letter as usize] = count;
+    }
+    model[b' ' as usize] = 21_000;
+
+    model
+}
+
+/// Mixed-case English prose with punctuation, digits and line breaks.
+///
+/// Builds on [`ascii_lowercase_model`]. Lewand's figures only cover letters,
+/// so the rates of capitals (about one letter in thirty), punctuation,
+/// digits and newlines are rough estimates for ordinary prose rather than
+/// counts from a corpus.
+pub fn english_text_model() -> [usize; 256] {
+    let mut model = ascii_lowercase_model();
+    for (letter, &count) in (b'A'..=b'Z').zip(&LETTER_FREQUENCIES) {
+        model[letter as usize] = (count / 30).max(1);
+    }
+    for digit in b'0'..=b'9' {
+        model[digit as usize] = 100;
+    }
+
+    model[b' ' as usize] = 19_000;
+    for (byte, count) in [
+        (b'\n', 2_000),
+        (b',', 1_200),
+        (b'.', 1_000),
+        (b'\'', 300),
+        (b'"', 300),
+        (b'-', 200),
+        (b'?', 100),
+        (b'!', 100),
+        (b';', 100),
+        (b':', 100),
+        (b'(', 50),
+        (b')', 50),
+    ] {
+        model[byte as usize] = count;
+    }
+
+    model
+}
+
+/// Reads a model from a JSON file; see [`parse_model_json`].
+pub fn load_model_json(path: &Path) -> io::Result<[usize; 256]> {
+    parse_model_json(&std::fs::read_to_string(path)?)
+}
+
+/// Parses a JSON object of byte values, `"0"` to `"255"`, to counts. Fails
+/// with [`io::ErrorKind::InvalidData`] on anything else, including a byte
+/// listed twice.
+pub fn parse_model_json(json: &str) -> io::Result<[usize; 256]> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let mut model = [0; 256];
+    let mut seen = [false; 256];
+    let mut rest = json
+        .trim_start()
+        .strip_prefix('{')
+        .ok_or_else(|| invalid("a model must be a JSON object".into()))?;
+
+    rest = rest.trim_start();
+    if let Some(after) = rest.strip_prefix('}') {
+        rest = after;
+    } else {
+        loop {
+            let (key, after) = rest
+                .strip_prefix('"')
+                .and_then(|key| key.split_once('"'))
+                .ok_or_else(|| invalid("expected a quoted byte value".into()))?;
+            let byte: u8 = key
+                .parse()
+                .map_err(|_| invalid(format!("`{key}` is not a byte value from 0 to 255")))?;
+            if std::mem::replace(&mut seen[byte as usize], true) {
+                return Err(invalid(format!("byte {byte} is listed twice")));
+            }
+
+            let after = after
+                .trim_start()
+                .strip_prefix(':')
+                .ok_or_else(|| invalid(format!("expected `:` after \"{key}\"")))?
+                .trim_start();
+            let digits = after
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(after.len());
+            model[byte as usize] = after[..digits]
+                .parse()
+                .map_err(|_| invalid(format!("the count for byte {byte} is not a whole number")))?;
+
+            rest = after[digits..].trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after.trim_start();
+            } else if let Some(after) = rest.strip_prefix('}') {
+                rest = after;
+                break;
+            } else {
+                return Err(invalid("expected `,` or `}` after a count".into()));
+            }
+        }
+    }
+
+    if !rest.trim().is_empty() {
+        return Err(invalid("unexpected data after the model".into()));
+    }
+
+    Ok(model)
+}
+
+/// The byte values that occur in `data` but have a count of zero in
+/// `model`, in ascending order, which a tree built from `model` can't be
+/// relied on to encode.
+pub fn missing_symbols(data: &[u8], model: &[usize; 256]) -> Vec<u8> {
+    let mut present = [false; 256];
+    for &byte in data {
+        present[byte as usize] = true;
+    }
+
+    (0..=u8::MAX)
+        .filter(|&byte| present[byte as usize] && model[byte as usize] == 0)
+        .collect()
+}
+
+/// Writes the nonzero counts of `model` as JSON that [`parse_model_json`]
+/// reads back, one byte per line.
+pub fn model_to_json(model: &[usize; 256]) -> String {
+    let entries: Vec<_> = (0..=u8::MAX)
+        .zip(model)
+        .filter(|&(_, &count)| count > 0)
+        .map(|(byte, count)| format!("  \"{byte}\": {count}"))
+        .collect();
+
+    if entries.is_empty() {
+        "{}\n".into()
+    } else {
+        format!("{{\n{}\n}}\n", entries.join(",\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_models_round_trip() {
+        for model in [english_text_model(), [0; 256]] {
+            assert_eq!(parse_model_json(&model_to_json(&model)).unwrap(), model);
+        }
+
+        let model = parse_model_json(r#" { "97" : 3, "10":1 } "#).unwrap();
+        assert_eq!((model[b'a' as usize], model[b'\n' as usize]), (3, 1));
+        assert_eq!(model.iter().sum::<usize>(), 4);
+    }
+
+    #[test]
+    fn lists_the_bytes_a_model_lacks() {
+        let mut model = ascii_lowercase_model();
+        model[b'!' as usize] = 0;
+
+        assert_eq!(missing_symbols(b"hello, world!", &model), b"!");
+        assert_eq!(missing_symbols(b"hello", &model), b"");
+        assert_eq!(missing_symbols(b"aab", &[0; 256]), b"ab");
+    }
+
+    #[test]
+    fn rejects_invalid_json_models() {
+        for json in [
+            "",
+            "[]",
+            r#"{"256": 1}"#,
+            r#"{"a": 1}"#,
+            r#"{"97": -1}"#,
+            r#"{"97": 1.5}"#,
+            r#"{"97": 1, "97": 2}"#,
+            r#"{"97": 1,}"#,
+            r#"{"97": 1} {}"#,
+        ] {
+            let err = parse_model_json(json).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{json}");
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! A minimal percentage display on stderr for long compressions.
+
+use std::io::IsTerminal;
+
+/// Inputs smaller than this are done too quickly to need a display.
+pub const THRESHOLD: usize = 64 * 1024 * 1024;
+
+pub struct Progress {
+    total: usize,
+    enabled: bool,
+}
+
+impl Progress {
+    /// Only shows anything for inputs of at least [`THRESHOLD`] bytes, and
+    /// only when stderr is a terminal.
+    pub fn new(total: usize, quiet: bool) -> Self {
+        Self {
+            total,
+            enabled: !quiet && total >= THRESHOLD && std::io::stderr().is_terminal(),
+        }
+    }
+
+    /// Passes `items` through unchanged, redrawing `stage: NN%` as they are
+    /// consumed. `items` should yield the `total` the display was made with.
+    pub fn track<I: Iterator>(&self, stage: &'static str, items: I) -> Tracked<I> {
+        Tracked {
+            items,
+            meter: self.meter(stage),
+        }
+    }
+
+    /// A display for `stage` that is told how far along it is, for work
+    /// that isn't an iterator over the `total` items.
+    pub fn meter(&self, stage: &'static str) -> Meter {
+        Meter {
+            stage,
+            enabled: self.enabled,
+            total: self.total,
+            done: 0,
+            next_redraw: 0,
+            percent: 0,
+        }
+    }
+}
+
+pub struct Tracked<I> {
+    items: I,
+    meter: Meter,
+}
+
+impl<I: Iterator> Iterator for Tracked<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.items.next();
+        match item {
+            Some(_) => self.meter.advance(1),
+            None => self.meter.finish(),
+        }
+        item
+    }
+}
+
+pub struct Meter {
+    stage: &'static str,
+    enabled: bool,
+    total: usize,
+    done: usize,
+    next_redraw: usize,
+    percent: usize,
+}
+
+impl Meter {
+    /// Counts `n` more items as done, redrawing the percentage if that
+    /// passed the next one.
+    pub fn advance(&mut self, n: usize) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut shown = None;
+        while self.next_redraw < self.done + n && self.percent <= 100 {
+            shown = Some(self.percent);
+            self.percent += 1;
+            self.next_redraw = self.total * self.percent / 100;
+        }
+        if let Some(percent) = shown {
+            eprint!("\r{}: {percent:>3}%", self.stage);
+        }
+        self.done += n;
+    }
+
+    /// Replaces the percentage with `stage: done`, once, if one was shown.
+    pub fn finish(&mut self) {
+        if self.enabled && self.done > 0 {
+            eprintln!("\r{}: done", self.stage);
+            self.enabled = false;
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Round trips over built-in samples, for `--self-test`.
+
+use huffman_encoding::{compress, decompress};
+
+/// Small fixed inputs covering each way a file can be written.
+fn samples() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        (
+            "text",
+            b"It was the best of times, it was the worst of times.\n".repeat(4),
+        ),
+        ("binary", (0..=u8::MAX).cycle().take(1000).collect()),
+        ("single symbol", vec![b'a'; 100]),
+        ("empty", Vec::new()),
+    ]
+}
+
+/// Compresses and decompresses every sample, printing a line per sample to
+/// stdout. Returns whether all of them came back unchanged.
+pub fn run() -> bool {
+    let mut passed = true;
+    for (name, data) in samples() {
+        let decoded = decompress(&compress(&data));
+
+        match decoded {
+            Ok(decoded) if decoded == data => println!("{name}: ok"),
+            Ok(_) => {
+                println!("{name}: FAILED, decoded bytes differ");
+                passed = false;
+            }
+            Err(err) => {
+                println!("{name}: FAILED, {err}");
+                passed = false;
+            }
+        }
+    }
+
+    passed
+}
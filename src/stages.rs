@@ -0,0 +1,34 @@
+//! Per-stage timings on stderr, for `--verbose`.
+
+use std::time::{Duration, Instant};
+
+pub struct Stages {
+    enabled: bool,
+}
+
+impl Stages {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+
+    /// Runs `f`, then prints how long it took as `stage: 1.23ms` if timings
+    /// are enabled.
+    pub fn time<T>(&self, stage: &str, f: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return f();
+        }
+
+        let start = Instant::now();
+        let result = f();
+        self.report(stage, start.elapsed());
+        result
+    }
+
+    /// Prints that `stage` took `elapsed`, for stages [`Self::time`] can't
+    /// wrap in a closure.
+    pub fn report(&self, stage: &str, elapsed: Duration) {
+        if self.enabled {
+            eprintln!("{stage}: {elapsed:.2?}");
+        }
+    }
+}
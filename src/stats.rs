@@ -0,0 +1,114 @@
+//! What the encoder did with an input, for `--stats`.
+
+use std::fmt::Write;
+
+use huffman_encoding::format::{self, Mode};
+use huffman_encoding::huffman::{self, HuffmanNode, TreeLayout};
+
+use crate::inspect::shown;
+
+/// Describes how `input` became `encoded`: both sizes and their ratio, how
+/// many distinct bytes `input` has and, if `encoded` has a single tree, its
+/// depth and the code length of each of its symbols.
+pub fn stats(input: &[u8], encoded: &[u8]) -> String {
+    let mut out = String::new();
+    let counts = huffman::count_frequencies(input.iter().copied());
+    let distinct = counts.iter().filter(|&&count| count > 0).count();
+
+    writeln!(out, "original size: {} bytes", input.len()).unwrap();
+    writeln!(
+        out,
+        "compressed size: {} bytes, including the header and any tree",
+        encoded.len()
+    )
+    .unwrap();
+    if input.is_empty() {
+        // there is nothing for the output to be a share of
+        writeln!(out, "ratio: n/a").unwrap();
+    } else {
+        let ratio = 100.0 * encoded.len() as f64 / input.len() as f64;
+        writeln!(out, "ratio: {ratio:.1}%").unwrap();
+    }
+    writeln!(out, "distinct symbols: {distinct}").unwrap();
+
+    let Some((mode, tree)) = tree(encoded) else {
+        return out;
+    };
+    let depth = tree.get_depth();
+    writeln!(out, "tree depth: {depth}, longest code {} bits", depth - 1).unwrap();
+    let what = if mode == Mode::Nibble {
+        " of nibbles"
+    } else {
+        ""
+    };
+    writeln!(out, "code lengths{what}:").unwrap();
+    for (symbol, &len) in (0..=u8::MAX).zip(&tree.code_lengths()) {
+        if len > 0 {
+            writeln!(out, "  0x{symbol:02x} {:<6} {len}", shown(symbol)).unwrap();
+        }
+    }
+
+    out
+}
+
+/// The mode and the one tree of `encoded`, unless it was stored or coded
+/// with a tree per context.
+fn tree(encoded: &[u8]) -> Option<(Mode, HuffmanNode)> {
+    let (header, stream) = format::peek_header(encoded).ok()?;
+    let (stream, layout) = match header.mode {
+        Mode::Huffman | Mode::Nibble | Mode::Lines => (stream, TreeLayout::Pairs),
+        Mode::LengthTable => (stream, TreeLayout::LengthTable),
+        Mode::Escaped => (stream.get(1..)?, TreeLayout::Pairs),
+        Mode::Stored | Mode::Order1 => return None,
+    };
+    let (_, tree) = HuffmanNode::peek_tree_with_layout(stream, layout).ok()?;
+
+    Some((header.mode, tree))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use huffman_encoding::checksum::Checksum;
+
+    #[test]
+    fn reports_sizes_and_code_lengths() {
+        let input = b"aaaaaaaabbbbcc\n";
+        let encoded = format::encode(input, Checksum::Crc32).unwrap();
+
+        assert_eq!(
+            stats(input, &encoded),
+            "original size: 15 bytes\n\
+             compressed size: 35 bytes, including the header and any tree\n\
+             ratio: 233.3%\n\
+             distinct symbols: 4\n\
+             tree depth: 4, longest code 3 bits\n\
+             code lengths:\n  \
+               0x0a '\\n'   3\n  \
+               0x61 'a'    1\n  \
+               0x62 'b'    2\n  \
+               0x63 'c'    3\n"
+        );
+    }
+
+    #[test]
+    fn stored_input_has_no_tree_to_report() {
+        let encoded = format::encode_stored(b"abc", Checksum::None);
+
+        assert_eq!(
+            stats(b"abc", &encoded),
+            "original size: 3 bytes\n\
+             compressed size: 21 bytes, including the header and any tree\n\
+             ratio: 700.0%\n\
+             distinct symbols: 3\n"
+        );
+    }
+
+    #[test]
+    fn empty_input_has_no_ratio() {
+        let encoded = format::encode(b"", Checksum::None).unwrap();
+
+        let report = stats(b"", &encoded);
+        assert!(report.contains("\nratio: n/a\n"), "{report}");
+    }
+}
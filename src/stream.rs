@@ -0,0 +1,718 @@
+//! Encoding and decoding through [`Read`] and [`Write`], for files too big
+//! to hold in memory.
+//!
+//! [`encode_stream`] reads its input twice, once to count its bytes and
+//! once to code them, and writes what [`format::encode`] would have. Only
+//! a buffer of the input and of the output is held at a time.
+//!
+//! [`decode_stream`] decodes [`Mode::Huffman`] and [`Mode::Stored`]
+//! members, the ones `encode_stream` writes, as they are read. The other
+//! modes need their whole stream to decode, so from the first member in one
+//! of them on the rest of the input is read into memory and decoded with
+//! [`format::decode_first`].
+
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+
+use crate::checksum::{Checksum, Digester};
+use crate::code_table::PrefixTable;
+use crate::error::{DecodeError, EncodeError};
+use crate::format::{self, DecodeOptions, Header, Mode};
+use crate::huffman::{BitWriter, HuffmanNode};
+
+/// How much input is read, and output written, at a time, unless the
+/// caller picks a `buffer_len`.
+pub const DEFAULT_BUFFER_LEN: usize = 64 * 1024;
+
+/// What [`encode_stream`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamStats {
+    /// The bytes read in each pass over the input.
+    pub read: u64,
+    pub written: u64,
+    /// [`Mode::Stored`] if the input wouldn't get any smaller, as
+    /// [`format::is_incompressible`] decides.
+    pub mode: Mode,
+}
+
+/// The stages of [`encode_stream_with`], in the order they happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// The first pass over the input.
+    Counting,
+    BuildingTree,
+    /// The second pass, which writes the output as it goes.
+    Encoding,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Counting => "counting",
+            Self::BuildingTree => "building tree",
+            Self::Encoding => "encoding",
+        }
+    }
+}
+
+/// Watches [`encode_stream_with`] or [`decode_stream_with`] work, e.g. to
+/// show how far along it is, and can stop it. The methods do nothing, and
+/// never cancel, unless implemented.
+pub trait Observer {
+    /// `stage` starts, and the one before it is done. Only encoding has
+    /// stages.
+    fn start(&mut self, _stage: Stage) {}
+
+    /// `len` more bytes of input went through the current stage, or, when
+    /// decoding, were decoded.
+    fn advance(&mut self, _len: usize) {}
+
+    /// Whether to stop as soon as possible. It is asked after every
+    /// buffer, so a [`Cancelled`](EncodeError::Cancelled) error comes at
+    /// most a buffer later.
+    fn cancelled(&mut self) -> bool {
+        false
+    }
+}
+
+impl Observer for () {}
+
+/// Compresses `input`, from where it is to its end, into `output`.
+///
+/// The input is read a second time after seeking back, so it must not
+/// change in between; if it visibly did, by its length or by a byte the
+/// first pass didn't see, this fails with [`io::ErrorKind::InvalidData`]
+/// and what was written is of no use.
+pub fn encode_stream<R: Read + Seek, W: Write>(
+    input: R,
+    output: W,
+    checksum: Checksum,
+) -> io::Result<StreamStats> {
+    encode_stream_with(input, output, checksum, DEFAULT_BUFFER_LEN, &mut ())
+}
+
+/// [`encode_stream`], reading `buffer_len` bytes at a time and telling
+/// `observer` what it is doing. If the observer cancels, this fails with an
+/// [`io::Error`] wrapping [`EncodeError::Cancelled`], and what was written
+/// is of no use.
+///
+/// # Panics
+///
+/// If `buffer_len` is 0.
+pub fn encode_stream_with<R: Read + Seek, W: Write>(
+    mut input: R,
+    mut output: W,
+    checksum: Checksum,
+    buffer_len: usize,
+    observer: &mut impl Observer,
+) -> io::Result<StreamStats> {
+    assert!(buffer_len > 0, "the buffer must hold at least one byte");
+    observer.start(Stage::Counting);
+    let start = input.stream_position()?;
+    let mut buffer = vec![0; buffer_len];
+    let mut counts = [0_usize; 256];
+    let mut digester = checksum.digester();
+    let mut len = 0_u64;
+    loop {
+        let read = read_some(&mut input, &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        for &byte in &buffer[..read] {
+            counts[byte as usize] += 1;
+        }
+        digester.update(&buffer[..read]);
+        len += read as u64;
+        advance(observer, read)?;
+    }
+    input.seek(SeekFrom::Start(start))?;
+
+    observer.start(Stage::BuildingTree);
+    let digest = digester.finish();
+    let tree = match HuffmanNode::build_tree_from_counts(&counts) {
+        Some(tree) if !format::is_incompressible(&counts) => tree,
+        _ => {
+            observer.start(Stage::Encoding);
+            let mut written = 0;
+            let mut write = |bytes: &[u8]| {
+                written += bytes.len() as u64;
+                output.write_all(bytes)
+            };
+            write(&format::header_with_digest(&digest, checksum, Mode::Stored))?;
+            write(&len.to_le_bytes())?;
+            let mut left = len;
+            while left > 0 {
+                let want = left.min(buffer_len as u64) as usize;
+                let read = read_some(&mut input, &mut buffer[..want])?;
+                if read == 0 {
+                    return Err(changed_input());
+                }
+                write(&buffer[..read])?;
+                left -= read as u64;
+                advance(observer, read)?;
+            }
+            return Ok(StreamStats {
+                read: len,
+                written,
+                mode: Mode::Stored,
+            });
+        }
+    };
+
+    let mut lookup = [None; 256];
+    let codes = tree.codes();
+    for (symbol, code) in &codes {
+        lookup[*symbol as usize] = Some(&code[..]);
+    }
+
+    let mut written = 0;
+    let mut write = |bytes: &[u8]| {
+        written += bytes.len() as u64;
+        output.write_all(bytes)
+    };
+    write(&format::header_with_digest(
+        &digest,
+        checksum,
+        Mode::Huffman,
+    ))?;
+    write(&len.to_le_bytes())?;
+    write(&tree.serialize_tree_bytes())?;
+
+    observer.start(Stage::Encoding);
+    let mut bits = BitWriter::new();
+    let mut left = len;
+    loop {
+        let read = read_some(&mut input, &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        left = left.checked_sub(read as u64).ok_or_else(changed_input)?;
+        for &byte in &buffer[..read] {
+            bits.write_code(lookup[byte as usize].ok_or_else(changed_input)?);
+        }
+        write(&bits.take_whole_bytes())?;
+        advance(observer, read)?;
+    }
+    if left > 0 {
+        return Err(changed_input());
+    }
+    write(&bits.into_bytes())?;
+
+    Ok(StreamStats {
+        read: len,
+        written,
+        mode: Mode::Huffman,
+    })
+}
+
+/// Decompresses every member of `input` into `output` with the checks in
+/// `options`, returning how many bytes it wrote. It fails as
+/// [`format::decode_with`] does, with [`DecodeError::Io`] for a failed
+/// read or write, and [`DecodeOptions::with_max_output`] limits the
+/// members' total. What was written before a failure is kept, but it may
+/// not be what was compressed.
+pub fn decode_stream<R: Read, W: Write>(
+    input: R,
+    output: W,
+    options: &DecodeOptions,
+) -> Result<u64, DecodeError> {
+    decode_stream_with(input, output, options, DEFAULT_BUFFER_LEN, &mut ())
+}
+
+/// [`decode_stream`], reading and writing `buffer_len` bytes at a time and
+/// telling `observer` how much it decoded. If the observer cancels, this
+/// fails with [`DecodeError::Cancelled`]. Members in modes that are decoded
+/// in memory can only be cancelled before they start.
+///
+/// # Panics
+///
+/// If `buffer_len` is 0.
+pub fn decode_stream_with<R: Read, W: Write>(
+    input: R,
+    mut output: W,
+    options: &DecodeOptions,
+    buffer_len: usize,
+    observer: &mut impl Observer,
+) -> Result<u64, DecodeError> {
+    assert!(buffer_len > 0, "the buffer must hold at least one byte");
+    let mut input = BufReader::with_capacity(buffer_len, input);
+    let mut written = 0;
+    loop {
+        let header_bytes = format::read_header(&mut input)?;
+        let (header, _) = format::peek_header(&header_bytes)?;
+        let limit = options.max_output() as u64 - written;
+        written += match header.mode {
+            Mode::Stored | Mode::Huffman => {
+                let len = u64::from_le_bytes(read_array(&mut input)?);
+                if len > limit {
+                    return Err(DecodeError::OutputTooLarge);
+                }
+                let mut out = Output::new(&mut output, header.checksum, buffer_len, observer);
+                if header.mode == Mode::Stored {
+                    copy_stored(&mut input, &mut out, len)?;
+                } else {
+                    decode_coded(&mut input, &mut out, len)?;
+                }
+                out.finish(&header, options)?;
+                len
+            }
+            _ => {
+                if observer.cancelled() {
+                    return Err(DecodeError::Cancelled);
+                }
+                let mut rest = header_bytes.clone();
+                input.read_to_end(&mut rest)?;
+                let options = options.clone().with_max_output(limit as usize);
+                return decode_in_memory(&rest, &mut output, &options).map(|n| written + n);
+            }
+        };
+        if input.fill_buf()?.is_empty() {
+            return Ok(written);
+        }
+    }
+}
+
+/// Decodes the members held in `input` one after another.
+fn decode_in_memory<W: Write>(
+    mut input: &[u8],
+    output: &mut W,
+    options: &DecodeOptions,
+) -> Result<u64, DecodeError> {
+    let mut written = 0;
+    while !input.is_empty() {
+        let limit = options.max_output() - written;
+        let (data, rest) = format::decode_first(input, &options.clone().with_max_output(limit))?;
+        output.write_all(&data)?;
+        written += data.len();
+        input = rest;
+    }
+    Ok(written as u64)
+}
+
+/// Copies the `len` bytes of a [`Mode::Stored`] member.
+fn copy_stored<R: Read, W: Write>(
+    input: &mut R,
+    output: &mut Output<W>,
+    len: u64,
+) -> Result<(), DecodeError> {
+    let mut left = len;
+    let mut buffer = vec![0; output.buffer_len.min(len as usize)];
+    while left > 0 {
+        let want = left.min(buffer.len() as u64) as usize;
+        let read = read_some(input, &mut buffer[..want])?;
+        if read == 0 {
+            return Err(DecodeError::TruncatedPayload);
+        }
+        output.extend(&buffer[..read])?;
+        left -= read as u64;
+    }
+    Ok(())
+}
+
+/// Decodes the tree and `len` symbols of a [`Mode::Huffman`] member. Whole
+/// codes are looked up in a [`PrefixTable`] while the buffer holds enough
+/// bits for them, and the tree is walked bit by bit across its end.
+fn decode_coded<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut Output<W>,
+    len: u64,
+) -> Result<(), DecodeError> {
+    // (length, symbol) pairs up to a zero length, and no more pairs than
+    // there are symbols, after which decoding the tree fails anyway
+    let mut tree_bytes = Vec::new();
+    for _ in 0..=256 {
+        let [code_len] = read_array(input)?;
+        tree_bytes.push(code_len);
+        if code_len == 0 {
+            break;
+        }
+        tree_bytes.extend(read_array::<1>(input)?);
+    }
+    let tree = HuffmanNode::decode_tree_bytes(&tree_bytes)?;
+    let table = PrefixTable::new(&tree);
+
+    let mut bits = StreamBits {
+        input,
+        used: 0,
+        error: None,
+    };
+    for _ in 0..len {
+        let symbol = match bits
+            .peek(table.bits())?
+            .and_then(|window| table.decode_symbol(window))
+        {
+            Some((symbol, code_len)) => {
+                bits.consume(code_len);
+                symbol
+            }
+            // the code runs past the buffer, or is too long for the table
+            None => {
+                let symbol = tree.decode_item(&mut bits);
+                if let Some(err) = bits.error.take() {
+                    return Err(err.into());
+                }
+                symbol?
+            }
+        };
+        output.push(symbol)?;
+    }
+    bits.skip_padding();
+    Ok(())
+}
+
+/// Decoded bytes on their way to the output, a buffer at a time, and their
+/// digest so far.
+struct Output<'a, W> {
+    output: &'a mut W,
+    buffer: Vec<u8>,
+    /// How full `buffer` gets before it is written.
+    buffer_len: usize,
+    digester: Digester,
+    /// Told about each write, and asked whether to go on after it.
+    observer: &'a mut dyn Observer,
+}
+
+impl<'a, W: Write> Output<'a, W> {
+    fn new(
+        output: &'a mut W,
+        checksum: Checksum,
+        buffer_len: usize,
+        observer: &'a mut dyn Observer,
+    ) -> Self {
+        Self {
+            output,
+            buffer: Vec::with_capacity(buffer_len),
+            buffer_len,
+            digester: checksum.digester(),
+            observer,
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), DecodeError> {
+        self.buffer.push(byte);
+        if self.buffer.len() == self.buffer_len {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn extend(&mut self, bytes: &[u8]) -> Result<(), DecodeError> {
+        self.flush()?;
+        self.digester.update(bytes);
+        self.output.write_all(bytes)?;
+        self.written(bytes.len())
+    }
+
+    fn flush(&mut self) -> Result<(), DecodeError> {
+        self.digester.update(&self.buffer);
+        self.output.write_all(&self.buffer)?;
+        let len = self.buffer.len();
+        self.buffer.clear();
+        self.written(len)
+    }
+
+    fn written(&mut self, len: usize) -> Result<(), DecodeError> {
+        self.observer.advance(len);
+        if self.observer.cancelled() {
+            return Err(DecodeError::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Writes what is left and checks the member's digest.
+    fn finish(mut self, header: &Header, options: &DecodeOptions) -> Result<(), DecodeError> {
+        self.flush()?;
+        if options.verify_checksum() && self.digester.finish() != header.digest {
+            return Err(DecodeError::ChecksumMismatch);
+        }
+        Ok(())
+    }
+}
+
+/// The bits of a [`BufRead`], most significant first, the order the codes
+/// are packed in. Bytes are only consumed once all their bits are, so none
+/// of the next member is. A failed read ends them and is kept in `error`.
+struct StreamBits<R> {
+    input: R,
+    /// How many bits of the first buffered byte are already used.
+    used: usize,
+    error: Option<io::Error>,
+}
+
+impl<R: BufRead> StreamBits<R> {
+    /// The next `n` bits, or `None` if the buffer holds fewer than 64.
+    fn peek(&mut self, n: usize) -> io::Result<Option<usize>> {
+        let buffer = self.input.fill_buf()?;
+        let Some(bytes) = buffer.first_chunk::<8>().filter(|_| n > 0) else {
+            return Ok(None);
+        };
+        let window = u64::from_be_bytes(*bytes) << self.used;
+        Ok(Some((window >> (64 - n)) as usize))
+    }
+
+    /// Uses `n` bits, which [`Self::peek`] saw.
+    fn consume(&mut self, n: usize) {
+        let used = self.used + n;
+        self.input.consume(used / 8);
+        self.used = used % 8;
+    }
+
+    /// Consumes the rest of a partly used byte, the padding after the last
+    /// code.
+    fn skip_padding(&mut self) {
+        if self.used > 0 {
+            self.input.consume(1);
+            self.used = 0;
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for StreamBits<R> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        let byte = match self.input.fill_buf() {
+            Ok(buffer) => *buffer.first()?,
+            Err(err) => {
+                self.error = Some(err);
+                return None;
+            }
+        };
+        let bit = byte >> (7 - self.used) & 1 == 1;
+        self.consume(1);
+        Some(bit)
+    }
+}
+
+/// The next `N` bytes, which belong to the length or the tree, so that a
+/// member that ends before them has a truncated header.
+fn read_array<const N: usize>(input: &mut impl Read) -> Result<[u8; N], DecodeError> {
+    let mut bytes = [0; N];
+    input
+        .read_exact(&mut bytes)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => DecodeError::TruncatedHeader,
+            kind => DecodeError::Io(kind),
+        })?;
+    Ok(bytes)
+}
+
+/// Reads into `buffer`, trying again if interrupted, and returns how much
+/// it read, 0 only at the end of the input.
+fn read_some(input: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match input.read(buffer) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Tells `observer` that `len` more bytes were encoded, failing if it
+/// cancels.
+fn advance(observer: &mut impl Observer, len: usize) -> io::Result<()> {
+    observer.advance(len);
+    if observer.cancelled() {
+        return Err(io::Error::other(EncodeError::Cancelled));
+    }
+    Ok(())
+}
+
+fn changed_input() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "the input changed between the two passes over it",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn encoded(data: &[u8], checksum: Checksum) -> Vec<u8> {
+        let mut out = Vec::new();
+        let stats = encode_stream(Cursor::new(data), &mut out, checksum).unwrap();
+        assert_eq!(stats.read, data.len() as u64);
+        assert_eq!(stats.written, out.len() as u64);
+        out
+    }
+
+    fn decoded(input: &[u8], options: &DecodeOptions) -> Result<Vec<u8>, DecodeError> {
+        let mut out = Vec::new();
+        let written = decode_stream(input, &mut out, options)?;
+        assert_eq!(written, out.len() as u64);
+        Ok(out)
+    }
+
+    #[test]
+    fn writes_what_encode_does() {
+        // longer than the buffer, so the codes cross from one to the next
+        let text = include_bytes!("../nyi.txt").repeat(DEFAULT_BUFFER_LEN / 1000 + 3);
+        let random: Vec<u8> = (0..DEFAULT_BUFFER_LEN as u32 * 2)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+
+        for &checksum in Checksum::ALL {
+            for data in [&text[..], &random, b"a", b""] {
+                let expected = format::encode(data, checksum).unwrap();
+                assert_eq!(encoded(data, checksum), expected);
+                assert_eq!(decoded(&expected, &DecodeOptions::new()).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn every_buffer_length_gives_the_same_output() {
+        let text = include_bytes!("../nyi.txt").repeat(3);
+        let expected = format::encode(&text, Checksum::Crc32).unwrap();
+
+        for buffer_len in [1, 7, 8, 100, DEFAULT_BUFFER_LEN] {
+            let mut out = Vec::new();
+            let input = Cursor::new(&text);
+            encode_stream_with(input, &mut out, Checksum::Crc32, buffer_len, &mut ()).unwrap();
+            assert_eq!(out, expected, "{buffer_len}");
+
+            let mut decoded = Vec::new();
+            let options = DecodeOptions::new();
+            decode_stream_with(&out[..], &mut decoded, &options, buffer_len, &mut ()).unwrap();
+            assert_eq!(decoded, text, "{buffer_len}");
+        }
+    }
+
+    #[test]
+    fn decodes_members_of_every_mode() {
+        let text = include_bytes!("../nyi.txt");
+        let mut input = format::encode(text, Checksum::Crc32).unwrap();
+        input.extend(format::encode_stored(b"stored", Checksum::None));
+        input.extend(format::encode_order1(text, Checksum::Crc32).unwrap());
+        input.extend(format::encode(b"last", Checksum::Crc32).unwrap());
+
+        assert_eq!(
+            decoded(&input, &DecodeOptions::new()).unwrap(),
+            [&text[..], b"stored", text, b"last"].concat()
+        );
+    }
+
+    #[test]
+    fn decodes_codes_longer_than_the_prefix_table() {
+        // doubling counts give codes of up to 15 bits
+        let skewed: Vec<u8> = (0..16_u8)
+            .flat_map(|byte| std::iter::repeat_n(byte, 1 << byte))
+            .collect();
+        let member = format::encode(&skewed, Checksum::Crc32).unwrap();
+        let input = [&member[..], &member].concat();
+
+        assert_eq!(
+            decoded(&input, &DecodeOptions::new()).unwrap(),
+            [&skewed[..], &skewed].concat()
+        );
+    }
+
+    #[test]
+    fn fails_like_decoding_in_memory() {
+        let text = include_bytes!("../nyi.txt");
+        let encoded = format::encode(text, Checksum::Crc32).unwrap();
+        let options = DecodeOptions::new();
+
+        let mut flipped = encoded.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        for broken in [
+            &encoded[..encoded.len() - 1],
+            &encoded[..12],
+            &flipped,
+            b"HUFX",
+        ] {
+            assert_eq!(
+                decoded(broken, &options),
+                format::decode_with(broken, &options)
+            );
+        }
+
+        let limited = options.with_max_output(text.len() - 1);
+        assert_eq!(
+            decoded(&encoded, &limited),
+            Err(DecodeError::OutputTooLarge)
+        );
+    }
+
+    #[test]
+    fn stops_a_buffer_after_being_cancelled() {
+        /// Cancels once `left` more bytes went through.
+        struct CancelAfter {
+            left: usize,
+            seen: usize,
+        }
+
+        impl Observer for CancelAfter {
+            fn advance(&mut self, len: usize) {
+                self.seen += len;
+                self.left = self.left.saturating_sub(len);
+            }
+
+            fn cancelled(&mut self) -> bool {
+                self.left == 0
+            }
+        }
+
+        let text = include_bytes!("../nyi.txt").repeat(50);
+        let buffer_len = 1000;
+        let cancel = || CancelAfter {
+            left: 3 * buffer_len,
+            seen: 0,
+        };
+
+        let mut observer = cancel();
+        let mut out = Vec::new();
+        let err = encode_stream_with(
+            Cursor::new(&text),
+            &mut out,
+            Checksum::Crc32,
+            buffer_len,
+            &mut observer,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.into_inner().unwrap().downcast_ref(),
+            Some(&EncodeError::Cancelled)
+        );
+        assert_eq!(observer.seen, 3 * buffer_len);
+
+        let encoded = format::encode(&text, Checksum::Crc32).unwrap();
+        let mut observer = cancel();
+        let mut out = Vec::new();
+        let options = DecodeOptions::new();
+        assert_eq!(
+            decode_stream_with(&encoded[..], &mut out, &options, buffer_len, &mut observer),
+            Err(DecodeError::Cancelled)
+        );
+        assert_eq!(out.len(), 3 * buffer_len);
+
+        // the input can still be decoded in full afterwards
+        assert_eq!(decoded(&encoded, &options).unwrap(), text);
+    }
+
+    #[test]
+    fn notices_the_input_changing_between_passes() {
+        /// Has its first byte changed once it is rewound.
+        struct Changing(Cursor<Vec<u8>>);
+
+        impl Read for Changing {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+
+        impl Seek for Changing {
+            fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+                if pos == SeekFrom::Start(0) {
+                    self.0.get_mut()[0] = 0xff;
+                }
+                self.0.seek(pos)
+            }
+        }
+
+        let input = Changing(Cursor::new(include_bytes!("../nyi.txt").to_vec()));
+        let err = encode_stream(input, io::sink(), Checksum::Crc32).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,71 @@
+//! Every symbol's count and code as CSV, for `--csv`.
+
+use std::fmt::Write;
+
+use huffman_encoding::huffman::{self, HuffmanNode};
+
+/// One `symbol,char,count,code_length,code` row per byte value that occurs
+/// in `input`, by byte value, after a header row. Returns `None` if `input`
+/// is empty.
+pub fn symbol_csv(input: &[u8]) -> Option<String> {
+    let counts = huffman::count_frequencies(input.iter().copied());
+    let codes = HuffmanNode::build_tree_from_counts(&counts)?.code_map();
+
+    let mut out = String::from("symbol,char,count,code_length,code\n");
+    for symbol in 0..=u8::MAX {
+        let count = counts[symbol as usize];
+        if count == 0 {
+            continue;
+        }
+        let code = &codes[&symbol];
+        writeln!(
+            out,
+            "{symbol},{},{count},{},{code}",
+            char_field(symbol),
+            code.len()
+        )
+        .unwrap();
+    }
+
+    Some(out)
+}
+
+/// The character column: the byte itself if it is printable ASCII, and its
+/// Rust escape otherwise, quoted if it has a comma, quote or space in it.
+fn char_field(symbol: u8) -> String {
+    let shown = if symbol.is_ascii_graphic() || symbol == b' ' {
+        (symbol as char).to_string()
+    } else {
+        (symbol as char).escape_debug().to_string()
+    };
+
+    if shown.contains([',', '"', ' ']) {
+        format!("\"{}\"", shown.replace('"', "\"\""))
+    } else {
+        shown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_every_symbol_by_byte_value() {
+        let csv = symbol_csv(b"aaaaaaaabbbbcc\"\n").unwrap();
+        let rows: Vec<_> = csv.lines().collect();
+
+        assert_eq!(
+            rows,
+            [
+                "symbol,char,count,code_length,code",
+                "10,\\n,1,4,1110",
+                "34,\"\"\"\",1,4,1111",
+                "97,a,8,1,0",
+                "98,b,4,2,10",
+                "99,c,2,3,110",
+            ]
+        );
+        assert_eq!(symbol_csv(b""), None);
+    }
+}
@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+
+use crate::huffman::HuffmanNode;
+
+/// An empty directory for the test `name` to write files in, emptied first
+/// if an earlier run left it behind.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("huffman-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Builds a tree from explicit `symbol -> code` mappings, e.g.
+/// `tree_from_codes(&[(b'a', "0"), (b'b', "10"), (b'c', "11")])`.
+///
+/// The codes must form a complete prefix code, otherwise this panics.
+pub fn tree_from_codes(codes: &[(u8, &str)]) -> HuffmanNode {
+    let codes: Vec<_> = codes
+        .iter()
+        .map(|&(val, code)| (val, code.as_bytes()))
+        .collect();
+
+    build(&codes)
+}
+
+/// A nonzero start for the xorshift `state` of [`flip_random_bits`].
+pub const FLIP_SEED: u32 = 0x2545_f491;
+
+/// Flips `n` bits of `data`, picked with the xorshift generator `state`,
+/// which is advanced so that the next call picks others.
+pub fn flip_random_bits(state: &mut u32, data: &mut [u8], n: usize) {
+    for _ in 0..n {
+        *state ^= *state << 13;
+        *state ^= *state >> 17;
+        *state ^= *state << 5;
+        let bit = *state as usize % (data.len() * 8);
+        data[bit / 8] ^= 0x80 >> (bit % 8);
+    }
+}
+
+fn build(codes: &[(u8, &[u8])]) -> HuffmanNode {
+    if let [(val, [])] = codes {
+        return HuffmanNode::Leaf {
+            val: *val,
+            count: 0,
+        };
+    }
+
+    assert!(
+        !codes.is_empty() && codes.iter().all(|(_, code)| !code.is_empty()),
+        "codes do not form a complete prefix code"
+    );
+
+    let (left, right): (Vec<_>, Vec<_>) = codes
+        .iter()
+        .map(|&(val, code)| (code[0], (val, &code[1..])))
+        .partition(|&(bit, _)| bit == b'0');
+    let left: Vec<_> = left.into_iter().map(|(_, code)| code).collect();
+    let right: Vec<_> = right.into_iter().map(|(_, code)| code).collect();
+
+    HuffmanNode::Node {
+        left: Box::new(build(&left)),
+        right: Box::new(build(&right)),
+    }
+}
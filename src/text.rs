@@ -0,0 +1,61 @@
+//! Optional changes to text input made before encoding, recorded in the
+//! header's [`Flags`](crate::format::Flags) so a decoder can undo them.
+
+/// The UTF-8 byte order mark some editors put at the start of text files.
+pub const BOM: &[u8; 3] = b"\xef\xbb\xbf";
+
+/// `data` without its leading [`BOM`], or `None` if it doesn't start with
+/// one.
+pub fn strip_bom(data: &[u8]) -> Option<&[u8]> {
+    data.strip_prefix(BOM)
+}
+
+/// Turns every CRLF in `data` into LF. A lone CR is left alone.
+pub fn normalize_newlines(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if byte == b'\r' && bytes.peek() == Some(&b'\n') {
+            continue;
+        }
+        out.push(byte);
+    }
+
+    out
+}
+
+/// Turns every LF in `data` into CRLF. This only gives back the original
+/// of [`normalize_newlines`] if all of its line endings were CRLF.
+pub fn restore_newlines(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 16);
+    for &byte in data {
+        if byte == b'\n' {
+            out.push(b'\r');
+        }
+        out.push(byte);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newlines_round_trip_through_lf() {
+        let crlf = b"one\r\ntwo\r\n\r\nthree";
+
+        assert_eq!(normalize_newlines(crlf), b"one\ntwo\n\nthree");
+        assert_eq!(restore_newlines(&normalize_newlines(crlf)), crlf);
+        // a lone CR isn't a line ending
+        assert_eq!(normalize_newlines(b"a\rb\r\r\n"), b"a\rb\r\n");
+    }
+
+    #[test]
+    fn strips_only_a_leading_bom() {
+        assert_eq!(strip_bom(b"\xef\xbb\xbfhi"), Some(&b"hi"[..]));
+        assert_eq!(strip_bom(b"hi\xef\xbb\xbf"), None);
+        assert_eq!(strip_bom(b"\xef\xbb"), None);
+    }
+}
@@ -0,0 +1,144 @@
+//! An on-disk cache of trees, keyed by the frequency table they were built
+//! from, for batch compression of many similarly distributed files.
+//!
+//! The cache is purely an optimization. Each entry stores the complete
+//! frequency table next to the code lengths of its tree and is only used if
+//! the table matches exactly, so output is identical with or without it, and
+//! any error reading or writing the cache falls back to building the tree.
+
+use std::path::PathBuf;
+
+use huffman_encoding::huffman::HuffmanNode;
+
+/// Names the cache directory; the cache is disabled when it is unset.
+pub const CACHE_DIR_VAR: &str = "HUFFMAN_TREE_CACHE";
+
+pub struct TreeCache {
+    dir: PathBuf,
+}
+
+impl TreeCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os(CACHE_DIR_VAR)
+            .filter(|dir| !dir.is_empty())
+            .map(Self::new)
+    }
+
+    /// Returns the cached tree for `counts`, building and storing it if there
+    /// is none yet or `rebuild` is set. The flag is `true` on a cache hit.
+    pub fn get_or_build(
+        &self,
+        counts: &[usize; 256],
+        rebuild: bool,
+    ) -> Option<(HuffmanNode, bool)> {
+        let path = self.dir.join(format!("{:016x}", hash_counts(counts)));
+
+        if !rebuild
+            && let Ok(entry) = std::fs::read(&path)
+            && let Some(tree) = parse_entry(&entry, counts)
+        {
+            return Some((tree, true));
+        }
+
+        let tree = HuffmanNode::build_tree_from_counts(counts)?;
+
+        // failing to store the entry only costs the next run a rebuild
+        let _ = std::fs::create_dir_all(&self.dir)
+            .and_then(|()| std::fs::write(&path, make_entry(counts, &tree)));
+
+        Some((tree, false))
+    }
+}
+
+/// FNV-1a over the little-endian counts, which is stable across runs and
+/// platforms, unlike `std`'s default hasher.
+fn hash_counts(counts: &[usize; 256]) -> u64 {
+    counts
+        .iter()
+        .flat_map(|&count| (count as u64).to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// An entry is the 256 counts as little-endian `u64`s, then one code length
+/// byte per symbol.
+fn make_entry(counts: &[usize; 256], tree: &HuffmanNode) -> Vec<u8> {
+    let mut entry: Vec<u8> = counts
+        .iter()
+        .flat_map(|&count| (count as u64).to_le_bytes())
+        .collect();
+    entry.extend(tree.code_lengths().map(|len| len as u8));
+    entry
+}
+
+fn parse_entry(entry: &[u8], counts: &[usize; 256]) -> Option<HuffmanNode> {
+    let (stored_counts, lengths) = entry.split_at_checked(256 * size_of::<u64>())?;
+    let counts_match = stored_counts
+        .chunks_exact(size_of::<u64>())
+        .zip(counts)
+        .all(|(stored, &count)| stored == (count as u64).to_le_bytes());
+    if !counts_match || lengths.len() != 256 {
+        return None;
+    }
+
+    let lengths: [usize; 256] = std::array::from_fn(|i| lengths[i] as usize);
+    HuffmanNode::from_code_lengths(&lengths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::scratch_dir;
+    use huffman_encoding::huffman::count_frequencies;
+
+    fn temp_cache(name: &str) -> TreeCache {
+        TreeCache::new(scratch_dir(name))
+    }
+
+    #[test]
+    fn same_distribution_hits_the_cache() {
+        let cache = temp_cache("cache-hit");
+        let first = b"mississippi river";
+        let second = b"river mississippi";
+        let counts = count_frequencies(first.iter().copied());
+        assert_eq!(counts, count_frequencies(second.iter().copied()));
+
+        let (built, hit) = cache.get_or_build(&counts, false).unwrap();
+        assert!(!hit);
+        let (cached, hit) = cache.get_or_build(&counts, false).unwrap();
+        assert!(hit);
+
+        assert_eq!(cached, built);
+        assert_eq!(cached.serialize(second), built.serialize(second));
+
+        let (_, hit) = cache.get_or_build(&counts, true).unwrap();
+        assert!(!hit);
+
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn ignores_entries_for_other_counts() {
+        let cache = temp_cache("cache-mismatch");
+        let counts = count_frequencies(b"aab".iter().copied());
+        let other = count_frequencies(b"abb".iter().copied());
+        let (tree, _) = cache.get_or_build(&other, false).unwrap();
+
+        // pretend `other` collided with `counts`
+        std::fs::write(
+            cache.dir.join(format!("{:016x}", hash_counts(&counts))),
+            make_entry(&other, &tree),
+        )
+        .unwrap();
+
+        let (_, hit) = cache.get_or_build(&counts, false).unwrap();
+        assert!(!hit);
+
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+//! How the codes of two inputs' trees differ, for `--compare-trees`.
+
+use std::fmt::Write;
+
+use huffman_encoding::huffman::HuffmanNode;
+
+/// Summarizes which symbols the two trees share and how far their code
+/// lengths are apart. The delta is `second` minus `first`, averaged over the
+/// shared symbols, so a positive one means `second` spends more bits on them.
+pub fn compare_trees(first: &HuffmanNode, second: &HuffmanNode) -> String {
+    let first = first.code_lengths();
+    let second = second.code_lengths();
+
+    let mut shared = 0;
+    let mut only_first = 0;
+    let mut only_second = 0;
+    let mut differing = 0;
+    let mut delta = 0;
+    for (&a, &b) in first.iter().zip(&second) {
+        match (a, b) {
+            (0, 0) => {}
+            (_, 0) => only_first += 1,
+            (0, _) => only_second += 1,
+            _ => {
+                shared += 1;
+                if a != b {
+                    differing += 1;
+                }
+                delta += b as isize - a as isize;
+            }
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "symbols: {shared} in both, {only_first} only in the first, {only_second} only in the second"
+    )
+    .unwrap();
+    let average = delta as f64 / shared.max(1) as f64;
+    writeln!(
+        out,
+        "code lengths: {differing} of {shared} shared symbols differ, average delta {average:+.2} bits"
+    )
+    .unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_shared_symbols_and_their_length_changes() {
+        // a: 1, b: 2, c: 2 against 2 bits for each of a, b, c and d, so only
+        // a's code changed, by one bit
+        let first = HuffmanNode::build_tree(b"aaaabbc");
+        let second = HuffmanNode::build_tree(b"aaaabbbbccdd");
+
+        assert_eq!(
+            compare_trees(&first, &second),
+            "symbols: 3 in both, 0 only in the first, 1 only in the second\n\
+             code lengths: 1 of 3 shared symbols differ, average delta +0.33 bits\n"
+        );
+    }
+}
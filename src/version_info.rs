@@ -0,0 +1,69 @@
+//! What this build can read and write, for `--version-info`.
+
+use std::fmt::Write;
+
+use huffman_encoding::checksum::Checksum;
+use huffman_encoding::format::{self, Mode};
+
+/// A summary of the crate version, file format and the modes and
+/// checksums compiled in, for comparing two builds that disagree about a
+/// file.
+pub fn version_info() -> String {
+    let mut out = String::new();
+    let names = |names: Vec<&str>| names.join(", ");
+
+    writeln!(out, "huffman_encoding {}", env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(
+        out,
+        "format version: writes {}, reads {} to {}",
+        format::FORMAT_VERSION,
+        format::SUPPORTED_VERSIONS.start(),
+        format::SUPPORTED_VERSIONS.end()
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "modes: {}",
+        names(Mode::ALL.iter().map(|mode| mode.name()).collect())
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "checksums: {} (default {})",
+        names(
+            Checksum::ALL
+                .iter()
+                .map(|checksum| checksum.name())
+                .collect()
+        ),
+        Checksum::default().name()
+    )
+    .unwrap();
+    writeln!(out, "bit order: most significant bit of each byte first").unwrap();
+    let features = if cfg!(feature = "xxhash") {
+        "xxhash"
+    } else {
+        "none"
+    };
+    writeln!(out, "features: {features}").unwrap();
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_every_mode_and_checksum() {
+        let info = version_info();
+
+        assert!(info.contains("format version: writes 4, reads 1 to 4\n"));
+        for mode in Mode::ALL {
+            assert!(info.contains(mode.name()), "{info}");
+        }
+        for checksum in Checksum::ALL {
+            assert!(info.contains(checksum.name()), "{info}");
+        }
+    }
+}
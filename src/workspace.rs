@@ -0,0 +1,133 @@
+//! Buffers kept between calls, for compressing many small inputs without
+//! allocating most of the intermediate state each time.
+
+use crate::checksum::Checksum;
+use crate::format::{self, Mode};
+use crate::huffman::HuffmanNode;
+
+/// The frequency counts, code table and output buffer of [`format::encode`],
+/// kept around so that [`Self::encode_reuse`] can fill them in place. Only
+/// the tree is still built afresh for every input.
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    checksum: Checksum,
+    counts: [usize; 256],
+    /// Each byte's code in its low bits, and how many bits that is.
+    codes: [(u64, u32); 256],
+    output: Vec<u8>,
+}
+
+impl Default for Workspace {
+    fn default() -> Self {
+        Self::new(Checksum::default())
+    }
+}
+
+impl Workspace {
+    pub fn new(checksum: Checksum) -> Self {
+        Self {
+            checksum,
+            counts: [0; 256],
+            codes: [(0, 0); 256],
+            output: Vec::new(),
+        }
+    }
+
+    /// Compresses `data` to the same bytes as [`format::encode`] would. The
+    /// returned slice borrows the workspace's output buffer, so it is only
+    /// valid until the next call; copy it out to keep it.
+    pub fn encode_reuse(&mut self, data: &[u8]) -> &[u8] {
+        self.counts.fill(0);
+        for &byte in data {
+            self.counts[byte as usize] += 1;
+        }
+
+        self.output.clear();
+        if format::is_incompressible(&self.counts) {
+            format::write_header(data, self.checksum, Mode::Stored, &mut self.output);
+            self.output.extend((data.len() as u64).to_le_bytes());
+            self.output.extend(data);
+            return &self.output;
+        }
+
+        let tree = HuffmanNode::build_tree_from_counts(&self.counts)
+            .expect("compressible data isn't empty");
+        format::write_header(data, self.checksum, Mode::Huffman, &mut self.output);
+        self.output.extend((data.len() as u64).to_le_bytes());
+        if !assign_codes(&tree, 0, 0, &mut self.codes, &mut self.output) {
+            // a code that long takes terabytes of input
+            let encoded = format::encode(data, self.checksum).expect("data isn't empty");
+            self.output.clear();
+            self.output.extend(encoded);
+            return &self.output;
+        }
+        self.output.push(0);
+
+        let mut buffer = 0_u128;
+        let mut buffered = 0;
+        for &byte in data {
+            let (code, len) = self.codes[byte as usize];
+            buffer = buffer << len | u128::from(code);
+            buffered += len;
+            while buffered >= 8 {
+                buffered -= 8;
+                self.output.push((buffer >> buffered) as u8);
+            }
+        }
+        if buffered > 0 {
+            self.output.push((buffer << (8 - buffered)) as u8);
+        }
+
+        &self.output
+    }
+}
+
+/// Walks `node`, whose path is the `len` bits of `code`, storing each leaf's
+/// code in `codes` and its `(code length, symbol)` pair in `out`, in the
+/// order of [`HuffmanNode::serialize_tree_bytes`]. Returns `false` if a code
+/// doesn't fit in 64 bits.
+fn assign_codes(
+    node: &HuffmanNode,
+    code: u64,
+    len: u32,
+    codes: &mut [(u64, u32); 256],
+    out: &mut Vec<u8>,
+) -> bool {
+    match node {
+        HuffmanNode::Leaf { val, .. } => {
+            codes[*val as usize] = (code, len);
+            out.extend([len as u8, *val]);
+            true
+        }
+        HuffmanNode::Node { .. } if len == u64::BITS => false,
+        HuffmanNode::Node { left, right } => {
+            assign_codes(left, code << 1, len + 1, codes, out)
+                && assign_codes(right, code << 1 | 1, len + 1, codes, out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_what_format_encode_writes() {
+        let mut workspace = Workspace::new(Checksum::Crc32);
+        let inputs: [&[u8]; 6] = [
+            include_bytes!("../nyi.txt"),
+            b"aaaaaaaabbbbcc\n",
+            b"",
+            b"zzzzzzzzzzzzzzzz",
+            // incompressible, so stored
+            b"abcdefgh",
+            b"a short one after longer ones",
+        ];
+
+        for input in inputs {
+            let encoded = workspace.encode_reuse(input);
+            assert_eq!(encoded, format::encode(input, Checksum::Crc32).unwrap());
+            assert_eq!(format::decode(encoded).unwrap(), input);
+        }
+    }
+}
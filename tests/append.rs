@@ -0,0 +1,59 @@
+//! `--append` compresses a file onto the end of an existing archive.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn appended_members_decode_in_order() {
+    let dir = scratch_dir("append");
+    std::fs::write(dir.join("first"), "the first file\n").unwrap();
+    std::fs::write(dir.join("second"), "then a second one\n").unwrap();
+    std::fs::write(dir.join("third"), "and a third\n").unwrap();
+    assert!(huffman(&dir, &["first", "-o", "all.huff"]).status.success());
+
+    for input in ["second", "third"] {
+        let appended = huffman(&dir, &["--append", input, "all.huff"]);
+        assert!(appended.status.success(), "{appended:?}");
+    }
+
+    let decoded = huffman(&dir, &["-d", "all.huff"]);
+    assert!(decoded.status.success(), "{decoded:?}");
+    assert_eq!(
+        String::from_utf8(decoded.stdout).unwrap(),
+        "the first file\nthen a second one\nand a third\n"
+    );
+}
+
+#[test]
+fn a_corrupt_archive_is_left_alone() {
+    let dir = scratch_dir("append_corrupt");
+    std::fs::write(dir.join("input"), "some text\n").unwrap();
+    std::fs::write(dir.join("archive.huff"), "not compressed").unwrap();
+
+    let appended = huffman(&dir, &["--append", "input", "archive.huff"]);
+    assert_eq!(appended.status.code(), Some(1));
+    let stderr = String::from_utf8(appended.stderr).unwrap();
+    assert!(
+        stderr.starts_with("error: `archive.huff` is not a valid archive: "),
+        "{stderr}"
+    );
+    assert_eq!(
+        std::fs::read(dir.join("archive.huff")).unwrap(),
+        b"not compressed"
+    );
+}
+
+#[test]
+fn a_missing_archive_is_an_error() {
+    let dir = scratch_dir("append_missing");
+    std::fs::write(dir.join("input"), "some text\n").unwrap();
+
+    let appended = huffman(&dir, &["--append", "input", "missing.huff"]);
+    assert_eq!(appended.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(appended.stderr).unwrap(),
+        "error: failed to read missing.huff: entity not found\n"
+    );
+    assert!(!dir.join("missing.huff").exists());
+}
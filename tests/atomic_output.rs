@@ -0,0 +1,50 @@
+//! Output files are only replaced once the new output is complete.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn failed_decode_leaves_the_target_alone() {
+    let dir = scratch_dir("atomic_output");
+    std::fs::write(dir.join("input.txt"), "to be damaged ".repeat(10)).unwrap();
+    assert!(
+        huffman(&dir, &["input.txt", "-o", "input.huff"])
+            .status
+            .success()
+    );
+    let mut damaged = std::fs::read(dir.join("input.huff")).unwrap();
+    let last = damaged.len() - 1;
+    damaged[last] ^= 0xff;
+    std::fs::write(dir.join("input.huff"), damaged).unwrap();
+    std::fs::write(dir.join("out.txt"), "an earlier result").unwrap();
+
+    let output = huffman(&dir, &["-d", "input.huff", "-o", "out.txt"]);
+    assert!(!output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(dir.join("out.txt")).unwrap(),
+        "an earlier result"
+    );
+
+    let mut names: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["input.huff", "input.txt", "out.txt"]);
+}
+
+#[test]
+fn a_failed_write_names_the_file() {
+    let dir = scratch_dir("failed_write");
+    std::fs::write(dir.join("input.txt"), "nowhere to go ".repeat(10)).unwrap();
+
+    // a regular file can't have anything inside it
+    let output = huffman(&dir, &["input.txt", "-p", "-o", "input.txt/out.huff"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("error: failed to write input.txt"),
+        "{stderr}"
+    );
+}
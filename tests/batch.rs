@@ -0,0 +1,61 @@
+//! Compressing several inputs at once, each to its own `.huff` file.
+
+mod common;
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use common::scratch_dir;
+
+fn huffman(args: &[&PathBuf], flags: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_huffman_encoding"))
+        .args(flags)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn compresses_each_input_concurrently() {
+    let dir = scratch_dir("batch");
+    let inputs: Vec<_> = (0..8)
+        .map(|i| {
+            let path = dir.join(format!("input{i}.txt"));
+            std::fs::write(
+                &path,
+                format!("file {i} says {}\n", "hello ".repeat(i * 10)),
+            )
+            .unwrap();
+            path
+        })
+        .collect();
+
+    let output = huffman(&inputs.iter().collect::<Vec<_>>(), &["--jobs", "3"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    for input in &inputs {
+        let compressed = PathBuf::from(format!("{}.huff", input.display()));
+        let decoded = huffman(&[&compressed], &["-d"]);
+        assert_eq!(decoded.stdout, std::fs::read(input).unwrap());
+    }
+}
+
+#[test]
+fn reports_failures_per_file() {
+    let dir = scratch_dir("batch_failure");
+    let good = dir.join("good.txt");
+    let missing = dir.join("missing.txt");
+    std::fs::write(&good, "still compressed\n").unwrap();
+
+    let output = huffman(&[&missing, &good], &["-j", "2"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    assert!(stderr.contains("missing.txt"), "{stderr}");
+    assert!(!stderr.contains("good.txt"), "{stderr}");
+    assert!(dir.join("good.txt.huff").exists());
+}
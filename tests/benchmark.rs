@@ -0,0 +1,21 @@
+//! Timing a round trip in memory without writing anything.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn reports_throughput_and_writes_nothing() {
+    let dir = scratch_dir("benchmark");
+    std::fs::write(dir.join("input.txt"), "timed over and over ".repeat(50)).unwrap();
+
+    let output = huffman(&dir, &["--benchmark", "input.txt"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert!(lines[0].starts_with("10 rounds of 1000 bytes"), "{stdout}");
+    assert!(lines[1].starts_with("encode: ") && lines[1].ends_with(" MB/s"));
+    assert!(lines[2].starts_with("decode: ") && lines[2].ends_with(" MB/s"));
+
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+}
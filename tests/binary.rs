@@ -0,0 +1,32 @@
+//! Any bytes compress, not just UTF-8 text.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn every_byte_value_round_trips() {
+    let dir = scratch_dir("binary");
+    // every byte once, which is stored as it is, and then with zeros and
+    // invalid UTF-8 common enough to be Huffman coded
+    let uniform: Vec<u8> = (0..=u8::MAX).collect();
+    let skewed: Vec<u8> = (0..=u8::MAX)
+        .flat_map(|byte| [byte, 0, 0, 0xff, 0xfe, 0])
+        .collect();
+
+    for (name, data) in [("uniform", uniform), ("skewed", skewed)] {
+        std::fs::write(dir.join(name), &data).unwrap();
+        let huff = format!("{name}.huff");
+        let out = format!("{name}.out");
+
+        let encoded = huffman(&dir, &[name, "-o", &huff]);
+        assert!(encoded.status.success(), "{encoded:?}");
+        let decoded = huffman(&dir, &["-d", &huff, "-o", &out]);
+        assert!(decoded.status.success(), "{decoded:?}");
+        assert_eq!(std::fs::read(dir.join(&out)).unwrap(), data, "{name}");
+    }
+    assert!(
+        std::fs::metadata(dir.join("skewed.huff")).unwrap().len()
+            < std::fs::metadata(dir.join("skewed")).unwrap().len()
+    );
+}
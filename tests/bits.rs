@@ -0,0 +1,15 @@
+//! `--bits` spells out the tree and codes as 0s and 1s.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn empty_input_has_an_empty_tree_and_payload() {
+    let dir = scratch_dir("bits_empty");
+    std::fs::write(dir.join("empty"), b"").unwrap();
+
+    let output = huffman(&dir, &["--bits", "empty"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, b"tree:    \npayload: \n");
+}
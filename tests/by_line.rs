@@ -0,0 +1,31 @@
+//! Compressing each line on its own and decoding just one of them.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn extracts_the_third_line() {
+    let dir = scratch_dir("by_line");
+    let text = "alpha beta gamma\ndelta epsilon\nzeta eta theta\niota kappa\n";
+    std::fs::write(dir.join("greek.txt"), text).unwrap();
+
+    let output = huffman(&dir, &["--by-line", "greek.txt", "-o", "greek.huff"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = huffman(&dir, &["-d", "--line", "3", "greek.huff"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"zeta eta theta\n");
+
+    let output = huffman(&dir, &["-d", "greek.huff"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, text.as_bytes());
+
+    let output = huffman(&dir, &["-d", "--line", "5", "greek.huff"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("fewer than 5 lines"));
+}
@@ -0,0 +1,49 @@
+//! Damaged or foreign files are reported as errors, never as panics.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn truncated_and_garbage_files_fail_cleanly() {
+    let dir = scratch_dir("corrupt_input");
+    std::fs::write(dir.join("text"), "the quick brown fox\n".repeat(20)).unwrap();
+    assert!(huffman(&dir, &["text", "-o", "text.huff"]).status.success());
+    let compressed = std::fs::read(dir.join("text.huff")).unwrap();
+
+    let cases = [
+        (
+            "mid-header",
+            &compressed[..7],
+            "input ends in the middle of the header",
+        ),
+        (
+            "mid-tree",
+            &compressed[..24],
+            "input ends in the middle of the header",
+        ),
+        (
+            "mid-codes",
+            &compressed[..compressed.len() - 1],
+            "input ends before all symbols were decoded",
+        ),
+        (
+            "garbage",
+            b"not compressed at all",
+            "input is not in a format this tool wrote",
+        ),
+    ];
+    for (name, data, message) in cases {
+        std::fs::write(dir.join(name), data).unwrap();
+        // decoding as it reads, and all at once for a hex dump
+        for args in [&["-d", name][..], &["-d", "--hex", name]] {
+            let output = huffman(&dir, args);
+            assert_eq!(output.status.code(), Some(1), "{name}: {output:?}");
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap(),
+                format!("error: {message}\n"),
+                "{name} with {args:?}"
+            );
+        }
+    }
+}
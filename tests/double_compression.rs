@@ -0,0 +1,39 @@
+//! Refusing to compress a file that is already compressed.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn refuses_compressed_input_unless_forced() {
+    let dir = scratch_dir("double_compression");
+    std::fs::write(dir.join("plain.txt"), "plain text, compressed once\n").unwrap();
+    // stored without a checksum, so the compressed file is still UTF-8
+    let args = [
+        "--fixed",
+        "--checksum",
+        "none",
+        "plain.txt",
+        "-o",
+        "once.huff",
+    ];
+    assert!(huffman(&dir, &args).status.success());
+
+    let output = huffman(&dir, &["once.huff", "-o", "twice.huff"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already compressed"));
+
+    // each input of a batch is checked too
+    let output = huffman(&dir, &["plain.txt", "once.huff"]);
+    assert!(!output.status.success());
+    assert!(!dir.join("once.huff.huff").exists());
+
+    let output = huffman(&dir, &["--force", "once.huff", "-o", "twice.huff"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = huffman(&dir, &["-d", "twice.huff"]);
+    assert_eq!(output.stdout, std::fs::read(dir.join("once.huff")).unwrap());
+}
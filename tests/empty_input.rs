@@ -0,0 +1,40 @@
+//! An empty file compresses to a file that decompresses to nothing, with
+//! every coding mode. `--bits` of one is in bits.rs.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn every_mode_round_trips_an_empty_file() {
+    let dir = scratch_dir("empty_input");
+    std::fs::write(dir.join("empty"), b"").unwrap();
+
+    let modes: [&[&str]; 7] = [
+        &[],
+        &["--fixed"],
+        &["--nibble"],
+        &["--length-table"],
+        &["--by-line"],
+        &["--order1"],
+        &["--escape-below", "2"],
+    ];
+    for mode in modes {
+        let encoded = huffman(&dir, &[mode, &["-q", "empty", "-o", "empty.huff"]].concat());
+        assert!(encoded.status.success(), "{mode:?}: {encoded:?}");
+        let compressed = std::fs::read(dir.join("empty.huff")).unwrap();
+        assert_eq!(
+            huffman_encoding::decompress(&compressed).unwrap(),
+            b"",
+            "{mode:?}"
+        );
+
+        let decoded = huffman(&dir, &["-d", "empty.huff", "-o", "empty.out"]);
+        assert!(decoded.status.success(), "{mode:?}: {decoded:?}");
+        assert_eq!(
+            std::fs::read(dir.join("empty.out")).unwrap(),
+            b"",
+            "{mode:?}"
+        );
+    }
+}
@@ -0,0 +1,76 @@
+//! Golden tests pinning the compressed format byte for byte.
+//!
+//! After an intentional format change, regenerate the fixtures with
+//! `REGENERATE_GOLDEN=1 cargo test --test golden` and commit the result.
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn huffman<I: AsRef<OsStr>>(args: &[I]) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_huffman_encoding"))
+        .args(args)
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+fn check_golden(name: &str) {
+    let input = fixture(name);
+    let golden = fixture(&format!("{name}.huff"));
+
+    let encoded = huffman(&[&input]);
+    if std::env::var_os("REGENERATE_GOLDEN").is_some() {
+        std::fs::write(&golden, &encoded).unwrap();
+    }
+
+    let expected = std::fs::read(&golden).unwrap();
+    assert!(
+        encoded == expected,
+        "`{name}` no longer compresses to its checked-in fixture"
+    );
+
+    let decoded = huffman(&[OsStr::new("-d"), golden.as_os_str()]);
+    assert_eq!(decoded, std::fs::read(&input).unwrap());
+}
+
+#[test]
+fn text() {
+    check_golden("text.txt");
+}
+
+#[test]
+fn prose() {
+    check_golden("prose.txt");
+}
+
+#[test]
+fn binary() {
+    check_golden("binary.bin");
+}
+
+#[test]
+fn single_symbol() {
+    check_golden("single_symbol.txt");
+}
+
+#[test]
+fn reads_the_previous_format_version() {
+    // `text.txt` as version 3 wrote it, before the length of the fields
+    let old = fixture("text.txt.v3.huff");
+    let decoded = huffman(&[OsStr::new("-d"), old.as_os_str()]);
+
+    assert_eq!(decoded, std::fs::read(fixture("text.txt")).unwrap());
+}
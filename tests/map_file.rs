@@ -0,0 +1,44 @@
+//! Mapping each member of an archive to where it decodes.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn maps_both_members_of_an_archive() {
+    let dir = scratch_dir("map_file");
+    let first = "the first member, the first member, the first member\n";
+    let second = "and then a second one, and then a second one\n";
+    std::fs::write(dir.join("first.txt"), first).unwrap();
+    std::fs::write(dir.join("second.txt"), second).unwrap();
+
+    assert!(
+        huffman(&dir, &["first.txt", "-o", "archive.huff"])
+            .status
+            .success()
+    );
+    let first_len = std::fs::metadata(dir.join("archive.huff")).unwrap().len();
+    assert!(
+        huffman(&dir, &["--append", "second.txt", "archive.huff"])
+            .status
+            .success()
+    );
+    let archive_len = std::fs::metadata(dir.join("archive.huff")).unwrap().len();
+
+    let output = huffman(&dir, &["-d", "archive.huff", "--map-file", "map.txt"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, format!("{first}{second}").as_bytes());
+
+    let (a, b) = (first.len(), first.len() + second.len());
+    assert_eq!(
+        std::fs::read_to_string(dir.join("map.txt")).unwrap(),
+        format!(
+            "member 1: compressed 0..{first_len}, decoded 0..{a}\n\
+             member 2: compressed {first_len}..{archive_len}, decoded {a}..{b}\n"
+        )
+    );
+}
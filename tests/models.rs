@@ -0,0 +1,18 @@
+//! Trees built from a model instead of the input.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn a_missing_corpus_is_an_error_naming_it() {
+    let dir = scratch_dir("models_missing_corpus");
+    std::fs::write(dir.join("text"), "some text").unwrap();
+
+    let output = huffman(&dir, &["--model-from", "missing", "text"]);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: failed to read missing: entity not found\n"
+    );
+}
@@ -0,0 +1,50 @@
+//! Storing the input's file name and decoding back to it.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn decodes_to_the_stored_name() {
+    let dir = scratch_dir("names");
+    std::fs::create_dir(dir.join("out")).unwrap();
+    std::fs::write(dir.join("notes.txt"), "some notes, some more notes\n").unwrap();
+
+    let output = huffman(&dir, &["--name", "notes.txt", "-o", "out/archive.huff"]);
+    assert!(output.status.success());
+
+    let output = huffman(&dir, &["-d", "out/archive.huff"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        std::fs::read(dir.join("out/notes.txt")).unwrap(),
+        std::fs::read(dir.join("notes.txt")).unwrap()
+    );
+
+    // a second decode would overwrite it
+    let output = huffman(&dir, &["-d", "out/archive.huff"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn rejects_a_stored_name_with_a_parent_component() {
+    let dir = scratch_dir("unsafe_names");
+    std::fs::write(dir.join("..evil"), "would land one directory up\n").unwrap();
+    let output = huffman(&dir, &["--name", "..evil", "-o", "crafted.huff"]);
+    assert!(output.status.success());
+
+    // turn the stored `..evil` into `../vil`
+    let mut crafted = std::fs::read(dir.join("crafted.huff")).unwrap();
+    let at = crafted.windows(6).position(|w| w == b"..evil").unwrap();
+    crafted[at + 2] = b'/';
+    std::fs::write(dir.join("crafted.huff"), crafted).unwrap();
+
+    let output = huffman(&dir, &["-d", "crafted.huff"]);
+    assert!(!output.status.success());
+    assert!(!dir.join("../vil").exists());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not a plain file name"));
+}
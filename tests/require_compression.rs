@@ -0,0 +1,46 @@
+//! Failing instead of writing output that is barely smaller than the input.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn fails_on_random_text() {
+    let dir = scratch_dir("require_compression");
+    // printable ASCII from a linear congruential generator, close to the
+    // 6.6 bits per byte of a uniform choice among 95 characters
+    let mut state = 12345_u32;
+    let random: String = (0..4096)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (b' ' + (state >> 16) as u8 % 95) as char
+        })
+        .collect();
+    std::fs::write(dir.join("random.txt"), random).unwrap();
+    std::fs::write(dir.join("repetitive.txt"), "ab".repeat(2048)).unwrap();
+
+    let args = [
+        "--require-compression",
+        "0.5",
+        "random.txt",
+        "-o",
+        "random.huff",
+    ];
+    let output = huffman(&dir, &args);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("but 50.0% was required"));
+
+    let args = [
+        "--require-compression",
+        "0.5",
+        "repetitive.txt",
+        "-o",
+        "repetitive.huff",
+    ];
+    let output = huffman(&dir, &args);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,46 @@
+//! `--stats` reports on the encoding without changing it.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn stats_go_to_stderr_and_leave_the_output_alone() {
+    let dir = scratch_dir("stats");
+    std::fs::write(dir.join("text"), "aaaaaaaabbbbcc\n".repeat(10)).unwrap();
+    let plain = huffman(&dir, &["text"]);
+    assert!(plain.status.success(), "{plain:?}");
+
+    let to_stdout = huffman(&dir, &["--stats", "text"]);
+    assert!(to_stdout.status.success(), "{to_stdout:?}");
+    assert_eq!(to_stdout.stdout, plain.stdout);
+
+    let to_file = huffman(&dir, &["--stats", "text", "-o", "text.huff"]);
+    assert!(to_file.status.success(), "{to_file:?}");
+    assert!(to_file.stdout.is_empty());
+    assert_eq!(std::fs::read(dir.join("text.huff")).unwrap(), plain.stdout);
+
+    for output in [to_stdout, to_file] {
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with("original size: 150 bytes\n"), "{stderr}");
+        assert!(stderr.contains("distinct symbols: 4\n"), "{stderr}");
+    }
+}
+
+#[test]
+fn stats_only_describe_encoding() {
+    let dir = scratch_dir("stats_decode");
+    let output = huffman(&dir, &["--stats", "-d", "text.huff"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn empty_input_has_no_ratio() {
+    let dir = scratch_dir("stats_empty");
+    std::fs::write(dir.join("empty"), b"").unwrap();
+
+    let output = huffman(&dir, &["--stats", "empty", "-o", "empty.huff"]);
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("\nratio: n/a\n"), "{stderr}");
+}
@@ -0,0 +1,96 @@
+//! Reading stdin and writing stdout, for use in pipelines.
+
+mod common;
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn huffman_with_stdin(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_huffman_encoding"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn binary_data_round_trips_through_a_pipe() {
+    let data: Vec<u8> = (0..=u8::MAX).cycle().take(3000).chain(*b"\0\0\0").collect();
+
+    for encode_args in [&[][..], &["-"], &["-", "-o", "-"]] {
+        let encoded = huffman_with_stdin(encode_args, &data);
+        assert!(encoded.status.success(), "{encoded:?}");
+
+        let decoded = huffman_with_stdin(&["-d", "-"], &encoded.stdout);
+        assert!(decoded.status.success(), "{decoded:?}");
+        assert_eq!(decoded.stdout, data);
+    }
+}
+
+#[test]
+fn empty_stdin_encodes_and_is_an_error_to_decode() {
+    let encoded = huffman_with_stdin(&["-q"], b"");
+    assert!(encoded.status.success());
+    let decoded = huffman_with_stdin(&["-d"], &encoded.stdout);
+    assert!(decoded.status.success());
+    assert!(decoded.stdout.is_empty());
+
+    let output = huffman_with_stdin(&["-d"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: input ends in the middle of the header\n"
+    );
+}
+
+#[test]
+fn a_closed_stdout_is_an_error_not_a_panic() {
+    let dir = common::scratch_dir("stdio_closed");
+    let data: Vec<u8> = (0..4_000_000_u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(dir.join("big"), &data).unwrap();
+
+    // in memory for --fixed, and streamed without it
+    for args in [&["--fixed", "big"][..], &["big"]] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_huffman_encoding"))
+            .current_dir(&dir)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        drop(child.stdout.take());
+        let output = child.wait_with_output().unwrap();
+
+        assert_eq!(output.status.code(), Some(1), "{args:?}: {output:?}");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.ends_with("error: failed to write stdout: broken pipe\n"),
+            "{args:?}: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn interactive_mode_reports_a_closed_stdout() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_huffman_encoding"))
+        .arg("--interactive")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    drop(child.stdout.take());
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"a line to code\n").unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("error: "), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
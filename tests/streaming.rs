@@ -0,0 +1,97 @@
+//! Files are compressed and decompressed a buffer at a time when the
+//! options allow it, which must not change what is written.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn a_file_bigger_than_the_buffers_round_trips() {
+    let dir = scratch_dir("streaming");
+    let data: Vec<u8> = (0..300_000_u32)
+        .map(|i| b"streamed text, "[i as usize % 15] ^ (i % 7 == 0) as u8)
+        .collect();
+    std::fs::write(dir.join("big"), &data).unwrap();
+
+    let encoded = huffman(&dir, &["big", "-o", "big.huff"]);
+    assert!(encoded.status.success(), "{encoded:?}");
+    let compressed = std::fs::read(dir.join("big.huff")).unwrap();
+    assert_eq!(compressed, huffman_encoding::compress(&data));
+
+    let decoded = huffman(&dir, &["-d", "big.huff", "-o", "big.out"]);
+    assert!(decoded.status.success(), "{decoded:?}");
+    assert_eq!(std::fs::read(dir.join("big.out")).unwrap(), data);
+}
+
+#[test]
+fn a_corrupt_file_leaves_no_output() {
+    let dir = scratch_dir("streaming_corrupt");
+    std::fs::write(dir.join("text"), "some text to damage ".repeat(5000)).unwrap();
+    assert!(huffman(&dir, &["text", "-o", "text.huff"]).status.success());
+    let mut compressed = std::fs::read(dir.join("text.huff")).unwrap();
+    compressed.truncate(compressed.len() / 2);
+    std::fs::write(dir.join("text.huff"), compressed).unwrap();
+
+    let decoded = huffman(&dir, &["-d", "text.huff", "-o", "text.out"]);
+    assert_eq!(decoded.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(decoded.stderr).unwrap(),
+        "error: input ends before all symbols were decoded\n"
+    );
+    assert!(!dir.join("text.out").exists());
+}
+
+#[test]
+fn an_empty_file_round_trips() {
+    let dir = scratch_dir("streaming_empty");
+    std::fs::write(dir.join("empty"), b"").unwrap();
+
+    let encoded = huffman(&dir, &["-q", "empty", "-o", "empty.huff"]);
+    assert!(encoded.status.success(), "{encoded:?}");
+    let compressed = std::fs::read(dir.join("empty.huff")).unwrap();
+    // a header and a length of 0, with no tree
+    let (header, stream) = huffman_encoding::format::peek_header(&compressed).unwrap();
+    assert_eq!(header.mode, huffman_encoding::format::Mode::Stored);
+    assert_eq!(stream, 0_u64.to_le_bytes());
+    assert_eq!(huffman_encoding::decompress(&compressed).unwrap(), b"");
+
+    let decoded = huffman(&dir, &["-d", "empty.huff", "-o", "empty.out"]);
+    assert!(decoded.status.success(), "{decoded:?}");
+    assert_eq!(std::fs::read(dir.join("empty.out")).unwrap(), b"");
+}
+
+#[test]
+fn verbose_streaming_times_every_stage() {
+    let dir = scratch_dir("streaming_verbose");
+    std::fs::write(dir.join("text"), "text to time ".repeat(1000)).unwrap();
+
+    let encoded = huffman(&dir, &["-v", "text", "-o", "text.huff"]);
+    assert!(encoded.status.success(), "{encoded:?}");
+    let stderr = String::from_utf8(encoded.stderr).unwrap();
+    let stages: Vec<_> = stderr
+        .lines()
+        .map(|line| line.split_once(": ").unwrap().0)
+        .collect();
+    assert_eq!(stages, ["counting", "building tree", "encoding", "writing"]);
+}
+
+#[test]
+fn the_buffer_size_does_not_change_the_output() {
+    let dir = scratch_dir("streaming_buffer_size");
+    std::fs::write(dir.join("text"), "buffered a bit at a time ".repeat(2000)).unwrap();
+    assert!(huffman(&dir, &["text", "-o", "text.huff"]).status.success());
+    let expected = std::fs::read(dir.join("text.huff")).unwrap();
+
+    for size in ["1", "100", "1000000"] {
+        let encoded = huffman(&dir, &["--buffer-size", size, "text", "-o", "sized.huff"]);
+        assert!(encoded.status.success(), "{size}: {encoded:?}");
+        assert_eq!(std::fs::read(dir.join("sized.huff")).unwrap(), expected);
+
+        let decoded = huffman(&dir, &["--buffer-size", size, "-d", "sized.huff"]);
+        assert!(decoded.status.success(), "{size}: {decoded:?}");
+        assert_eq!(decoded.stdout, std::fs::read(dir.join("text")).unwrap());
+    }
+
+    let zero = huffman(&dir, &["--buffer-size", "0", "text"]);
+    assert_eq!(zero.status.code(), Some(2));
+}
@@ -0,0 +1,51 @@
+//! Storing the input's modification time and restoring it when decoding.
+
+mod common;
+
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::{huffman, scratch_dir};
+
+fn modified(path: &Path) -> SystemTime {
+    std::fs::metadata(path).unwrap().modified().unwrap()
+}
+
+#[test]
+fn restores_the_stored_modification_time() {
+    let dir = scratch_dir("timestamps");
+    let input = dir.join("old.txt");
+    std::fs::write(&input, "written a long time ago\n").unwrap();
+    let then = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&input)
+        .unwrap();
+    file.set_modified(then).unwrap();
+    drop(file);
+
+    let output = huffman(&dir, &["--timestamp", "old.txt", "-o", "old.huff"]);
+    assert!(output.status.success());
+
+    let output = huffman(
+        &dir,
+        &[
+            "-d",
+            "--restore-timestamp",
+            "old.huff",
+            "-o",
+            "restored.txt",
+        ],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(modified(&dir.join("restored.txt")), then);
+
+    // without the flag the output is simply new
+    let output = huffman(&dir, &["-d", "old.huff", "-o", "new.txt"]);
+    assert!(output.status.success());
+    assert_ne!(modified(&dir.join("new.txt")), then);
+}
@@ -0,0 +1,41 @@
+//! Splitting the compressed output into volumes and decoding them back.
+
+mod common;
+
+use common::{huffman, scratch_dir};
+
+#[test]
+fn reassembles_small_volumes() {
+    let dir = scratch_dir("volumes");
+    let text = "volume after volume of the same few words\n".repeat(20);
+    std::fs::write(dir.join("input.txt"), &text).unwrap();
+
+    let output = huffman(&dir, &["--split", "100", "input.txt", "-o", "out.huff"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!dir.join("out.huff").exists());
+
+    let volumes: Vec<_> = (1..)
+        .map(|n| dir.join(format!("out.huff.{n:03}")))
+        .take_while(|path| path.exists())
+        .map(|path| std::fs::read(path).unwrap())
+        .collect();
+    assert!(volumes.len() > 1);
+    assert!(volumes.iter().all(|volume| volume.len() <= 100));
+    assert!(
+        volumes[..volumes.len() - 1]
+            .iter()
+            .all(|volume| volume.len() == 100)
+    );
+
+    let output = huffman(&dir, &["-d", "out.huff.001"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, text.as_bytes());
+}
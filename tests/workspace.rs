@@ -0,0 +1,76 @@
+//! How many allocations [`Workspace`] saves per small message.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use huffman_encoding::checksum::Checksum;
+use huffman_encoding::format;
+use huffman_encoding::workspace::Workspace;
+
+/// Counts every allocation, so a run can be measured by the difference.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[test]
+#[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+fn bench_allocations_per_message() {
+    // small, but long enough to be worth compressing rather than storing
+    let messages: Vec<Vec<u8>> = (0..10_000)
+        .map(|i| {
+            let path = format!("/items/{}", i % 97);
+            format!("{{\"id\":{i},\"event\":\"page_view\",\"path\":\"{path}\",\"referrer\":\"{path}\"}}\n")
+                .repeat(3)
+                .into_bytes()
+        })
+        .collect();
+    assert!(
+        format::peek_header(&format::encode(&messages[0], Checksum::Crc32).unwrap())
+            .is_ok_and(|(header, _)| header.mode == format::Mode::Huffman)
+    );
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = std::time::Instant::now();
+    let mut fresh = 0;
+    for message in &messages {
+        fresh += format::encode(message, Checksum::Crc32).unwrap().len();
+    }
+    let fresh_time = start.elapsed();
+    let fresh_allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    let mut workspace = Workspace::new(Checksum::Crc32);
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = std::time::Instant::now();
+    let mut reused = 0;
+    for message in &messages {
+        reused += workspace.encode_reuse(message).len();
+    }
+    let reuse_time = start.elapsed();
+    let reuse_allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    assert_eq!(fresh, reused);
+
+    let per_message = |allocations: usize| allocations as f64 / messages.len() as f64;
+    println!(
+        "format::encode: {:.1} allocations per message, {fresh_time:?}",
+        per_message(fresh_allocations)
+    );
+    println!(
+        "encode_reuse:   {:.1} allocations per message, {reuse_time:?}",
+        per_message(reuse_allocations)
+    );
+    assert!(reuse_allocations < fresh_allocations);
+}